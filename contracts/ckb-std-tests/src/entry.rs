@@ -114,6 +114,40 @@ fn test_high_level_apis() {
     };
     assert_eq!(tx_hash, tx_hash2);
 
+    let mut hex_buf = [0u8; 64];
+    let hex = encode_hex_into(&tx_hash, &mut hex_buf).unwrap();
+    assert_eq!(hex.as_bytes(), encode_hex(&tx_hash).as_bytes());
+    assert_eq!(alloc::format!("{}", Hex(&tx_hash)), hex);
+    let mut decoded = [0u8; 32];
+    assert_eq!(decode_hex_into(hex.as_bytes(), &mut decoded), Ok(32));
+    assert_eq!(decoded, tx_hash);
+    assert_eq!(
+        decode_hex_into(b"0g", &mut decoded),
+        Err(SysError::Encoding)
+    );
+    debug!("tx hash {}", Hex(&tx_hash));
+
+    let args = load_script_args_into::<256>().unwrap();
+    assert_eq!(&args[..], &script.args().raw_data()[..]);
+    let data = load_cell_data(0, Source::Output).unwrap();
+    let data2 = load_cell_data_into::<1024>(0, Source::Output).unwrap();
+    assert_eq!(&data2[..], &data[..]);
+    assert_eq!(
+        load_cell_data_into::<16>(0, Source::Output),
+        Err(SysError::LengthNotEnough(data.len()))
+    );
+    let mut witness_buf = [0u8; 1024];
+    match load_witness_args(0, Source::Input) {
+        Ok(witness_args) => {
+            let reader = load_witness_args_into(&mut witness_buf, 0, Source::Input).unwrap();
+            assert_eq!(reader.as_slice(), witness_args.as_slice());
+        }
+        Err(err) => assert_eq!(
+            load_witness_args_into(&mut witness_buf, 0, Source::Input).err(),
+            Some(err)
+        ),
+    }
+
     let inputs_capacity = QueryIter::new(load_cell, Source::Input)
         .map(|cell| {
             let capacity: u64 = cell.capacity().unpack();
@@ -162,13 +196,32 @@ fn test_calc_data_hash() {
     debug!("data hash {:?}", data_hash);
 }
 
+#[cfg(target_arch = "riscv64")]
+ckb_std::library_interface! {
+    struct SharedLib {
+        plus_42: unsafe extern "C" fn(n: usize) -> usize,
+        get_foo: unsafe extern "C" fn() -> *const u8 = "foo",
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+fn test_library_interface<L: dynamic_loading::SymbolLookup>(lib: &L) {
+    unsafe {
+        let shared_lib = SharedLib::resolve(lib).expect("resolve shared lib");
+        assert_eq!((shared_lib.plus_42)(13), 13 + 42);
+        let ptr = (shared_lib.get_foo)();
+        let mut buf = [0u8; 3];
+        buf.as_mut_ptr().copy_from(ptr, buf.len());
+        assert_eq!(&buf[..], b"foo");
+    }
+}
+
 #[cfg(target_arch = "riscv64")]
 type ContextTypeOld = dynamic_loading::CKBDLContext<[u8; 64 * 1024]>;
 
 #[cfg(target_arch = "riscv64")]
 fn test_dynamic_loading(context: &mut ContextTypeOld) {
     unsafe {
-        #[allow(deprecated)]
         let lib = context
             .load(&CODE_HASH_SHARED_LIB)
             .expect("load shared lib");
@@ -181,17 +234,28 @@ fn test_dynamic_loading(context: &mut ContextTypeOld) {
         let mut buf = [0u8; 3];
         buf.as_mut_ptr().copy_from(ptr, buf.len());
         assert_eq!(&buf[..], b"foo");
+        test_library_interface(&lib);
 
         // load multiple libraries
         let mut size = size_of_val(context) - lib.consumed_size();
         let mut offset = lib.consumed_size();
         let mut libs = Vec::new();
 
-        for _i in 0..3 {
-            #[allow(deprecated)]
-            let lib = context
-                .load_with_offset(&CODE_HASH_SHARED_LIB, offset, size)
-                .expect("load shared lib");
+        let index = high_level::look_for_dep_with_data_hash(&CODE_HASH_SHARED_LIB).unwrap();
+        for i in 0..3 {
+            let lib = match i {
+                0 => context.load_with_offset(&CODE_HASH_SHARED_LIB, offset, size),
+                1 => context.load_by_with_offset(
+                    &CODE_HASH_SHARED_LIB,
+                    ScriptHashType::Data,
+                    offset,
+                    size,
+                ),
+                _ => context.load_index_with_offset(index, offset, size),
+            }
+            .expect("load shared lib");
+            assert_eq!(lib.data_hash(), &CODE_HASH_SHARED_LIB);
+            assert_eq!(lib.cell_dep_index(), index);
             size -= lib.consumed_size();
             offset += lib.consumed_size();
             libs.push(lib);
@@ -213,6 +277,36 @@ fn test_dynamic_loading(context: &mut ContextTypeOld) {
     }
 }
 
+#[cfg(target_arch = "riscv64")]
+type ArenaContextType = dynamic_loading::CKBDLContext<[u8; 32 * 1024]>;
+
+#[cfg(target_arch = "riscv64")]
+fn test_dynamic_loading_arena(context: &mut ArenaContextType) {
+    let index = high_level::look_for_dep_with_data_hash(&CODE_HASH_SHARED_LIB).unwrap();
+    let required = dynamic_loading::required_size(index).expect("required size");
+    let mut arena = dynamic_loading::CKBDLArena::new(context);
+    let mut libs = Vec::new();
+    loop {
+        match arena.load(&CODE_HASH_SHARED_LIB) {
+            Ok(lib) => {
+                assert_eq!(lib.consumed_size(), required);
+                libs.push(lib);
+            }
+            Err(err) => {
+                assert_eq!(err, dynamic_loading::Error::MemoryNotEnough);
+                break;
+            }
+        }
+        assert_eq!(arena.consumed_size(), libs.len() * required);
+        assert_eq!(arena.consumed_size() + arena.remaining(), arena.capacity());
+    }
+    assert!(!libs.is_empty());
+    assert!(arena.remaining() < required);
+    for lib in libs {
+        test_library_interface(&lib);
+    }
+}
+
 #[cfg(target_arch = "riscv64")]
 type ContextType = dynamic_loading_c_impl::CKBDLContext<[u8; 64 * 1024]>;
 
@@ -233,16 +327,22 @@ fn test_dynamic_loading_c_impl(context: &mut ContextType) {
         let mut buf = [0u8; 3];
         buf.as_mut_ptr().copy_from(ptr, buf.len());
         assert_eq!(&buf[..], b"foo");
+        test_library_interface(&lib);
 
         // load multiple libraries
         let mut size = size_of_val(context) - lib.consumed_size();
         let mut offset = lib.consumed_size();
         let mut libs = Vec::new();
 
-        for _i in 0..3 {
-            let lib = context
-                .load_with_offset(&CODE_HASH_SHARED_LIB, ScriptHashType::Data, offset, size)
-                .expect("load shared lib");
+        let index = high_level::look_for_dep_with_data_hash(&CODE_HASH_SHARED_LIB).unwrap();
+        for i in 0..3 {
+            let lib = if i < 2 {
+                context.load_with_offset(&CODE_HASH_SHARED_LIB, ScriptHashType::Data, offset, size)
+            } else {
+                context.load_index_with_offset(index, offset, size)
+            }
+            .expect("load shared lib");
+            assert_eq!(lib.data_hash(), &CODE_HASH_SHARED_LIB);
             size -= lib.consumed_size();
             offset += lib.consumed_size();
             libs.push(lib);
@@ -264,6 +364,67 @@ fn test_dynamic_loading_c_impl(context: &mut ContextType) {
     }
 }
 
+#[cfg(target_arch = "riscv64")]
+type ParityContextType = dynamic_loading::CKBDLContext<[u8; 32 * 1024]>;
+
+#[cfg(target_arch = "riscv64")]
+type ParityCContextType = dynamic_loading_c_impl::CKBDLContext<[u8; 32 * 1024]>;
+
+#[cfg(target_arch = "riscv64")]
+fn test_dynamic_loading_parity(
+    rust_context: &mut ParityContextType,
+    c_context: &mut ParityCContextType,
+) {
+    use dynamic_loading::SymbolLookup;
+
+    // loaded pages are frozen, so the failures go to the second half of the
+    // contexts, which nothing else loads to
+    let index = high_level::look_for_dep_with_data_hash(&CODE_HASH_SHARED_LIB).unwrap();
+    let required = dynamic_loading::required_size(index).expect("required size");
+    let offset = 16 * 1024;
+    assert!(
+        rust_context
+            .load_with_offset(&CODE_HASH_SHARED_LIB, offset, required - 4096)
+            .is_err()
+    );
+    assert!(
+        c_context
+            .load_with_offset(
+                &CODE_HASH_SHARED_LIB,
+                ScriptHashType::Data,
+                offset,
+                required - 4096
+            )
+            .is_err()
+    );
+    assert!(rust_context.load(&[0u8; 32]).is_err());
+    #[allow(deprecated)]
+    let missing = c_context.load(&[0u8; 32]);
+    assert!(missing.is_err());
+
+    // both loaders map the shared library alike at the start of their context
+    let rust_base = rust_context as *const ParityContextType as usize;
+    let c_base = c_context as *const ParityCContextType as usize;
+    let rust_lib = rust_context
+        .load(&CODE_HASH_SHARED_LIB)
+        .expect("load shared lib");
+    #[allow(deprecated)]
+    let c_lib = c_context
+        .load(&CODE_HASH_SHARED_LIB)
+        .expect("load shared lib");
+    assert_eq!(rust_lib.consumed_size(), required);
+    assert_eq!(c_lib.consumed_size(), required);
+    assert_eq!(rust_lib.cell_dep_index(), c_lib.cell_dep_index());
+    assert_eq!(rust_lib.data_hash(), c_lib.data_hash());
+    for symbol in [&b"plus_42"[..], b"foo", b"missing"] {
+        let rust_offset = rust_lib.lookup(symbol).map(|ptr| ptr as usize - rust_base);
+        let c_offset = c_lib.lookup(symbol).map(|ptr| ptr as usize - c_base);
+        assert_eq!(rust_offset, c_offset);
+    }
+    test_library_interface(&rust_lib);
+    test_library_interface(&c_lib);
+}
+
 fn test_vm_version() {
     let version = syscalls::vm_version().unwrap();
     debug!("vm version: {}", version);
@@ -791,7 +952,8 @@ fn test_atomic2() {
 
 #[cfg(target_arch = "riscv64")]
 fn test_log() {
-    drop(ckb_std::logger::init());
+    // installed by the `init_logger` hook
+    assert!(ckb_std::logger::init().is_err());
     ckb_std::log::trace!("this is trace");
     ckb_std::log::debug!("this is debug");
     ckb_std::log::info!("this is info");
@@ -799,17 +961,175 @@ fn test_log() {
     ckb_std::log::error!("this is error");
 }
 
+#[cfg(target_arch = "riscv64")]
+fn test_cached_syscalls() {
+    use ckb_std::syscalls::{
+        NativeSyscalls,
+        cache::CachedSyscalls,
+        traits::{IoResult, SyscallImpls},
+    };
+
+    let cached = CachedSyscalls::new(NativeSyscalls, 4 * 1024);
+    let mut tx_hash = [0u8; 32];
+    for _ in 0..2 {
+        assert_eq!(
+            cached.load_tx_hash(&mut tx_hash, 0),
+            IoResult::FullyLoaded(32)
+        );
+        assert_eq!(tx_hash, high_level::load_tx_hash().unwrap());
+    }
+    let mut buf = [0u8; 8];
+    assert_eq!(
+        cached.load_script(&mut buf, 0).available(),
+        Some(high_level::load_script().unwrap().as_slice().len())
+    );
+    assert_eq!(cached.misses(), 2);
+    assert_eq!(cached.hits(), 1);
+    // 1000 bytes of output data don't fit in the budget left
+    let cached = CachedSyscalls::new(NativeSyscalls, 64);
+    let mut data = [0u8; 1000];
+    assert_eq!(
+        cached.load_cell_data(&mut data, 0, 0, Source::Output),
+        IoResult::FullyLoaded(1000)
+    );
+    assert_eq!(cached.cached_bytes(), 0);
+}
+
+#[cfg(target_arch = "riscv64")]
+fn test_ctx() {
+    use ckb_std::ctx::Ctx;
+    use ckb_std::syscalls::{NativeSyscalls, cache::CachedSyscalls};
+
+    let ctx = Ctx::new(CachedSyscalls::new(NativeSyscalls, 16 * 1024));
+    assert_eq!(
+        ctx.load_tx_hash().unwrap(),
+        high_level::load_tx_hash().unwrap()
+    );
+    assert_eq!(
+        ctx.load_script().unwrap().as_slice(),
+        high_level::load_script().unwrap().as_slice()
+    );
+    assert_eq!(
+        ctx.query(Ctx::load_cell_capacity, Source::Output)
+            .collect::<Vec<_>>(),
+        QueryIter::new(high_level::load_cell_capacity, Source::Output).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        ctx.load_cell_data(0, Source::Output).unwrap(),
+        high_level::load_cell_data(0, Source::Output).unwrap()
+    );
+    assert_eq!(
+        ctx.load_cell_type_hash(0, Source::Input).unwrap(),
+        high_level::load_cell_type_hash(0, Source::Input).unwrap()
+    );
+    assert!(ctx.impls().hits() > 0);
+}
+
+#[cfg(target_arch = "riscv64")]
+fn test_allocator_stats() {
+    use ckb_std::allocator::{self, FIXED_BLOCK_SIZE};
+
+    let before = allocator::stats();
+    let small = vec![0u8; 16];
+    let large = vec![0u8; 1000];
+    let stats = allocator::stats();
+    assert_eq!(stats.fixed.current, before.fixed.current + FIXED_BLOCK_SIZE);
+    assert_eq!(stats.buddy.current, before.buddy.current + 1024);
+    assert!(stats.buddy.peak >= stats.buddy.current);
+    assert_eq!(stats.fixed.capacity, 4 * 1024);
+    assert_eq!(stats.buddy.capacity, 516 * 1024);
+    drop(small);
+    drop(large);
+    let after = allocator::stats();
+    assert_eq!(after.fixed.current, before.fixed.current);
+    assert_eq!(after.buddy.current, before.buddy.current);
+    assert_eq!(after.buddy.peak, stats.buddy.peak);
+    debug!("{}", after);
+}
+
+#[cfg(target_arch = "riscv64")]
+fn test_vm_memory_alloc() {
+    use ckb_std::allocator::{HeapState, VmMemoryAlloc};
+    use core::alloc::{GlobalAlloc, Layout};
+
+    const STACK_RESERVE: usize = 512 * 1024;
+    // not the global allocator: it claims the memory after the binary, which
+    // the heap of default_alloc! in .bss doesn't overlap
+    static VM_MEMORY: VmMemoryAlloc = VmMemoryAlloc::new(STACK_RESERVE);
+
+    let capacity = VM_MEMORY.capacity();
+    assert!(capacity > 1024 * 1024);
+    let free = VM_MEMORY.free_bytes().unwrap();
+    let block = Layout::from_size_align(60 * 1000, 8).unwrap();
+    let mut blocks = Vec::new();
+    loop {
+        let ptr = unsafe { VM_MEMORY.alloc(block) };
+        if ptr.is_null() {
+            break;
+        }
+        // every byte of a block is usable
+        unsafe { ptr.write_bytes(0xff, block.size()) };
+        blocks.push(ptr);
+    }
+    assert_eq!(blocks.len(), free / block.size());
+    let rest = VM_MEMORY.free_bytes().unwrap();
+    assert!(rest < block.size());
+    // the heap stops a stack reserve below the end of the VM memory
+    let marker = 0u8;
+    let heap_end = *blocks.last().unwrap() as usize + block.size() + rest;
+    assert!(heap_end <= 4 * 1024 * 1024 - STACK_RESERVE);
+    assert!(heap_end < &marker as *const u8 as usize);
+
+    // near the limit, the rest can be allocated at once, and then nothing
+    let too_large = Layout::from_size_align(rest + 1, 8).unwrap();
+    assert!(unsafe { VM_MEMORY.alloc(too_large) }.is_null());
+    let rest_layout = Layout::from_size_align(rest, 8).unwrap();
+    if rest > 0 {
+        let ptr = unsafe { VM_MEMORY.alloc(rest_layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(VM_MEMORY.free_bytes(), Some(0));
+        assert!(unsafe { VM_MEMORY.alloc(Layout::new::<u8>()) }.is_null());
+        unsafe { VM_MEMORY.dealloc(ptr, rest_layout) };
+    }
+    for ptr in blocks {
+        unsafe { VM_MEMORY.dealloc(ptr, block) };
+    }
+    assert_eq!(VM_MEMORY.free_bytes(), Some(free));
+}
+
 #[cfg(target_arch = "riscv64")]
 lazy_static! {
     // Context should not be dropped.
     static ref old_context: Mutex<ContextTypeOld> = {
-        #[allow(deprecated)]
         Mutex::new(unsafe { ContextTypeOld::new() })
     };
     // Context should not be dropped.
     static ref new_context: Mutex<ContextType> = {
         Mutex::new(unsafe { ContextType::new() })
     };
+    // Context should not be dropped.
+    static ref arena_context: Mutex<ArenaContextType> = {
+        Mutex::new(unsafe { ArenaContextType::new() })
+    };
+    // Context should not be dropped.
+    static ref parity_context: Mutex<ParityContextType> = {
+        Mutex::new(unsafe { ParityContextType::new() })
+    };
+    // Context should not be dropped.
+    static ref parity_c_context: Mutex<ParityCContextType> = {
+        Mutex::new(unsafe { ParityCContextType::new() })
+    };
+}
+
+/// Runs before `main`, the logger is installed once for the whole script
+pub fn init_logger() {
+    drop(ckb_std::logger::init());
+}
+
+/// Runs before `main`, the script exits with the error if it fails
+pub fn check_script() -> Result<(), Error> {
+    high_level::load_script()?;
+    Ok(())
 }
 
 pub fn main() -> Result<(), Error> {
@@ -825,7 +1145,11 @@ pub fn main() -> Result<(), Error> {
     #[cfg(target_arch = "riscv64")]
     test_dynamic_loading(&mut old_context.lock());
     #[cfg(target_arch = "riscv64")]
+    test_dynamic_loading_arena(&mut arena_context.lock());
+    #[cfg(target_arch = "riscv64")]
     test_dynamic_loading_c_impl(&mut new_context.lock());
+    #[cfg(target_arch = "riscv64")]
+    test_dynamic_loading_parity(&mut parity_context.lock(), &mut parity_c_context.lock());
 
     test_vm_version();
     test_current_cycles();
@@ -835,6 +1159,10 @@ pub fn main() -> Result<(), Error> {
         test_atomic();
         test_atomic2();
         test_log();
+        test_allocator_stats();
+        test_vm_memory_alloc();
+        test_cached_syscalls();
+        test_ctx();
     }
 
     Ok(())
@@ -14,14 +14,8 @@ mod error;
 
 use ckb_std::default_alloc;
 
-ckb_std::entry!(program_entry);
-default_alloc!();
-
-/// program entry
-fn program_entry() -> i8 {
-    // Call main function and return error code
-    match entry::main() {
-        Ok(_) => 0,
-        Err(err) => err as i8,
-    }
-}
+ckb_std::entry!(
+    entry::main,
+    before = [entry::init_logger, entry::check_script]
+);
+default_alloc!(stats);
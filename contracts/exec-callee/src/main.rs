@@ -11,10 +11,10 @@
 mod entry;
 mod error;
 
-use ckb_std::default_alloc;
+use ckb_std::{allocator::OomConfig, list_alloc};
 
 ckb_std::entry!(program_entry);
-default_alloc!();
+list_alloc!(64 * 1024, oom = OomConfig::new());
 
 /// program entry
 fn program_entry() -> i8 {
@@ -11,10 +11,11 @@
 mod entry;
 mod error;
 
-use ckb_std::default_alloc;
+use ckb_std::bump_alloc;
 
 ckb_std::entry!(program_entry);
-default_alloc!();
+// spawned for a single write, nothing is ever freed
+bump_alloc!(64 * 1024);
 
 /// program entry
 ///
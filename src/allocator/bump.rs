@@ -0,0 +1,171 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+
+use super::HeapState;
+
+/// Bump allocator, memory is never freed
+///
+/// Allocating is a few instructions, which suits short scripts whose total
+/// allocations fit in the heap. Growing the last allocation, e.g. pushing to
+/// the most recent `Vec`, is done in place.
+pub struct BumpAlloc {
+    heap: *const u8,
+    heap_size: usize,
+    // offset of the first free byte
+    next: Cell<usize>,
+    // offset of the last allocation
+    last: Cell<usize>,
+}
+
+// CKB-VM is single threaded.
+unsafe impl Sync for BumpAlloc {}
+
+impl BumpAlloc {
+    /// Allocate from the `heap_size` bytes at `heap`
+    pub const fn new(heap: *const u8, heap_size: usize) -> Self {
+        BumpAlloc {
+            heap,
+            heap_size,
+            next: Cell::new(0),
+            last: Cell::new(usize::MAX),
+        }
+    }
+
+    /// Heap size
+    pub fn capacity(&self) -> usize {
+        self.heap_size
+    }
+
+    /// Bytes used so far, including alignment padding
+    pub fn used(&self) -> usize {
+        self.next.get()
+    }
+
+    /// Bytes left
+    pub fn remaining(&self) -> usize {
+        self.heap_size - self.next.get()
+    }
+
+    fn offset(&self, ptr: *mut u8) -> usize {
+        ptr as usize - self.heap as usize
+    }
+}
+
+impl HeapState for BumpAlloc {
+    fn free_bytes(&self) -> Option<usize> {
+        Some(self.remaining())
+    }
+}
+
+unsafe impl GlobalAlloc for BumpAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let start = self.heap as usize + self.next.get();
+        let aligned = match start.checked_add(layout.align() - 1) {
+            Some(end) => end & !(layout.align() - 1),
+            None => return core::ptr::null_mut(),
+        };
+        let offset = aligned - self.heap as usize;
+        match offset.checked_add(layout.size()) {
+            Some(end) if end <= self.heap_size => {
+                self.next.set(end);
+                self.last.set(offset);
+                aligned as *mut u8
+            }
+            _ => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let offset = self.offset(ptr);
+        if offset == self.last.get() && new_size <= self.heap_size - offset {
+            self.next.set(offset + new_size);
+            return ptr;
+        }
+        if new_size <= layout.size() {
+            return ptr;
+        }
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe { core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size()) };
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    const HEAP_SIZE: usize = 1024;
+
+    #[repr(align(64))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    fn heap() -> Box<Heap> {
+        Box::new(Heap([0; HEAP_SIZE]))
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn allocates_aligned_until_full() {
+        let mut heap = heap();
+        let start = heap.0.as_mut_ptr();
+        let alloc = BumpAlloc::new(start, HEAP_SIZE);
+        unsafe {
+            assert_eq!(alloc.alloc(layout(3, 1)), start);
+            let aligned = alloc.alloc(layout(8, 64));
+            assert_eq!(aligned, start.add(64));
+            // the padding is used up too
+            assert_eq!(alloc.used(), 72);
+            assert_eq!(alloc.remaining(), HEAP_SIZE - 72);
+
+            // freeing doesn't give memory back
+            alloc.dealloc(aligned, layout(8, 64));
+            assert_eq!(alloc.used(), 72);
+
+            assert!(alloc.alloc(layout(HEAP_SIZE - 71, 1)).is_null());
+            assert_eq!(alloc.alloc(layout(HEAP_SIZE - 72, 1)), start.add(72));
+            assert_eq!(alloc.remaining(), 0);
+            assert!(alloc.alloc(layout(1, 1)).is_null());
+        }
+    }
+
+    #[test]
+    fn grows_the_last_allocation_in_place() {
+        let mut heap = heap();
+        let start = heap.0.as_mut_ptr();
+        let alloc = BumpAlloc::new(start, HEAP_SIZE);
+        unsafe {
+            let first = alloc.alloc(layout(16, 8));
+            first.write_bytes(1, 16);
+            let last = alloc.alloc(layout(16, 8));
+            assert_eq!(alloc.realloc(last, layout(16, 8), 256), last);
+            assert_eq!(alloc.used(), 16 + 256);
+            // shrinking the last allocation gives the tail back
+            assert_eq!(alloc.realloc(last, layout(256, 8), 32), last);
+            assert_eq!(alloc.used(), 16 + 32);
+            // up to the end of the heap, not beyond
+            assert_eq!(alloc.realloc(last, layout(32, 8), HEAP_SIZE - 16), last);
+            assert!(
+                alloc
+                    .realloc(last, layout(HEAP_SIZE - 16, 8), HEAP_SIZE)
+                    .is_null()
+            );
+            assert_eq!(alloc.realloc(last, layout(HEAP_SIZE - 16, 8), 32), last);
+
+            // others move, keeping their content
+            assert_eq!(alloc.realloc(first, layout(16, 8), 8), first);
+            let moved = alloc.realloc(first, layout(16, 8), 64);
+            assert_eq!(moved, start.add(16 + 32));
+            assert_eq!(core::slice::from_raw_parts(moved, 16), &[1; 16]);
+            assert_eq!(alloc.used(), 16 + 32 + 64);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+use super::HeapState;
+
+// A free block, stored at the start of the block. Blocks are kept sorted by
+// address so neighbours can be merged when freeing.
+struct Node {
+    size: usize,
+    next: *mut Node,
+}
+
+// Allocation granularity, every block address and size is a multiple of it
+const UNIT: usize = size_of::<Node>();
+
+/// First fit free list allocator
+///
+/// Freed blocks are merged with their free neighbours, which keeps
+/// fragmentation low for workloads mixing sizes and lifetimes, at the cost of
+/// walking the list on each allocation.
+pub struct ListAlloc {
+    heap: Cell<*const u8>,
+    heap_size: Cell<usize>,
+    head: Cell<*mut Node>,
+    initialized: Cell<bool>,
+}
+
+// CKB-VM is single threaded.
+unsafe impl Sync for ListAlloc {}
+
+fn round_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
+fn block_size(layout: &Layout) -> usize {
+    round_up(layout.size().max(UNIT), UNIT)
+}
+
+impl ListAlloc {
+    /// Allocate from the `heap_size` bytes at `heap`
+    pub const fn new(heap: *const u8, heap_size: usize) -> Self {
+        ListAlloc {
+            heap: Cell::new(heap),
+            heap_size: Cell::new(heap_size),
+            head: Cell::new(null_mut()),
+            initialized: Cell::new(false),
+        }
+    }
+
+    /// Heap size
+    pub fn capacity(&self) -> usize {
+        self.heap_size.get()
+    }
+
+    pub(super) fn heap(&self) -> *const u8 {
+        self.heap.get()
+    }
+
+    // Move the heap, only before the first allocation
+    pub(super) fn set_heap(&self, heap: *const u8, heap_size: usize) {
+        debug_assert!(!self.initialized.get());
+        self.heap.set(heap);
+        self.heap_size.set(heap_size);
+    }
+
+    /// Sum of the free blocks, a request may still fail on fragmentation
+    pub fn free_bytes(&self) -> usize {
+        self.init();
+        let mut free = 0;
+        let mut node = self.head.get();
+        while !node.is_null() {
+            unsafe {
+                free += (*node).size;
+                node = (*node).next;
+            }
+        }
+        free
+    }
+
+    fn init(&self) {
+        if self.initialized.get() {
+            return;
+        }
+        self.initialized.set(true);
+        let heap = self.heap.get() as usize;
+        let start = round_up(heap, UNIT);
+        let end = (heap + self.heap_size.get()) & !(UNIT - 1);
+        if end > start {
+            let node = start as *mut Node;
+            unsafe {
+                node.write(Node {
+                    size: end - start,
+                    next: null_mut(),
+                })
+            };
+            self.head.set(node);
+        }
+    }
+}
+
+impl HeapState for ListAlloc {
+    fn free_bytes(&self) -> Option<usize> {
+        Some(ListAlloc::free_bytes(self))
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(ListAlloc::capacity(self))
+    }
+}
+
+unsafe impl GlobalAlloc for ListAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.init();
+        let size = block_size(&layout);
+        let align = layout.align().max(UNIT);
+        let mut prev: *mut Node = null_mut();
+        let mut node = self.head.get();
+        while !node.is_null() {
+            let (start, end, next) =
+                unsafe { (node as usize, node as usize + (*node).size, (*node).next) };
+            let aligned = round_up(start, align);
+            if aligned <= end && end - aligned >= size {
+                // the rest of the block stays free
+                let tail = aligned + size;
+                let mut rest = next;
+                if tail < end {
+                    rest = tail as *mut Node;
+                    unsafe {
+                        rest.write(Node {
+                            size: end - tail,
+                            next,
+                        })
+                    };
+                }
+                if aligned > start {
+                    // keep the padding before the allocation
+                    unsafe {
+                        (*node).size = aligned - start;
+                        (*node).next = rest;
+                    }
+                } else if prev.is_null() {
+                    self.head.set(rest);
+                } else {
+                    unsafe { (*prev).next = rest };
+                }
+                return aligned as *mut u8;
+            }
+            prev = node;
+            node = next;
+        }
+        null_mut()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let addr = ptr as usize;
+        let node = ptr as *mut Node;
+        let mut size = block_size(&layout);
+
+        let mut prev: *mut Node = null_mut();
+        let mut next = self.head.get();
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = unsafe { (*next).next };
+        }
+
+        unsafe {
+            if !next.is_null() && addr + size == next as usize {
+                size += (*next).size;
+                next = (*next).next;
+            }
+            if !prev.is_null() && prev as usize + (*prev).size == addr {
+                (*prev).size += size;
+                (*prev).next = next;
+            } else {
+                node.write(Node { size, next });
+                if prev.is_null() {
+                    self.head.set(node);
+                } else {
+                    (*prev).next = node;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, vec::Vec};
+
+    const HEAP_SIZE: usize = 4096;
+
+    #[repr(align(64))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    fn heap() -> Box<Heap> {
+        Box::new(Heap([0; HEAP_SIZE]))
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn splits_blocks_and_aligns() {
+        let mut heap = heap();
+        let alloc = ListAlloc::new(heap.0.as_mut_ptr(), HEAP_SIZE);
+        assert_eq!(alloc.free_bytes(), HEAP_SIZE);
+        unsafe {
+            // sizes are rounded up to the unit
+            let a = alloc.alloc(layout(1, 1));
+            assert_eq!(a, heap.0.as_mut_ptr());
+            assert_eq!(alloc.free_bytes(), HEAP_SIZE - UNIT);
+            let b = alloc.alloc(layout(3 * UNIT, 8));
+            assert_eq!(b as usize, a as usize + UNIT);
+            assert_eq!(alloc.free_bytes(), HEAP_SIZE - 4 * UNIT);
+
+            // the padding before an aligned block stays free
+            let c = alloc.alloc(layout(UNIT, 256));
+            assert_eq!(c as usize % 256, 0);
+            assert_eq!(c as usize, heap.0.as_ptr() as usize + 256);
+            assert_eq!(alloc.free_bytes(), HEAP_SIZE - 5 * UNIT);
+            // and serves the next fitting request
+            let d = alloc.alloc(layout(UNIT, 1));
+            assert_eq!(d as usize, b as usize + 3 * UNIT);
+
+            assert!(alloc.alloc(layout(HEAP_SIZE, 1)).is_null());
+        }
+    }
+
+    #[test]
+    fn merges_with_free_neighbours() {
+        let mut heap = heap();
+        let alloc = ListAlloc::new(heap.0.as_mut_ptr(), HEAP_SIZE);
+        let block = layout(4 * UNIT, 1);
+        unsafe {
+            let a = alloc.alloc(block);
+            let b = alloc.alloc(block);
+            let c = alloc.alloc(block);
+            let d = alloc.alloc(block);
+
+            alloc.dealloc(a, block);
+            alloc.dealloc(c, block);
+            // neither a nor c fit twice the block
+            assert_eq!(alloc.alloc(layout(8 * UNIT, 1)), d.add(4 * UNIT));
+            alloc.dealloc(d.add(4 * UNIT), layout(8 * UNIT, 1));
+
+            // b merges with both a and c
+            alloc.dealloc(b, block);
+            assert_eq!(alloc.alloc(layout(12 * UNIT, 1)), a);
+            alloc.dealloc(a, layout(12 * UNIT, 1));
+
+            // d merges with the block before it and the rest of the heap
+            alloc.dealloc(d, block);
+            assert_eq!(alloc.free_bytes(), HEAP_SIZE);
+            let all = layout(HEAP_SIZE, 1);
+            assert_eq!(alloc.alloc(all), a);
+            assert_eq!(alloc.free_bytes(), 0);
+            alloc.dealloc(a, all);
+        }
+        assert_eq!(alloc.free_bytes(), HEAP_SIZE);
+    }
+
+    #[test]
+    fn coalesces_back_to_the_whole_heap() {
+        let mut heap = heap();
+        let alloc = ListAlloc::new(heap.0.as_mut_ptr(), HEAP_SIZE);
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+        let mut seed = 0x2545_f491_u32;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize
+        };
+        for step in 0..5000 {
+            if live.is_empty() || random() % 3 != 0 {
+                let layout = layout(1 + random() % 200, 1 << (random() % 7));
+                let ptr = unsafe { alloc.alloc(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+                assert_eq!(ptr as usize % layout.align(), 0);
+                let fill = step as u8;
+                unsafe { ptr.write_bytes(fill, layout.size()) };
+                live.push((ptr, layout, fill));
+            } else {
+                let (ptr, layout, fill) = live.swap_remove(random() % live.len());
+                // no other block overlapped it
+                let data = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+                assert!(data.iter().all(|byte| *byte == fill));
+                unsafe { alloc.dealloc(ptr, layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { alloc.dealloc(ptr, layout) };
+        }
+        assert_eq!(alloc.free_bytes(), HEAP_SIZE);
+        assert!(!unsafe { alloc.alloc(layout(HEAP_SIZE, 1)) }.is_null());
+    }
+}
@@ -0,0 +1,41 @@
+//! Allocators used by the global allocator macros.
+//!
+//! * [`StatsAlloc`] wraps the allocator of `default_alloc!` to keep track of
+//!   the usage of the fixed block heap and of the buddy heap
+//! * [`BumpAlloc`] backs `bump_alloc!`, it never frees memory
+//! * [`ListAlloc`] backs `list_alloc!`, a first fit free list
+//! * [`VmMemoryAlloc`] backs `vm_memory_alloc!`, a [`ListAlloc`] on the VM
+//!   memory left between the loaded image and the stack
+//! * [`OomExit`] wraps the allocator of any macro given a trailing
+//!   `oom = ...`, it exits the script with a dedicated code when the heap is
+//!   exhausted, see [`OomConfig`]
+//!
+//! Statistics are enabled with `default_alloc!(stats)` or
+//! `default_alloc!(stats, fixed, heap, min_block)`:
+//!
+//! ```ignore
+//! default_alloc!(stats, 4 * 1024, 516 * 1024, 64);
+//!
+//! fn main() -> i8 {
+//!     // ...
+//!     debug!("{}", ckb_std::allocator::stats());
+//!     0
+//! }
+//! ```
+//!
+//! Nothing is printed automatically, neither by `default_alloc!` nor by
+//! `entry!`: the script prints [`stats`] itself, where the summary is
+//! wanted. The peak usage tells how much of each heap the script needs, so
+//! the arguments of `default_alloc!` can be sized from data.
+
+mod bump;
+mod list;
+mod oom;
+mod stats;
+mod vm_memory;
+
+pub use bump::BumpAlloc;
+pub use list::ListAlloc;
+pub use oom::{DEFAULT_OOM_EXIT_CODE, HeapState, OomConfig, OomExit, OomInfo, report};
+pub use stats::{FIXED_BLOCK_SIZE, HeapStats, Stats, StatsAlloc, stats};
+pub use vm_memory::{STACK_OVERFLOW_EXIT_CODE, VmMemoryAlloc};
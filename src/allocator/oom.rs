@@ -0,0 +1,249 @@
+use crate::debug::StackWriter;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code of scripts running out of memory, unless configured otherwise
+pub const DEFAULT_OOM_EXIT_CODE: i8 = -2;
+
+/// A failed allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OomInfo {
+    /// Requested size
+    pub size: usize,
+    /// Requested alignment
+    pub align: usize,
+    /// Heap size
+    pub capacity: usize,
+    /// Free bytes, if the allocator tracks them
+    pub free: Option<usize>,
+}
+
+impl fmt::Display for OomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "out of memory size={} align={} capacity={}",
+            self.size, self.align, self.capacity
+        )?;
+        if let Some(free) = self.free {
+            write!(f, " free={}", free)?;
+        }
+        Ok(())
+    }
+}
+
+/// The default hook, prints the failed allocation with `syscalls::debug_c_str`,
+/// e.g. `out of memory size=4096 align=8 capacity=532480 free=1024`
+pub fn report(info: &OomInfo) {
+    // the heap can't be used when it's exhausted
+    let mut w = StackWriter::<128>::new();
+    let _ = write!(w, "{}", info);
+    crate::syscalls::debug_c_str(w.as_c_str());
+}
+
+/// What to do when an allocation fails
+///
+/// # Example
+///
+/// ```ignore
+/// fn on_oom(info: &ckb_std::allocator::OomInfo) {
+///     ckb_std::allocator::report(info);
+///     // ...
+/// }
+///
+/// default_alloc!(
+///     4 * 1024,
+///     516 * 1024,
+///     64,
+///     oom = ckb_std::allocator::OomConfig::new().exit_code(-100).hook(on_oom)
+/// );
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct OomConfig {
+    exit_code: i8,
+    hook: fn(&OomInfo),
+}
+
+impl Default for OomConfig {
+    fn default() -> Self {
+        OomConfig::new()
+    }
+}
+
+impl OomConfig {
+    /// Call [`report`] then exit with [`DEFAULT_OOM_EXIT_CODE`]
+    pub const fn new() -> Self {
+        OomConfig {
+            exit_code: DEFAULT_OOM_EXIT_CODE,
+            hook: report,
+        }
+    }
+
+    /// Exit code of the script
+    pub const fn exit_code(mut self, exit_code: i8) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Called before exiting, in place of [`report`]
+    ///
+    /// The heap is exhausted when it runs: allocating fails again and exits
+    /// right away.
+    pub const fn hook(mut self, hook: fn(&OomInfo)) -> Self {
+        self.hook = hook;
+        self
+    }
+}
+
+/// Allocators able to tell how much memory is left
+pub trait HeapState {
+    /// Free bytes, a request may still fail on fragmentation
+    fn free_bytes(&self) -> Option<usize>;
+
+    /// Heap size, for allocators sizing their heap at runtime
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl HeapState for buddy_alloc::NonThreadsafeAlloc {
+    fn free_bytes(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Allocator wrapper exiting the script when an allocation fails
+///
+/// Without it, the script panics through the allocation error handler and
+/// exits with the same code as any other panic. The macros only use it when
+/// given a trailing `oom = ...`, since fallible allocations such as
+/// `Vec::try_reserve` exit too instead of returning an error.
+pub struct OomExit<A> {
+    inner: A,
+    capacity: usize,
+    config: OomConfig,
+}
+
+impl<A> OomExit<A> {
+    /// Wrap `inner`, whose heaps add up to `capacity` bytes unless it reports
+    /// its own
+    pub const fn new(inner: A, capacity: usize, config: OomConfig) -> Self {
+        OomExit {
+            inner,
+            capacity,
+            config,
+        }
+    }
+}
+
+impl<A: HeapState> OomExit<A> {
+    #[cold]
+    fn oom(&self, layout: Layout) -> ! {
+        // a failing allocation in the hook exits right away
+        static HANDLING: AtomicBool = AtomicBool::new(false);
+        if !HANDLING.swap(true, Ordering::Relaxed) {
+            (self.config.hook)(&OomInfo {
+                size: layout.size(),
+                align: layout.align(),
+                capacity: self.inner.capacity().unwrap_or(self.capacity),
+                free: self.inner.free_bytes(),
+            });
+        }
+        crate::syscalls::exit(self.config.exit_code)
+    }
+}
+
+unsafe impl<A: GlobalAlloc + HeapState> GlobalAlloc for OomExit<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if ptr.is_null() {
+            self.oom(layout);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if ptr.is_null() {
+            self.oom(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if new_ptr.is_null() {
+            self.oom(unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) });
+        }
+        new_ptr
+    }
+}
+
+#[cfg(all(test, feature = "stub-syscalls"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{
+        allocator::BumpAlloc,
+        syscalls::{stub_processes::NativeProcesses, traits::SyscallImpls},
+    };
+    use alloc::vec::Vec;
+    use core::ffi::CStr;
+    use std::sync::Mutex;
+
+    struct Silent;
+
+    impl SyscallImpls for Silent {
+        fn debug(&self, _s: &CStr) {}
+    }
+
+    #[repr(align(64))]
+    struct Heap([u8; 256]);
+
+    static mut HEAP: Heap = Heap([0; 256]);
+
+    static ALLOC: OomExit<BumpAlloc> = OomExit::new(
+        BumpAlloc::new(unsafe { &raw const HEAP.0 }.cast(), 256),
+        256,
+        OomConfig::new().exit_code(-100).hook(record),
+    );
+
+    static REPORTED: Mutex<Vec<OomInfo>> = Mutex::new(Vec::new());
+
+    fn record(info: &OomInfo) {
+        REPORTED.lock().unwrap().push(*info);
+        // allocating again in the hook doesn't call it twice
+        unsafe { ALLOC.alloc(Layout::from_size_align(512, 1).unwrap()) };
+        unreachable!("the second failure exits");
+    }
+
+    // the re-entry guard is global, so this is the only test running out
+    // of memory
+    #[test]
+    fn exits_with_the_configured_code() {
+        let code = NativeProcesses::new().run(Silent, || unsafe {
+            let layout = Layout::from_size_align(100, 8).unwrap();
+            assert!(!ALLOC.alloc(layout).is_null());
+            assert!(!ALLOC.alloc(layout).is_null());
+            ALLOC.alloc(layout);
+            unreachable!("the third allocation exits");
+        });
+        assert_eq!(code, -100);
+        assert_eq!(
+            *REPORTED.lock().unwrap(),
+            [OomInfo {
+                size: 100,
+                align: 8,
+                capacity: 256,
+                // the second block starts at 104 once aligned
+                free: Some(52),
+            }]
+        );
+    }
+}
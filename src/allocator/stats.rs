@@ -0,0 +1,185 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::fmt;
+
+use super::HeapState;
+
+/// Size of the blocks of the fixed block heap
+pub const FIXED_BLOCK_SIZE: usize = buddy_alloc::fast_alloc::BLOCK_SIZE;
+
+/// Usage of one heap, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Heap size
+    pub capacity: usize,
+    /// Bytes currently allocated
+    pub current: usize,
+    /// Highest value of `current`
+    pub peak: usize,
+    /// Number of allocations so far
+    pub allocations: usize,
+}
+
+impl HeapStats {
+    fn alloc(&mut self, size: usize) {
+        self.current += size;
+        self.peak = self.peak.max(self.current);
+        self.allocations += 1;
+    }
+
+    fn dealloc(&mut self, size: usize) {
+        self.current -= size;
+    }
+}
+
+/// Usage of the heaps of `default_alloc!`
+///
+/// The buddy heap counts the size of the blocks handed out, i.e. requests
+/// rounded up to a power of two and at least the min block size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Fixed block heap, for allocations up to [`FIXED_BLOCK_SIZE`] bytes
+    pub fixed: HeapStats,
+    /// Buddy heap, for larger allocations or when the fixed heap is full
+    pub buddy: HeapStats,
+    /// Number of allocations that failed
+    pub failures: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fixed heap: {}/{} bytes (peak {}, {} allocations), \
+             buddy heap: {}/{} bytes (peak {}, {} allocations), {} failures",
+            self.fixed.current,
+            self.fixed.capacity,
+            self.fixed.peak,
+            self.fixed.allocations,
+            self.buddy.current,
+            self.buddy.capacity,
+            self.buddy.peak,
+            self.buddy.allocations,
+            self.failures,
+        )
+    }
+}
+
+struct StatsCell(Cell<Stats>);
+
+// CKB-VM is single threaded.
+unsafe impl Sync for StatsCell {}
+
+static STATS: StatsCell = StatsCell(Cell::new(Stats {
+    fixed: HeapStats {
+        capacity: 0,
+        current: 0,
+        peak: 0,
+        allocations: 0,
+    },
+    buddy: HeapStats {
+        capacity: 0,
+        current: 0,
+        peak: 0,
+        allocations: 0,
+    },
+    failures: 0,
+}));
+
+/// Current usage of the heaps, all zero unless the global allocator is a
+/// [`StatsAlloc`]
+///
+/// Its `Display` output is a one line summary for `debug!`, to be printed
+/// by the script, usually before returning from `main`.
+pub fn stats() -> Stats {
+    STATS.0.get()
+}
+
+/// Allocator wrapper recording heap usage in [`stats`]
+///
+/// Allocations are attributed to the fixed block heap or to the buddy heap
+/// by address, so the wrapped allocator is expected to be the
+/// `NonThreadsafeAlloc` built on these heaps.
+pub struct StatsAlloc<A> {
+    inner: A,
+    fixed_heap: *const u8,
+    fixed_heap_size: usize,
+    buddy_heap_size: usize,
+    min_block_size: usize,
+}
+
+// CKB-VM is single threaded.
+unsafe impl<A> Sync for StatsAlloc<A> {}
+
+impl<A> StatsAlloc<A> {
+    /// Wrap `inner`, `fixed_heap` and `fixed_heap_size` locate the fixed
+    /// block heap, the other arguments are the ones of the buddy heap
+    pub const fn new(
+        inner: A,
+        fixed_heap: *const u8,
+        fixed_heap_size: usize,
+        buddy_heap_size: usize,
+        min_block_size: usize,
+    ) -> Self {
+        StatsAlloc {
+            inner,
+            fixed_heap,
+            fixed_heap_size,
+            buddy_heap_size,
+            min_block_size,
+        }
+    }
+
+    fn update<F: FnOnce(&mut Stats)>(&self, f: F) {
+        let mut stats = STATS.0.get();
+        stats.fixed.capacity = self.fixed_heap_size;
+        stats.buddy.capacity = self.buddy_heap_size;
+        f(&mut stats);
+        STATS.0.set(stats);
+    }
+
+    fn is_fixed(&self, ptr: *mut u8) -> bool {
+        let start = self.fixed_heap as usize;
+        (start..start + self.fixed_heap_size).contains(&(ptr as usize))
+    }
+
+    fn buddy_block_size(&self, size: usize) -> usize {
+        size.max(self.min_block_size).next_power_of_two()
+    }
+}
+
+impl<A> HeapState for StatsAlloc<A> {
+    fn free_bytes(&self) -> Option<usize> {
+        let stats = stats();
+        Some(
+            self.fixed_heap_size + self.buddy_heap_size - stats.fixed.current - stats.buddy.current,
+        )
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for StatsAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        self.update(|stats| {
+            if ptr.is_null() {
+                stats.failures += 1;
+            } else if self.is_fixed(ptr) {
+                stats.fixed.alloc(FIXED_BLOCK_SIZE);
+            } else {
+                stats.buddy.alloc(self.buddy_block_size(layout.size()));
+            }
+        });
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.update(|stats| {
+            if self.is_fixed(ptr) {
+                stats.fixed.dealloc(FIXED_BLOCK_SIZE);
+            } else {
+                stats.buddy.dealloc(self.buddy_block_size(layout.size()));
+            }
+        });
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+}
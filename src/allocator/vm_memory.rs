@@ -0,0 +1,124 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+
+use super::{HeapState, ListAlloc};
+
+/// Exit code of scripts whose stack grew into the heap of [`VmMemoryAlloc`]
+pub const STACK_OVERFLOW_EXIT_CODE: i8 = -3;
+
+// Memory of CKB-VM, the stack grows down from its end
+#[cfg(target_arch = "riscv64")]
+const VM_MEMORY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Allocator claiming the VM memory between the end of the loaded image and
+/// the stack
+///
+/// The heap starts at the `_end` linker symbol, right after `.bss`, and stops
+/// `stack_reserve` bytes below the end of the VM memory, where CKB-VM puts
+/// the stack. The arguments of the script are copied at the top of the
+/// stack, so they count in the reserve. `_end` comes from the linker script
+/// the contract is linked with: the default one of the riscv64 toolchains
+/// defines it, a custom linker script must define it after `.bss` too.
+///
+/// The heap bounds aren't set up by `entry!`, they are resolved on the first
+/// allocation, or the first call to [`capacity`](Self::capacity). The heap
+/// is then managed as a [`ListAlloc`]. Each allocation and deallocation checks that the stack
+/// pointer is above the heap, and exits with [`STACK_OVERFLOW_EXIT_CODE`]
+/// otherwise. This only catches a stack overflowing its reserve while
+/// memory is allocated or freed, a deeper stack in between still overwrites
+/// the heap.
+///
+/// Only riscv64 has a VM memory layout to claim, on other targets the heap
+/// is empty.
+pub struct VmMemoryAlloc {
+    stack_reserve: usize,
+    list: ListAlloc,
+    resolved: Cell<bool>,
+}
+
+// CKB-VM is single threaded.
+unsafe impl Sync for VmMemoryAlloc {}
+
+impl VmMemoryAlloc {
+    /// Leave `stack_reserve` bytes to the stack
+    pub const fn new(stack_reserve: usize) -> Self {
+        VmMemoryAlloc {
+            stack_reserve,
+            list: ListAlloc::new(core::ptr::null(), 0),
+            resolved: Cell::new(false),
+        }
+    }
+
+    /// Heap size, resolving the heap if needed
+    pub fn capacity(&self) -> usize {
+        self.list().capacity()
+    }
+
+    fn list(&self) -> &ListAlloc {
+        if !self.resolved.get() {
+            self.resolved.set(true);
+            let (start, end) = region(self.stack_reserve);
+            self.list
+                .set_heap(start as *const u8, end.saturating_sub(start));
+        }
+        &self.list
+    }
+
+    // Exit if the stack reached the heap, before the heap is used
+    fn check_stack(&self) {
+        let list = self.list();
+        let end = list.heap() as usize + list.capacity();
+        if list.capacity() > 0 && stack_pointer() < end {
+            crate::syscalls::debug_c_str(c"stack overflow into the heap of vm_memory_alloc!");
+            crate::syscalls::exit(STACK_OVERFLOW_EXIT_CODE);
+        }
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+fn region(stack_reserve: usize) -> (usize, usize) {
+    unsafe extern "C" {
+        static _end: u8;
+    }
+    let start = &raw const _end as usize;
+    (start, VM_MEMORY_SIZE.saturating_sub(stack_reserve))
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+fn region(_stack_reserve: usize) -> (usize, usize) {
+    (0, 0)
+}
+
+#[cfg(target_arch = "riscv64")]
+fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe { core::arch::asm!("mv {}, sp", out(reg) sp) };
+    sp
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+fn stack_pointer() -> usize {
+    usize::MAX
+}
+
+impl HeapState for VmMemoryAlloc {
+    fn free_bytes(&self) -> Option<usize> {
+        Some(self.list().free_bytes())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Some(VmMemoryAlloc::capacity(self))
+    }
+}
+
+unsafe impl GlobalAlloc for VmMemoryAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.check_stack();
+        unsafe { self.list().alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.check_stack();
+        unsafe { self.list().dealloc(ptr, layout) }
+    }
+}
@@ -4,10 +4,42 @@ pub const DEFAULT_PANIC_EXIT_CODE: i8 = -1;
 
 pub static mut __PANIC_EXIT_CODE: i8 = DEFAULT_PANIC_EXIT_CODE;
 
+// Each native process of `syscalls::stub_processes` runs on its own thread.
+#[cfg(feature = "stub-syscalls")]
+std::thread_local! {
+    static PANIC_EXIT_CODE: core::cell::Cell<i8> =
+        const { core::cell::Cell::new(DEFAULT_PANIC_EXIT_CODE) };
+}
+
+#[cfg(feature = "stub-syscalls")]
+extern crate std;
+
 pub fn set_panic_exit_code(code: i8) {
+    #[cfg(not(feature = "stub-syscalls"))]
     unsafe {
         __PANIC_EXIT_CODE = code;
     }
+    #[cfg(feature = "stub-syscalls")]
+    PANIC_EXIT_CODE.with(|current| current.set(code))
+}
+
+/// Exit code of a panic at this point, per thread under `stub-syscalls`
+pub fn panic_exit_code() -> i8 {
+    #[cfg(not(feature = "stub-syscalls"))]
+    unsafe {
+        __PANIC_EXIT_CODE
+    }
+    #[cfg(feature = "stub-syscalls")]
+    PANIC_EXIT_CODE.with(|current| current.get())
+}
+
+/// Called by the panic handler of [`entry!`](crate::entry), emits a compact
+/// report with feature `panic-report`, see [`panic_report`](crate::panic_report)
+#[doc(hidden)]
+#[inline(always)]
+pub fn __report_panic(_info: &core::panic::PanicInfo) {
+    #[cfg(feature = "panic-report")]
+    crate::panic_report::report(_info);
 }
 
 #[macro_export]
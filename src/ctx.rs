@@ -0,0 +1,739 @@
+//! High level APIs over an explicit syscall implementation.
+//!
+//! The functions of [`high_level`](crate::high_level) use the global
+//! syscalls, which under `stub-syscalls` is the implementation installed on
+//! the current thread. [`Ctx`] offers the same functions as methods over a
+//! [`SyscallImpls`] it owns, so a library can be tested against several mock
+//! transactions at once without installing any:
+//!
+//! ```ignore
+//! use ckb_std::ctx::Ctx;
+//! use ckb_std::testing::{MockSyscalls, MockTransaction, ScriptGroup};
+//!
+//! fn total_input_capacity<S: SyscallImpls>(ctx: &Ctx<S>) -> u64 {
+//!     ctx.query(Ctx::load_cell_capacity, Source::Input).sum()
+//! }
+//!
+//! let tx = MockTransaction::new().input(...);
+//! let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::Lock(0)));
+//! assert_eq!(total_input_capacity(&ctx), 1000);
+//! ```
+//!
+//! On chain, `Ctx::new(NativeSyscalls)` issues the syscalls directly.
+
+use crate::ckb_constants::*;
+use crate::error::SysError;
+use crate::high_level::{ArrayBuf, BUF_SIZE, QueryIter};
+use crate::syscalls::{
+    self,
+    traits::{Bounds, Error, IoResult, SyscallImpls},
+};
+use alloc::{vec, vec::Vec};
+use ckb_types::{core::ScriptHashType, packed::*, prelude::*};
+use core::convert::Infallible;
+use core::ffi::CStr;
+
+fn io(result: IoResult) -> Result<usize, SysError> {
+    result.into()
+}
+
+// Fully load data from a loading syscall
+fn load_data<F: Fn(&mut [u8], usize) -> Result<usize, SysError>>(
+    syscall: F,
+) -> Result<Vec<u8>, SysError> {
+    let mut buf = [0u8; BUF_SIZE];
+    match syscall(&mut buf, 0) {
+        Ok(len) => Ok(buf[..len].to_vec()),
+        Err(SysError::LengthNotEnough(actual_size)) => {
+            let mut data = vec![0; actual_size];
+            let loaded_len = buf.len();
+            data[..loaded_len].copy_from_slice(&buf);
+            let len = syscall(&mut data[loaded_len..], loaded_len)?;
+            debug_assert_eq!(len + loaded_len, actual_size);
+            Ok(data)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Fill `buf` with the data at `offset`, the data may go on after it
+fn load_exact<F: Fn(&mut [u8], usize) -> Result<usize, SysError>>(
+    syscall: F,
+    buf: &mut [u8],
+    offset: usize,
+) -> Result<(), SysError> {
+    match syscall(buf, offset) {
+        Ok(len) if len == buf.len() => Ok(()),
+        Ok(_) => Err(SysError::Encoding),
+        Err(SysError::LengthNotEnough(_)) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn script_args_into<const N: usize, F>(load_script: F) -> Result<ArrayBuf<N>, SysError>
+where
+    F: Fn(&mut [u8], usize) -> Result<usize, SysError>,
+{
+    // Script is a molecule table: total size and the offsets of code_hash,
+    // hash_type and args, then the fields. args is `Bytes`, a 4 bytes length
+    // and the bytes.
+    let mut header = [0u8; 16];
+    load_exact(&load_script, &mut header, 0)?;
+    let word = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap()) as usize;
+    let (total_size, args_offset) = (word(0), word(3));
+    let mut args_len = [0u8; 4];
+    load_exact(&load_script, &mut args_len, args_offset)?;
+    let args_len = u32::from_le_bytes(args_len) as usize;
+    if args_offset + 4 + args_len != total_size {
+        return Err(SysError::Encoding);
+    }
+    if args_len > N {
+        return Err(SysError::LengthNotEnough(args_len));
+    }
+    ArrayBuf::load_with(|buf| {
+        load_exact(&load_script, &mut buf[..args_len], args_offset + 4)?;
+        Ok(args_len)
+    })
+}
+
+/// The syscalls of the backend selected by the features, as used by the
+/// functions of [`high_level`](crate::high_level)
+pub(crate) struct GlobalSyscalls;
+
+pub(crate) const GLOBAL: Ctx<GlobalSyscalls> = Ctx {
+    impls: GlobalSyscalls,
+};
+
+// Back to the result of the trait, so that the high level functions return
+// the errors of the global syscalls unchanged
+fn load_result(result: Result<usize, SysError>, len: usize) -> IoResult {
+    let err = match result {
+        Ok(loaded) => return IoResult::FullyLoaded(loaded),
+        Err(SysError::LengthNotEnough(available)) => {
+            return IoResult::PartialLoaded {
+                loaded: len,
+                available,
+            };
+        }
+        Err(err) => err,
+    };
+    IoResult::Error(match err {
+        SysError::IndexOutOfBound => Error::IndexOutOfBound,
+        SysError::ItemMissing => Error::ItemMissing,
+        SysError::Encoding => Error::SliceOutOfBound,
+        SysError::WaitFailure => Error::WaitFailure,
+        SysError::InvalidFd => Error::InvalidFd,
+        SysError::OtherEndClosed => Error::OtherEndClosed,
+        SysError::MaxVmsSpawned => Error::MaxVmsSpawned,
+        SysError::MaxFdsCreated => Error::MaxFdsCreated,
+        SysError::Unknown(errno) => Error::Other(errno),
+        err => unreachable!("not a loading error: {:?}", err),
+    })
+}
+
+impl SyscallImpls for GlobalSyscalls {
+    fn debug(&self, s: &CStr) {
+        syscalls::debug_c_str(s)
+    }
+
+    fn load_cell(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_cell(buf, offset, index, source), len)
+    }
+
+    fn load_cell_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: CellField,
+    ) -> IoResult {
+        let len = buf.len();
+        load_result(
+            syscalls::load_cell_by_field(buf, offset, index, source, field),
+            len,
+        )
+    }
+
+    fn load_cell_data(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_cell_data(buf, offset, index, source), len)
+    }
+
+    fn load_header(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_header(buf, offset, index, source), len)
+    }
+
+    fn load_header_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: HeaderField,
+    ) -> IoResult {
+        let len = buf.len();
+        load_result(
+            syscalls::load_header_by_field(buf, offset, index, source, field),
+            len,
+        )
+    }
+
+    fn load_input(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_input(buf, offset, index, source), len)
+    }
+
+    fn load_input_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: InputField,
+    ) -> IoResult {
+        let len = buf.len();
+        load_result(
+            syscalls::load_input_by_field(buf, offset, index, source, field),
+            len,
+        )
+    }
+
+    fn load_script(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_script(buf, offset), len)
+    }
+
+    fn load_script_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_script_hash(buf, offset), len)
+    }
+
+    fn load_transaction(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_transaction(buf, offset), len)
+    }
+
+    fn load_tx_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_tx_hash(buf, offset), len)
+    }
+
+    fn load_witness(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let len = buf.len();
+        load_result(syscalls::load_witness(buf, offset, index, source), len)
+    }
+}
+
+/// High level APIs over a [`SyscallImpls`], see the [module](self) docs
+///
+/// Methods mirror the functions of [`high_level`](crate::high_level) with
+/// the same arguments and results, except that [`inherited_fds`](Self::inherited_fds)
+/// returns the errors of the syscall. The loading functions of `high_level`
+/// call these methods with the global syscalls.
+pub struct Ctx<S> {
+    impls: S,
+}
+
+impl<S: SyscallImpls> Ctx<S> {
+    /// Use `impls` for all syscalls
+    pub fn new(impls: S) -> Self {
+        Ctx { impls }
+    }
+
+    /// Syscall implementation
+    pub fn impls(&self) -> &S {
+        &self.impls
+    }
+
+    /// Take the syscall implementation back
+    pub fn into_inner(self) -> S {
+        self.impls
+    }
+
+    fn load_cell_by_field_raw(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: CellField,
+    ) -> Result<usize, SysError> {
+        self.impls
+            .load_cell_by_field(buf, offset, index, source, field)
+            .into()
+    }
+
+    fn load_u64(&self, index: usize, source: Source, field: CellField) -> Result<u64, SysError> {
+        let mut buf = [0u8; 8];
+        let len = self.load_cell_by_field_raw(&mut buf, 0, index, source, field)?;
+        debug_assert_eq!(len, buf.len());
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn load_header_u64(
+        &self,
+        index: usize,
+        source: Source,
+        field: HeaderField,
+    ) -> Result<u64, SysError> {
+        let mut buf = [0u8; 8];
+        let len = io(self
+            .impls
+            .load_header_by_field(&mut buf, 0, index, source, field))?;
+        debug_assert_eq!(len, buf.len());
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn load_hash(
+        &self,
+        index: usize,
+        source: Source,
+        field: CellField,
+    ) -> Result<[u8; 32], SysError> {
+        let mut buf = [0u8; 32];
+        let len = self.load_cell_by_field_raw(&mut buf, 0, index, source, field)?;
+        debug_assert_eq!(len, buf.len());
+        Ok(buf)
+    }
+
+    /// See [`high_level::load_tx_hash`](crate::high_level::load_tx_hash)
+    pub fn load_tx_hash(&self) -> Result<[u8; 32], SysError> {
+        let mut hash = [0u8; 32];
+        let len = io(self.impls.load_tx_hash(&mut hash, 0))?;
+        debug_assert_eq!(hash.len(), len);
+        Ok(hash)
+    }
+
+    /// See [`high_level::load_script_hash`](crate::high_level::load_script_hash)
+    pub fn load_script_hash(&self) -> Result<[u8; 32], SysError> {
+        let mut hash = [0u8; 32];
+        let len = io(self.impls.load_script_hash(&mut hash, 0))?;
+        debug_assert_eq!(hash.len(), len);
+        Ok(hash)
+    }
+
+    /// See [`high_level::load_cell`](crate::high_level::load_cell)
+    pub fn load_cell(&self, index: usize, source: Source) -> Result<CellOutput, SysError> {
+        let data =
+            load_data(|buf, offset| self.impls.load_cell(buf, offset, index, source).into())?;
+
+        match CellOutputReader::verify(&data, false) {
+            Ok(()) => Ok(CellOutput::new_unchecked(data.into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_input`](crate::high_level::load_input)
+    pub fn load_input(&self, index: usize, source: Source) -> Result<CellInput, SysError> {
+        let mut data = [0u8; CellInput::TOTAL_SIZE];
+        io(self.impls.load_input(&mut data, 0, index, source))?;
+
+        match CellInputReader::verify(&data, false) {
+            Ok(()) => Ok(CellInput::new_unchecked(data.to_vec().into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_header`](crate::high_level::load_header)
+    pub fn load_header(&self, index: usize, source: Source) -> Result<Header, SysError> {
+        let mut data = [0u8; Header::TOTAL_SIZE];
+        io(self.impls.load_header(&mut data, 0, index, source))?;
+
+        match HeaderReader::verify(&data, false) {
+            Ok(()) => Ok(Header::new_unchecked(data.to_vec().into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_witness`](crate::high_level::load_witness)
+    pub fn load_witness(&self, index: usize, source: Source) -> Result<Vec<u8>, SysError> {
+        load_data(|buf, offset| self.impls.load_witness(buf, offset, index, source).into())
+    }
+
+    /// See [`high_level::load_witness_args`](crate::high_level::load_witness_args)
+    pub fn load_witness_args(&self, index: usize, source: Source) -> Result<WitnessArgs, SysError> {
+        let data = self.load_witness(index, source)?;
+
+        match WitnessArgsReader::verify(&data, false) {
+            Ok(()) => Ok(WitnessArgs::new_unchecked(data.into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_transaction`](crate::high_level::load_transaction)
+    pub fn load_transaction(&self) -> Result<Transaction, SysError> {
+        let data = load_data(|buf, offset| self.impls.load_transaction(buf, offset).into())?;
+
+        match TransactionReader::verify(&data, false) {
+            Ok(()) => Ok(Transaction::new_unchecked(data.into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_cell_capacity`](crate::high_level::load_cell_capacity)
+    pub fn load_cell_capacity(&self, index: usize, source: Source) -> Result<u64, SysError> {
+        self.load_u64(index, source, CellField::Capacity)
+    }
+
+    /// See [`high_level::load_cell_occupied_capacity`](crate::high_level::load_cell_occupied_capacity)
+    pub fn load_cell_occupied_capacity(
+        &self,
+        index: usize,
+        source: Source,
+    ) -> Result<u64, SysError> {
+        self.load_u64(index, source, CellField::OccupiedCapacity)
+    }
+
+    /// See [`high_level::load_cell_data_hash`](crate::high_level::load_cell_data_hash)
+    pub fn load_cell_data_hash(&self, index: usize, source: Source) -> Result<[u8; 32], SysError> {
+        self.load_hash(index, source, CellField::DataHash)
+    }
+
+    /// See [`high_level::load_cell_lock_hash`](crate::high_level::load_cell_lock_hash)
+    pub fn load_cell_lock_hash(&self, index: usize, source: Source) -> Result<[u8; 32], SysError> {
+        self.load_hash(index, source, CellField::LockHash)
+    }
+
+    /// See [`high_level::load_cell_type_hash`](crate::high_level::load_cell_type_hash)
+    pub fn load_cell_type_hash(
+        &self,
+        index: usize,
+        source: Source,
+    ) -> Result<Option<[u8; 32]>, SysError> {
+        match self.load_hash(index, source, CellField::TypeHash) {
+            Ok(hash) => Ok(Some(hash)),
+            Err(SysError::ItemMissing) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// See [`high_level::load_cell_lock`](crate::high_level::load_cell_lock)
+    pub fn load_cell_lock(&self, index: usize, source: Source) -> Result<Script, SysError> {
+        let data = load_data(|buf, offset| {
+            self.load_cell_by_field_raw(buf, offset, index, source, CellField::Lock)
+        })?;
+
+        match ScriptReader::verify(&data, false) {
+            Ok(()) => Ok(Script::new_unchecked(data.into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_cell_type`](crate::high_level::load_cell_type)
+    pub fn load_cell_type(&self, index: usize, source: Source) -> Result<Option<Script>, SysError> {
+        let data = match load_data(|buf, offset| {
+            self.load_cell_by_field_raw(buf, offset, index, source, CellField::Type)
+        }) {
+            Ok(data) => data,
+            Err(SysError::ItemMissing) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        match ScriptReader::verify(&data, false) {
+            Ok(()) => Ok(Some(Script::new_unchecked(data.into()))),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_header_epoch_number`](crate::high_level::load_header_epoch_number)
+    pub fn load_header_epoch_number(&self, index: usize, source: Source) -> Result<u64, SysError> {
+        self.load_header_u64(index, source, HeaderField::EpochNumber)
+    }
+
+    /// See [`high_level::load_header_epoch_start_block_number`](crate::high_level::load_header_epoch_start_block_number)
+    pub fn load_header_epoch_start_block_number(
+        &self,
+        index: usize,
+        source: Source,
+    ) -> Result<u64, SysError> {
+        self.load_header_u64(index, source, HeaderField::EpochStartBlockNumber)
+    }
+
+    /// See [`high_level::load_header_epoch_length`](crate::high_level::load_header_epoch_length)
+    pub fn load_header_epoch_length(&self, index: usize, source: Source) -> Result<u64, SysError> {
+        self.load_header_u64(index, source, HeaderField::EpochLength)
+    }
+
+    /// See [`high_level::load_input_since`](crate::high_level::load_input_since)
+    pub fn load_input_since(&self, index: usize, source: Source) -> Result<u64, SysError> {
+        let mut buf = [0u8; 8];
+        let field = InputField::Since;
+        let len = io(self
+            .impls
+            .load_input_by_field(&mut buf, 0, index, source, field))?;
+        debug_assert_eq!(len, buf.len());
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// See [`high_level::load_input_out_point`](crate::high_level::load_input_out_point)
+    pub fn load_input_out_point(&self, index: usize, source: Source) -> Result<OutPoint, SysError> {
+        let mut data = [0u8; OutPoint::TOTAL_SIZE];
+        let field = InputField::OutPoint;
+        io(self
+            .impls
+            .load_input_by_field(&mut data, 0, index, source, field))?;
+
+        match OutPointReader::verify(&data, false) {
+            Ok(()) => Ok(OutPoint::new_unchecked(data.to_vec().into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_cell_data`](crate::high_level::load_cell_data)
+    pub fn load_cell_data(&self, index: usize, source: Source) -> Result<Vec<u8>, SysError> {
+        load_data(|buf, offset| self.impls.load_cell_data(buf, offset, index, source).into())
+    }
+
+    /// See [`high_level::load_cell_data_into`](crate::high_level::load_cell_data_into)
+    pub fn load_cell_data_into<const N: usize>(
+        &self,
+        index: usize,
+        source: Source,
+    ) -> Result<ArrayBuf<N>, SysError> {
+        ArrayBuf::load_with(|buf| self.impls.load_cell_data(buf, 0, index, source).into())
+    }
+
+    /// See [`high_level::load_script`](crate::high_level::load_script)
+    pub fn load_script(&self) -> Result<Script, SysError> {
+        let data = load_data(|buf, offset| self.impls.load_script(buf, offset).into())?;
+
+        match ScriptReader::verify(&data, false) {
+            Ok(()) => Ok(Script::new_unchecked(data.into())),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// See [`high_level::load_script_args_into`](crate::high_level::load_script_args_into)
+    pub fn load_script_args_into<const N: usize>(&self) -> Result<ArrayBuf<N>, SysError> {
+        script_args_into(|buf, offset| self.impls.load_script(buf, offset).into())
+    }
+
+    /// See [`high_level::load_witness_args_into`](crate::high_level::load_witness_args_into)
+    pub fn load_witness_args_into<'a>(
+        &self,
+        buf: &'a mut [u8],
+        index: usize,
+        source: Source,
+    ) -> Result<WitnessArgsReader<'a>, SysError> {
+        let len = io(self.impls.load_witness(buf, 0, index, source))?;
+        let data = &buf[..len];
+        match WitnessArgsReader::verify(data, false) {
+            Ok(()) => Ok(WitnessArgsReader::new_unchecked(data)),
+            Err(_err) => Err(SysError::Encoding),
+        }
+    }
+
+    /// Iterate with a query method, like [`QueryIter`]
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let inputs_capacity = ctx
+    ///     .query(Ctx::load_cell_capacity, Source::Input)
+    ///     .sum::<u64>();
+    /// ```
+    pub fn query<'a, T, F>(
+        &'a self,
+        query_fn: F,
+        source: Source,
+    ) -> QueryIter<impl Fn(usize, Source) -> Result<T, SysError> + 'a>
+    where
+        F: Fn(&Self, usize, Source) -> Result<T, SysError> + 'a,
+    {
+        QueryIter::new(move |index, source| query_fn(self, index, source), source)
+    }
+
+    /// See [`high_level::find_cell_by_data_hash`](crate::high_level::find_cell_by_data_hash)
+    pub fn find_cell_by_data_hash(
+        &self,
+        data_hash: &[u8],
+        source: Source,
+    ) -> Result<Option<usize>, SysError> {
+        for i in 0.. {
+            let hash = match self.load_cell_data_hash(i, source) {
+                Ok(hash) => hash,
+                Err(SysError::IndexOutOfBound) => break,
+                Err(err) => return Err(err),
+            };
+            if data_hash == &hash[..] {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// See [`high_level::look_for_dep_with_hash2`](crate::high_level::look_for_dep_with_hash2)
+    pub fn look_for_dep_with_hash2(
+        &self,
+        code_hash: &[u8],
+        hash_type: ScriptHashType,
+    ) -> Result<usize, SysError> {
+        let field = match hash_type {
+            ScriptHashType::Type => CellField::TypeHash,
+            _ => CellField::DataHash,
+        };
+        for i in 0.. {
+            match self.load_hash(i, Source::CellDep, field) {
+                Ok(hash) if hash == code_hash => return Ok(i),
+                Ok(_) | Err(SysError::ItemMissing) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        unreachable!()
+    }
+
+    /// See [`high_level::look_for_dep_with_data_hash`](crate::high_level::look_for_dep_with_data_hash)
+    pub fn look_for_dep_with_data_hash(&self, data_hash: &[u8]) -> Result<usize, SysError> {
+        self.look_for_dep_with_hash2(data_hash, ScriptHashType::Data)
+    }
+
+    /// See [`high_level::exec_cell`](crate::high_level::exec_cell)
+    ///
+    /// A syscall implementation returning `Ok` from `exec` didn't replace
+    /// the process, this panics.
+    pub fn exec_cell(
+        &self,
+        code_hash: &[u8],
+        hash_type: ScriptHashType,
+        argv: &[&CStr],
+    ) -> Result<Infallible, SysError> {
+        let index = self.look_for_dep_with_hash2(code_hash, hash_type)?;
+        match self
+            .impls
+            .exec(index, Source::CellDep, Place::Cell, Bounds::from(0), argv)
+        {
+            Ok(()) => panic!("exec returned"),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// See [`high_level::spawn_cell`](crate::high_level::spawn_cell)
+    pub fn spawn_cell(
+        &self,
+        code_hash: &[u8],
+        hash_type: ScriptHashType,
+        argv: &[&CStr],
+        inherited_fds: &[u64],
+    ) -> Result<u64, SysError> {
+        let index = self.look_for_dep_with_hash2(code_hash, hash_type)?;
+        self.impls
+            .spawn(
+                index,
+                Source::CellDep,
+                Place::Cell,
+                Bounds::from(0),
+                argv,
+                inherited_fds,
+            )
+            .map_err(Into::into)
+    }
+
+    /// See [`high_level::inherited_fds`](crate::high_level::inherited_fds)
+    pub fn inherited_fds(&self) -> Result<Vec<u64>, SysError> {
+        let mut fds = [0u64; 64];
+        let len = self.impls.inherited_fds(&mut fds)?;
+        Ok(fds[..len.min(fds.len())].to_vec())
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "stub-syscalls"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::high_level;
+    use crate::syscalls::{NOT_INSTALLED, with_impls};
+    use crate::testing::{Contract, MockSyscalls, MockTransaction, ScriptGroup};
+
+    fn tx(capacities: &[u64]) -> MockTransaction {
+        let lock = Contract::new("lock", || 0).script(&[]);
+        capacities
+            .iter()
+            .fold(MockTransaction::new(), |tx, capacity| {
+                let cell = CellOutput::new_builder()
+                    .capacity(*capacity)
+                    .lock(lock.clone())
+                    .build();
+                tx.input(cell, [])
+            })
+    }
+
+    fn total_input_capacity<S: SyscallImpls>(ctx: &Ctx<S>) -> u64 {
+        ctx.query(Ctx::load_cell_capacity, Source::Input).sum()
+    }
+
+    #[test]
+    fn contexts_are_independent() {
+        let first = tx(&[100, 200]);
+        let second = tx(&[1000]);
+        let first = Ctx::new(MockSyscalls::new(&first, ScriptGroup::Lock(0)));
+        let second = Ctx::new(MockSyscalls::new(&second, ScriptGroup::Lock(0)));
+        assert_eq!(total_input_capacity(&first), 300);
+        assert_eq!(total_input_capacity(&second), 1000);
+        assert_eq!(first.query(Ctx::load_cell, Source::GroupInput).count(), 2);
+        assert_eq!(
+            second.load_cell_capacity(1, Source::Input),
+            Err(SysError::IndexOutOfBound)
+        );
+        // nothing is installed for the global syscalls
+        assert!(!crate::syscalls::installed());
+        std::thread::scope(|scope| {
+            scope.spawn(|| assert_eq!(total_input_capacity(&second), 1000));
+            assert_eq!(total_input_capacity(&first), 300);
+        });
+    }
+
+    #[test]
+    fn high_level_loads_with_the_global_syscalls() {
+        // longer than the first load of `load_data`
+        let tx = tx(&[100]).witness([7; 1000]);
+        let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::Lock(0)));
+        // the errors of the global syscalls are returned unchanged
+        assert_eq!(high_level::load_tx_hash(), Err(NOT_INSTALLED));
+        with_impls(MockSyscalls::new(&tx, ScriptGroup::Lock(0)), || {
+            let witness = high_level::load_witness(0, Source::Input);
+            assert_eq!(witness, ctx.load_witness(0, Source::Input));
+            assert_eq!(witness.unwrap(), [7; 1000]);
+            assert_eq!(high_level::load_cell_capacity(0, Source::Input), Ok(100));
+            assert_eq!(high_level::load_cell_type_hash(0, Source::Input), Ok(None));
+            assert_eq!(
+                high_level::load_cell_capacity(1, Source::Input),
+                Err(SysError::IndexOutOfBound)
+            );
+        });
+    }
+
+    #[test]
+    fn inherited_fds_returns_errors() {
+        struct Fds(Result<usize, Error>);
+
+        impl SyscallImpls for Fds {
+            fn inherited_fds(&self, fds: &mut [u64]) -> Result<usize, Error> {
+                let len = self.0?;
+                fds.iter_mut().zip(2..).for_each(|(fd, n)| *fd = n);
+                Ok(len)
+            }
+        }
+
+        assert_eq!(Ctx::new(Fds(Ok(2))).inherited_fds(), Ok(alloc::vec![2, 3]));
+        assert_eq!(
+            Ctx::new(Fds(Err(Error::InvalidFd))).inherited_fds(),
+            Err(SysError::InvalidFd)
+        );
+    }
+}
@@ -40,3 +40,216 @@ macro_rules! debug {
         $crate::syscalls::debug(alloc::format!($fmt, $($args), +));
     };
 }
+
+/// debug_kv macro
+///
+/// Output a debug message as a line of `key=value` pairs ([logfmt](https://brandur.org/logfmt)),
+/// which is easier to parse by tools than the free-form text of `debug!`. Values
+/// are formatted with `Display`, or with `Debug` when prefixed with `?`. Values
+/// containing spaces, `=`, `"` or control characters are quoted, with `"` and
+/// `\` escaped and control characters written as Rust escapes (`\n`,
+/// `\u{0}`...), so a value never spans several lines. An optional leading
+/// literal is output as the `msg` field.
+///
+/// Like `debug!`, this macro only compiles under debug build.
+///
+/// # Example
+///
+/// ```
+/// debug_kv!("load cell", index = 3, capacity = capacity, lock = ?lock_hash);
+/// // msg="load cell" index=3 capacity=1000 lock="[1, 2, ...]"
+/// ```
+#[macro_export]
+macro_rules! debug_kv {
+    (@fields $line:ident $(,)?) => {};
+    (@fields $line:ident, $key:ident = ?$value:expr $(, $($rest:tt)*)?) => {
+        $line.debug(core::stringify!($key), &$value);
+        $crate::debug_kv!(@fields $line $(, $($rest)*)?);
+    };
+    (@fields $line:ident, $key:ident = $value:expr $(, $($rest:tt)*)?) => {
+        $line.display(core::stringify!($key), &$value);
+        $crate::debug_kv!(@fields $line $(, $($rest)*)?);
+    };
+    ($msg:literal $(, $($fields:tt)*)?) => {
+        #[cfg(debug_assertions)]
+        {
+            let mut line = $crate::debug::KvLine::new();
+            line.display("msg", $msg);
+            $crate::debug_kv!(@fields line $(, $($fields)*)?);
+            line.emit();
+        }
+    };
+    ($($fields:tt)+) => {
+        #[cfg(debug_assertions)]
+        {
+            let mut line = $crate::debug::KvLine::new();
+            $crate::debug_kv!(@fields line, $($fields)+);
+            line.emit();
+        }
+    };
+}
+
+/// A line of `key=value` pairs, used by `debug_kv!`
+#[derive(Default)]
+pub struct KvLine(alloc::string::String);
+
+impl KvLine {
+    /// Create an empty line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a field formatted with `Display`
+    pub fn display<T: core::fmt::Display + ?Sized>(&mut self, key: &str, value: &T) {
+        self.push(key, format_args!("{}", value));
+    }
+
+    /// Append a field formatted with `Debug`
+    pub fn debug<T: core::fmt::Debug + ?Sized>(&mut self, key: &str, value: &T) {
+        self.push(key, format_args!("{:?}", value));
+    }
+
+    fn push(&mut self, key: &str, value: core::fmt::Arguments) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        self.0.push_str(key);
+        self.0.push('=');
+        let value = alloc::fmt::format(value);
+        let quote = value.is_empty()
+            || value
+                .chars()
+                .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+        if !quote {
+            self.0.push_str(&value);
+            return;
+        }
+        self.0.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => self.0.push_str("\\\""),
+                '\\' => self.0.push_str("\\\\"),
+                c if c.is_control() => self.0.extend(c.escape_default()),
+                c => self.0.push(c),
+            }
+        }
+        self.0.push('"');
+    }
+
+    /// Output the line with `syscalls::debug`
+    pub fn emit(self) {
+        crate::syscalls::debug(self.0)
+    }
+
+    /// The line built so far
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+// Formats into a nul terminated stack buffer, truncating on a char boundary,
+// for the paths that can't allocate: out of memory and panic reports.
+#[cfg(any(feature = "allocator", feature = "panic-report"))]
+pub(crate) struct StackWriter<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+#[cfg(any(feature = "allocator", feature = "panic-report"))]
+impl<const N: usize> StackWriter<N> {
+    pub(crate) const fn new() -> Self {
+        StackWriter {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn as_c_str(&self) -> &core::ffi::CStr {
+        core::ffi::CStr::from_bytes_until_nul(&self.buf).unwrap_or_default()
+    }
+}
+
+#[cfg(any(feature = "allocator", feature = "panic-report"))]
+impl<const N: usize> core::fmt::Write for StackWriter<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let mut n = s.len().min(N - 1 - self.len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KvLine;
+
+    #[test]
+    fn kv_line_quotes_and_escapes() {
+        let mut line = KvLine::new();
+        line.display("plain", "abc");
+        line.display("empty", "");
+        line.display("spaced", "a b");
+        line.display("quoted", "say \"hi\" \\o/");
+        line.display("controls", "a\nb\r\tc\0\x1b[31m");
+        line.debug("debug", &Some(1));
+        assert_eq!(
+            line.as_str(),
+            r#"plain=abc empty="" spaced="a b" quoted="say \"hi\" \\o/" controls="a\nb\r\tc\u{0}\u{1b}[31m" debug=Some(1)"#
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "stub-syscalls", debug_assertions))]
+    fn debug_kv_formats_fields() {
+        extern crate std;
+        use crate::syscalls::{traits::SyscallImpls, with_impls};
+        use alloc::{string::String, vec::Vec};
+        use core::ffi::CStr;
+        use std::sync::{Arc, Mutex};
+
+        struct Captured(Arc<Mutex<Vec<String>>>);
+
+        impl SyscallImpls for Captured {
+            fn debug(&self, s: &CStr) {
+                let message = s.to_string_lossy().into_owned();
+                self.0.lock().unwrap().push(message);
+            }
+        }
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        with_impls(Captured(captured.clone()), || {
+            let capacity = 1000;
+            crate::debug_kv!("load cell", index = 3, capacity = capacity, lock = ?[1, 2]);
+            crate::debug_kv!(key = "a=b");
+        });
+        assert_eq!(
+            *captured.lock().unwrap(),
+            [
+                r#"msg="load cell" index=3 capacity=1000 lock="[1, 2]""#,
+                r#"key="a=b""#
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "allocator", feature = "panic-report"))]
+    fn stack_writer_truncates_on_char_boundaries() {
+        use super::StackWriter;
+        use core::fmt::Write;
+
+        let mut w = StackWriter::<8>::new();
+        w.write_str("ab").unwrap();
+        w.write_str("cdé").unwrap();
+        assert_eq!(w.as_c_str().to_bytes(), "abcdé".as_bytes());
+        // 'é' takes 2 bytes, only one is left before the nul
+        w.write_str("é").unwrap();
+        assert_eq!(w.as_c_str().to_bytes(), "abcdé".as_bytes());
+        w.write_str("f").unwrap();
+        assert_eq!(w.as_c_str().to_bytes(), "abcdéf".as_bytes());
+        w.write_str("g").unwrap();
+        assert_eq!(w.as_c_str().to_bytes(), "abcdéf".as_bytes());
+    }
+}
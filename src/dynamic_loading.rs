@@ -6,6 +6,24 @@
 //! * Shared library cell: deploy the shared library to the chain.
 //! * Transaction: use the CellDep field reference to the shared library cell.
 //!
+//! The library cell can be located by data hash ([`CKBDLContext::load`]), by
+//! type hash for libraries upgraded through Type ID ([`CKBDLContext::load_by`])
+//! or directly by cell dep index ([`CKBDLContext::load_index`]).
+//!
+//! # Supported ELF features
+//!
+//! The loader is written in pure Rust and doesn't require a C toolchain, it
+//! supports the same subset of ELF as `dynamic_loading_c_impl`:
+//!
+//! * any number of `PT_LOAD` segments (up to 16 program headers), executable
+//!   segments are loaded with `load_cell_code`, others as plain data;
+//! * `R_RISCV_RELATIVE`, `R_RISCV_64` and `R_RISCV_JUMP_SLOT` relocations
+//!   found through the `PT_DYNAMIC` segment (`DT_RELA` and `DT_JMPREL`);
+//! * symbol lookup through `DT_GNU_HASH` or `DT_HASH`.
+//!
+//! Libraries can't import symbols from other libraries: undefined symbols are
+//! only accepted when they are weak, and resolve to 0.
+//!
 //! # Example
 //!
 //! Shared library(C)
@@ -67,11 +85,13 @@
 //! <https://github.com/riscv/riscv-pk/blob/master/pk/elf.h>
 //! original code is in BSD license.
 
-use crate::ckb_constants::Source;
+use crate::ckb_constants::{CellField, Source};
 use crate::error::SysError;
-use crate::high_level::find_cell_by_data_hash;
-use crate::syscalls::{load_cell_code, load_cell_data_raw};
-use core::cmp::{max, min};
+use crate::high_level::{find_cell_by_data_hash, look_for_dep_with_hash2};
+use crate::syscalls::{load_cell_by_field, load_cell_code, load_cell_data_raw};
+use alloc::vec::Vec;
+use ckb_types::core::ScriptHashType;
+use core::cmp::max;
 use core::marker::PhantomData;
 use core::mem::{size_of, zeroed};
 
@@ -94,27 +114,14 @@ struct Elf64Ehdr {
     e_shstrndx: u16,
 }
 
-const SHT_STRTAB: usize = 3;
-const SHT_RELA: usize = 4;
-const SHT_DYNSYM: usize = 11;
-
-#[repr(C)]
-#[derive(Default)]
-struct Elf64Shdr {
-    sh_name: u32,
-    sh_type: u32,
-    sh_flags: u64,
-    sh_addr: u64,
-    sh_offset: u64,
-    sh_size: u64,
-    sh_link: u32,
-    sh_info: u32,
-    sh_addralign: u64,
-    sh_entsize: u64,
-}
+const ELFMAG: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const EM_RISCV: u16 = 243;
 
 const PT_LOAD: usize = 1;
+const PT_DYNAMIC: usize = 2;
 const PF_X: usize = 1;
+const MAX_PHNUM: usize = 16;
 
 #[repr(C)]
 #[derive(Default)]
@@ -129,6 +136,27 @@ struct Elf64Phdr {
     p_align: u64,
 }
 
+const DT_NULL: u64 = 0;
+const DT_PLTRELSZ: u64 = 2;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_SYMENT: u64 = 11;
+const DT_JMPREL: u64 = 23;
+const DT_GNU_HASH: u64 = 0x6fff_fef5;
+
+#[repr(C)]
+struct Elf64Dyn {
+    d_tag: u64,
+    d_val: u64,
+}
+
+const SHN_UNDEF: u16 = 0;
+const STB_WEAK: u8 = 2;
+
 #[repr(C)]
 struct Elf64Sym {
     st_name: u32,
@@ -139,10 +167,12 @@ struct Elf64Sym {
     st_size: u64,
 }
 
-const R_RISCV_RELATIVE: usize = 3;
+const R_RISCV_64: u32 = 2;
+const R_RISCV_RELATIVE: u32 = 3;
+const R_RISCV_JUMP_SLOT: u32 = 5;
 
 #[repr(C)]
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 struct Elf64Rela {
     r_offset: u64,
     r_info: u64,
@@ -205,21 +235,50 @@ impl<T> core::ops::Deref for Symbol<T> {
 
 /// Dynamic loaded library
 pub struct Library {
-    dynsyms: *const Elf64Sym,
-    dynstr: *const u8,
-    dynsym_size: usize,
     base_addr: *const u8,
+    symtab: *const Elf64Sym,
+    strtab: *const u8,
+    hash: *const u32,
+    gnu_hash: *const u32,
     consumed_size: usize,
+    data_hash: [u8; 32],
+    index: usize,
+}
+
+/// Hash function used by `DT_HASH`
+fn elf_hash(name: &[u8]) -> u32 {
+    let mut h: u32 = 0;
+    for c in name {
+        h = (h << 4).wrapping_add(*c as u32);
+        let g = h & 0xf000_0000;
+        if g != 0 {
+            h ^= g >> 24;
+        }
+        h &= !g;
+    }
+    h
+}
+
+/// Hash function used by `DT_GNU_HASH`
+fn gnu_hash(name: &[u8]) -> u32 {
+    let mut h: u32 = 5381;
+    for c in name {
+        h = h.wrapping_mul(33).wrapping_add(*c as u32);
+    }
+    h
 }
 
 impl Library {
     fn new() -> Self {
         Library {
-            dynsyms: core::ptr::null(),
-            dynstr: core::ptr::null(),
-            dynsym_size: 0,
             base_addr: core::ptr::null(),
+            symtab: core::ptr::null(),
+            strtab: core::ptr::null(),
+            hash: core::ptr::null(),
+            gnu_hash: core::ptr::null(),
             consumed_size: 0,
+            data_hash: [0u8; 32],
+            index: 0,
         }
     }
 
@@ -228,45 +287,393 @@ impl Library {
         self.consumed_size
     }
 
-    /// # Unsafe
+    /// Data hash of the cell the library was loaded from
+    ///
+    /// When the library is loaded by type hash, this is the hash of the code
+    /// actually loaded, which can be logged or checked against an allow list.
+    pub fn data_hash(&self) -> &[u8; 32] {
+        &self.data_hash
+    }
+
+    /// Index of the cell dep the library was loaded from
+    pub fn cell_dep_index(&self) -> usize {
+        self.index
+    }
+
+    /// Compare a nul terminated string in the library with `s`
+    unsafe fn name_eq(&self, sym: *const Elf64Sym, s: &[u8]) -> bool {
+        unsafe {
+            let ptr = self.strtab.add((*sym).st_name as usize);
+            for (i, c) in s.iter().enumerate() {
+                // return false if symbol string is end
+                if *ptr.add(i) != *c {
+                    return false;
+                }
+            }
+            // return false if symbol string is not terminated
+            *ptr.add(s.len()) == 0
+        }
+    }
+
+    unsafe fn lookup_gnu_hash(&self, name: &[u8]) -> Option<*const Elf64Sym> {
+        unsafe {
+            let nbuckets = *self.gnu_hash;
+            let symoffset = *self.gnu_hash.add(1);
+            let bloom_size = *self.gnu_hash.add(2);
+            let bloom_shift = *self.gnu_hash.add(3);
+            if nbuckets == 0 || bloom_size == 0 {
+                return None;
+            }
+            let bloom = self.gnu_hash.add(4).cast::<u64>();
+            let buckets = bloom.add(bloom_size as usize).cast::<u32>();
+            let chain = buckets.add(nbuckets as usize);
+
+            let h = gnu_hash(name);
+            let word = bloom.add(((h / 64) % bloom_size) as usize).read_unaligned();
+            let mask = (1u64 << (h % 64)) | (1u64 << ((h >> bloom_shift) % 64));
+            if word & mask != mask {
+                return None;
+            }
+            let mut index = *buckets.add((h % nbuckets) as usize);
+            if index < symoffset {
+                return None;
+            }
+            loop {
+                let h2 = *chain.add((index - symoffset) as usize);
+                let sym = self.symtab.add(index as usize);
+                if (h | 1) == (h2 | 1) && self.name_eq(sym, name) {
+                    return Some(sym);
+                }
+                if h2 & 1 != 0 {
+                    return None;
+                }
+                index += 1;
+            }
+        }
+    }
+
+    unsafe fn lookup_hash(&self, name: &[u8]) -> Option<*const Elf64Sym> {
+        unsafe {
+            let nbucket = *self.hash;
+            let nchain = *self.hash.add(1);
+            if nbucket == 0 {
+                return None;
+            }
+            let buckets = self.hash.add(2);
+            let chain = buckets.add(nbucket as usize);
+            let mut index = *buckets.add((elf_hash(name) % nbucket) as usize);
+            while index != 0 && index < nchain {
+                let sym = self.symtab.add(index as usize);
+                if self.name_eq(sym, name) {
+                    return Some(sym);
+                }
+                index = *chain.add(index as usize);
+            }
+            None
+        }
+    }
+
+    /// # Safety
     ///
     /// Undefined behavior will happen if the type S not match the type of symbol in the shared
     /// library
     ///
     /// Return None if not found the symbol
     pub unsafe fn get<S>(&self, symbol: &[u8]) -> Option<Symbol<S>> {
+        // accept both plain and nul terminated names
+        let symbol = match symbol.split_last() {
+            Some((0, rest)) => rest,
+            _ => symbol,
+        };
         unsafe {
-            unsafe fn cmp_raw_str(ptr: *const u8, s: &[u8]) -> bool {
-                unsafe {
-                    let mut i = 0;
-                    for c in s {
-                        let sym_c = *ptr.add(i);
-                        // return false if symbol string is end
-                        if sym_c == 0 {
-                            return false;
-                        }
-                        if &sym_c != c {
-                            return false;
-                        }
-                        i += 1;
+            let sym = if !self.gnu_hash.is_null() {
+                self.lookup_gnu_hash(symbol)
+            } else {
+                self.lookup_hash(symbol)
+            }?;
+            if (*sym).st_shndx == SHN_UNDEF {
+                return None;
+            }
+            Some(Symbol::new(self.base_addr.add((*sym).st_value as usize)))
+        }
+    }
+}
+
+impl SymbolLookup for Library {
+    fn lookup(&self, symbol: &[u8]) -> Option<*const u8> {
+        unsafe { self.get::<*const u8>(symbol).map(|s| *s) }
+    }
+}
+
+/// Raw symbol lookup of a loaded library
+///
+/// Implemented by the `Library` of both `dynamic_loading` and
+/// `dynamic_loading_c_impl`, this is what [`library_interface!`](crate::library_interface) resolves
+/// symbols with.
+pub trait SymbolLookup {
+    /// Return the address of `symbol`, None if not found.
+    /// `symbol` can be either nul terminated or not.
+    fn lookup(&self, symbol: &[u8]) -> Option<*const u8>;
+}
+
+/// Errors of resolving a library interface declared by [`library_interface!`](crate::library_interface)
+#[derive(Debug, Eq, PartialEq)]
+pub enum InterfaceError {
+    /// Symbols not found in the library, in declaration order
+    MissingSymbols(Vec<&'static str>),
+    /// Version symbol not found in the library
+    MissingVersion(&'static str),
+    /// Version exported by the library doesn't match the expected one
+    VersionMismatch { expected: u32, actual: u32 },
+}
+
+/// Check that the library exports a `u32` named `symbol` equal to `expected`
+pub fn check_version<L: SymbolLookup + ?Sized>(
+    lib: &L,
+    symbol: &'static str,
+    expected: u32,
+) -> Result<(), InterfaceError> {
+    let ptr = lib
+        .lookup(symbol.as_bytes())
+        .ok_or(InterfaceError::MissingVersion(symbol))?;
+    let actual = unsafe { ptr.cast::<u32>().read_unaligned() };
+    if actual != expected {
+        return Err(InterfaceError::VersionMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Declare the interface of a shared library
+///
+/// The macro generates a struct with one function pointer field per symbol,
+/// and an `unsafe fn resolve(lib: &impl SymbolLookup) -> Result<Self, InterfaceError>`
+/// looking up all symbols at once. The symbol name is the field name, unless
+/// given explicitly after the type. If any symbol is missing,
+/// `InterfaceError::MissingSymbols` lists all of them.
+///
+/// A version check can be added after the struct name: the library must then
+/// export a `u32` with the given name and value, it's checked before the
+/// other symbols are resolved.
+///
+/// # Example
+///
+/// ```
+/// use ckb_std::{dynamic_loading::CKBDLContext, library_interface};
+///
+/// library_interface! {
+///     /// Interface of the shared library
+///     pub struct SharedLib: version("shared_lib_version") == 1 {
+///         pub plus_42: unsafe extern "C" fn(usize) -> usize,
+///         pub get_foo: unsafe extern "C" fn() -> *const u8 = "foo",
+///     }
+/// }
+///
+/// let mut context = unsafe { CKBDLContext::<[u8; 64 * 1024]>::new() };
+/// let lib = context.load(&CODE_HASH_SHARED_LIB).expect("load shared lib");
+/// let shared_lib = unsafe { SharedLib::resolve(&lib) }.expect("resolve");
+/// assert_eq!(unsafe { (shared_lib.plus_42)(13) }, 13 + 42);
+/// ```
+#[macro_export]
+macro_rules! library_interface {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident $(: version($version_symbol:literal) == $version:literal)? {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident: $ty:ty $(= $symbol:literal)?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $name {
+            /// Resolve all symbols of the interface from `lib`
+            ///
+            /// # Safety
+            ///
+            /// Undefined behavior will happen if the declared types don't match
+            /// the types of the symbols in the shared library
+            #[allow(dead_code)]
+            pub unsafe fn resolve<L: $crate::dynamic_loading::SymbolLookup + ?Sized>(
+                lib: &L,
+            ) -> Result<Self, $crate::dynamic_loading::InterfaceError> {
+                $(
+                    $crate::dynamic_loading::check_version(lib, $version_symbol, $version)?;
+                )?
+                let mut missing = $crate::__alloc::vec::Vec::new();
+                $(
+                    let symbol = $crate::library_interface!(@symbol $field $($symbol)?);
+                    let $field = lib.lookup(symbol.as_bytes());
+                    if $field.is_none() {
+                        missing.push(symbol);
                     }
-                    let term_c = *ptr.add(i);
-                    // return false if symbol string is not terminated
-                    term_c == 0
+                )*
+                if !missing.is_empty() {
+                    return Err($crate::dynamic_loading::InterfaceError::MissingSymbols(missing));
                 }
+                Ok(Self {
+                    $(
+                        $field: unsafe { core::mem::transmute::<*const u8, $ty>($field.unwrap()) },
+                    )*
+                })
             }
+        }
+    };
+    (@symbol $field:ident) => {
+        stringify!($field)
+    };
+    (@symbol $field:ident $symbol:literal) => {
+        $symbol
+    };
+}
 
-            for i in 0..self.dynsym_size {
-                let sym = self.dynsyms.add(i);
-                let str_ptr = self.dynstr.add((*sym).st_name as usize);
-                if cmp_raw_str(str_ptr, symbol) {
-                    let sym_ptr = self.base_addr.add((*sym).st_value as usize);
-                    return Some(Symbol::new(sym_ptr));
-                }
+/// Load `len` bytes at `offset` of the cell data into `ptr`, the cell data
+/// must contain enough bytes.
+fn load_exact(ptr: *mut u8, len: usize, offset: usize, index: usize) -> Result<(), Error> {
+    if len == 0 {
+        return Ok(());
+    }
+    let loaded_len = match load_cell_data_raw(ptr, len, offset, index, Source::CellDep) {
+        Ok(len) => len,
+        Err(SysError::LengthNotEnough(_)) => len,
+        Err(err) => return Err(err.into()),
+    };
+    if loaded_len < len {
+        return Err(Error::InvalidElf);
+    }
+    Ok(())
+}
+
+/// ELF header and program headers of a library
+struct ElfHeaders {
+    program_hdrs: [Elf64Phdr; MAX_PHNUM],
+    phnum: usize,
+}
+
+impl ElfHeaders {
+    fn load(index: usize) -> Result<Self, Error> {
+        let mut hdr = Elf64Ehdr::default();
+        load_exact(
+            (&mut hdr as *mut Elf64Ehdr).cast(),
+            size_of::<Elf64Ehdr>(),
+            0,
+            index,
+        )?;
+        if hdr.e_ident[0..4] != ELFMAG
+            || hdr.e_ident[4] != ELFCLASS64
+            || hdr.e_machine != EM_RISCV
+            || hdr.e_phentsize as usize != size_of::<Elf64Phdr>()
+            || hdr.e_phnum as usize > MAX_PHNUM
+        {
+            return Err(Error::InvalidElf);
+        }
+        let mut headers = ElfHeaders {
+            program_hdrs: Default::default(),
+            phnum: hdr.e_phnum as usize,
+        };
+        load_exact(
+            headers.program_hdrs.as_mut_ptr().cast(),
+            size_of::<Elf64Phdr>() * headers.phnum,
+            hdr.e_phoff as usize,
+            index,
+        )?;
+        Ok(headers)
+    }
+
+    fn program_hdrs(&self) -> &[Elf64Phdr] {
+        &self.program_hdrs[..self.phnum]
+    }
+
+    /// Page aligned memory required to load all `PT_LOAD` segments
+    fn required_size(&self) -> Result<usize, Error> {
+        let mut size = 0;
+        for ph in self.program_hdrs() {
+            if ph.p_type as usize == PT_LOAD && ph.p_memsz > 0 {
+                let end = (ph.p_vaddr as usize)
+                    .checked_add(ph.p_memsz as usize)
+                    .ok_or(Error::InvalidElf)?;
+                size = max(size, roundup_shift(end, RISCV_PGSIZE_SHIFT));
             }
+        }
+        if size == 0 {
+            return Err(Error::InvalidElf);
+        }
+        Ok(size)
+    }
+}
+
+/// Resolve the value of a relocation against symbol `sym_index`
+unsafe fn symbol_value(
+    base: *mut u8,
+    symtab: *const Elf64Sym,
+    sym_index: usize,
+) -> Result<u64, Error> {
+    unsafe {
+        if sym_index == 0 {
+            return Ok(0);
+        }
+        if symtab.is_null() {
+            return Err(Error::InvalidElf);
+        }
+        let sym = symtab.add(sym_index);
+        if (*sym).st_shndx == SHN_UNDEF {
+            // there is no global namespace to import symbols from
+            return if (*sym).st_info >> 4 == STB_WEAK {
+                Ok(0)
+            } else {
+                Err(Error::InvalidElf)
+            };
+        }
+        Ok(base.add((*sym).st_value as usize) as u64)
+    }
+}
 
-            return None;
+/// Apply a table of relocations located at `vaddr` in the loaded image
+unsafe fn relocate(
+    base: *mut u8,
+    size: usize,
+    symtab: *const Elf64Sym,
+    vaddr: usize,
+    table_size: usize,
+) -> Result<(), Error> {
+    unsafe {
+        let count = table_size / size_of::<Elf64Rela>();
+        let end = count
+            .checked_mul(size_of::<Elf64Rela>())
+            .and_then(|len| vaddr.checked_add(len))
+            .ok_or(Error::InvalidElf)?;
+        if end > size {
+            return Err(Error::InvalidElf);
         }
+        let relocations = base.add(vaddr).cast::<Elf64Rela>();
+        for i in 0..count {
+            let r = relocations.add(i).read_unaligned();
+            let end = (r.r_offset as usize)
+                .checked_add(size_of::<u64>())
+                .ok_or(Error::InvalidElf)?;
+            if end > size {
+                return Err(Error::InvalidElf);
+            }
+            let sym_index = (r.r_info >> 32) as usize;
+            let value = match r.r_info as u32 {
+                R_RISCV_RELATIVE => base.offset(r.r_addend as isize) as u64,
+                R_RISCV_64 => {
+                    symbol_value(base, symtab, sym_index)?.wrapping_add(r.r_addend as u64)
+                }
+                R_RISCV_JUMP_SLOT => symbol_value(base, symtab, sym_index)?,
+                _ => return Err(Error::InvalidElf),
+            };
+            base.add(r.r_offset as usize)
+                .cast::<u64>()
+                .write_unaligned(value);
+        }
+        Ok(())
     }
 }
 
@@ -277,12 +684,8 @@ impl Library {
 #[repr(align(4096))]
 pub struct CKBDLContext<T>(T);
 
-#[deprecated(
-    since = "0.7.3",
-    note = "Please use the dynamic_loading_c_impl instead"
-)]
 impl<T> CKBDLContext<T> {
-    /// # Unsafe
+    /// # Safety
     ///
     /// Undefined behavior will happen if the type T is not a [u8; length]
     ///
@@ -293,14 +696,15 @@ impl<T> CKBDLContext<T> {
 
     /// Load a shared library from dep cells
     /// See module level documentation for details
-    pub fn load<'a>(&'a mut self, dep_cell_data_hash: &[u8]) -> Result<Library, Error> {
+    pub fn load(&mut self, dep_cell_data_hash: &[u8]) -> Result<Library, Error> {
         self.load_with_offset(dep_cell_data_hash, 0, size_of::<CKBDLContext<T>>())
     }
 
     /// Load a shared library from dep cells to specified buffer offset.
     /// See module level documentation for details
     ///
-    /// This function is used for loading multiple libraries.
+    /// This function is used for loading multiple libraries, see also
+    /// [`CKBDLArena`] which keeps track of the offsets.
     ///
     /// # Example
     ///
@@ -319,232 +723,378 @@ impl<T> CKBDLContext<T> {
     ///
     /// let lib3 = context.load_with_offset(&CODE_HASH_LIB_3, offset, size).expect("load shared lib");
     /// ```
-    pub fn load_with_offset<'a>(
-        &'a mut self,
+    pub fn load_with_offset(
+        &mut self,
         dep_cell_data_hash: &[u8],
         offset: usize,
         size: usize,
     ) -> Result<Library, Error> {
-        if size_of::<Library>() > RISCV_PGSIZE || size < RISCV_PGSIZE {
+        let index = find_cell_dep(dep_cell_data_hash, ScriptHashType::Data)?;
+        self.load_index_with_offset(index, offset, size)
+    }
+
+    /// Load a shared library from dep cells by code hash and hash type.
+    /// See module level documentation for details
+    ///
+    /// With `ScriptHashType::Type` the library is found by the type script
+    /// hash of the cell dep, so an upgradable library (e.g. deployed with
+    /// Type ID) can be referenced without pinning its data hash. Use
+    /// [`Library::data_hash`] to find out which code was loaded.
+    pub fn load_by(
+        &mut self,
+        code_hash: &[u8],
+        hash_type: ScriptHashType,
+    ) -> Result<Library, Error> {
+        self.load_by_with_offset(code_hash, hash_type, 0, size_of::<CKBDLContext<T>>())
+    }
+
+    /// Load a shared library from dep cells by code hash and hash type to
+    /// specified buffer offset.
+    /// See [`load_by`](CKBDLContext::load_by) and
+    /// [`load_with_offset`](CKBDLContext::load_with_offset) for details
+    pub fn load_by_with_offset(
+        &mut self,
+        code_hash: &[u8],
+        hash_type: ScriptHashType,
+        offset: usize,
+        size: usize,
+    ) -> Result<Library, Error> {
+        let index = find_cell_dep(code_hash, hash_type)?;
+        self.load_index_with_offset(index, offset, size)
+    }
+
+    /// Load a shared library from the cell dep at `index`
+    pub fn load_index(&mut self, index: usize) -> Result<Library, Error> {
+        self.load_index_with_offset(index, 0, size_of::<CKBDLContext<T>>())
+    }
+
+    /// Load a shared library from the cell dep at `index` to specified buffer
+    /// offset.
+    /// See [`load_with_offset`](CKBDLContext::load_with_offset) for details
+    pub fn load_index_with_offset(
+        &mut self,
+        index: usize,
+        offset: usize,
+        size: usize,
+    ) -> Result<Library, Error> {
+        if size < RISCV_PGSIZE || offset + size > size_of::<CKBDLContext<T>>() {
             return Err(Error::ContextFailure);
         }
 
-        // size must aligned to page size
-        if ((size >> RISCV_PGSIZE_SHIFT) << RISCV_PGSIZE_SHIFT) != size {
+        // offset and size must aligned to page size
+        if (offset | size) & (RISCV_PGSIZE - 1) != 0 {
             return Err(Error::InvalidAlign);
         }
 
+        let headers = ElfHeaders::load(index)?;
+        if headers.required_size()? > size {
+            return Err(Error::MemoryNotEnough);
+        }
+
         unsafe {
-            // initialize context
-            let aligned_size = size;
             let aligned_addr = (&mut self.0 as *mut T).cast::<u8>().add(offset);
             let mut library = Library::new();
             library.base_addr = aligned_addr;
 
-            let index = find_cell_by_data_hash(dep_cell_data_hash, Source::CellDep)?
-                .ok_or(Error::CellNotFound)?;
-
-            // Basic ELF header parsing
-            let mut hdr = Elf64Ehdr::default();
-            let len = size_of::<Elf64Ehdr>();
-            let loaded_len = {
-                let elf_hdr_ptr = &mut hdr as *mut Elf64Ehdr;
-                match load_cell_data_raw(elf_hdr_ptr.cast(), len, 0, index, Source::CellDep) {
-                    Ok(len) => len,
-                    Err(SysError::LengthNotEnough(_)) => len,
-                    Err(err) => return Err(err.into()),
+            // Load all segments
+            let mut consumed_size = 0;
+            let mut dynamic: Option<(usize, usize)> = None;
+            for ph in headers.program_hdrs() {
+                if ph.p_type as usize == PT_DYNAMIC {
+                    dynamic = Some((ph.p_vaddr as usize, ph.p_memsz as usize));
                 }
-            };
-            if loaded_len < len {
-                return Err(Error::InvalidElf);
-            }
-            if (hdr.e_phentsize as usize != size_of::<Elf64Phdr>())
-                || (hdr.e_shentsize as usize != size_of::<Elf64Shdr>())
-                || (hdr.e_phnum > 16)
-                || (hdr.e_shnum > 32)
-            {
-                return Err(Error::InvalidElf);
-            }
-
-            // Parse program headers and load relevant parts
-            let mut program_hdrs: [Elf64Phdr; 16] = Default::default();
-            let len = size_of::<Elf64Phdr>() * hdr.e_phnum as usize;
-            let loaded_len = {
-                let ptr = program_hdrs.as_mut_ptr();
-                match load_cell_data_raw(
-                    ptr.cast(),
-                    len,
-                    hdr.e_phoff as usize,
-                    index,
-                    Source::CellDep,
-                ) {
-                    Ok(len) => len,
-                    Err(SysError::LengthNotEnough(_)) => len,
-                    Err(err) => return Err(err.into()),
+                if ph.p_type as usize != PT_LOAD || ph.p_memsz == 0 {
+                    continue;
                 }
-            };
-            if loaded_len < len {
-                return Err(Error::InvalidElf);
-            }
-            let mut max_consumed_size = 0;
-            for ph in &program_hdrs[0..hdr.e_phnum as usize] {
-                if ph.p_type as usize == PT_LOAD && ph.p_memsz > 0 {
-                    if (ph.p_flags as usize & PF_X) != 0 {
-                        let prepad = ph.p_vaddr as usize % RISCV_PGSIZE;
-                        let vaddr = ph.p_vaddr as usize - prepad;
-                        let memsz = roundup_shift(prepad + ph.p_memsz as usize, RISCV_PGSIZE_SHIFT);
-                        let size = vaddr + memsz;
-                        if size > aligned_size {
-                            return Err(Error::MemoryNotEnough);
-                        }
-                        load_cell_code(
-                            aligned_addr.add(vaddr),
-                            memsz,
-                            ph.p_offset as usize,
-                            ph.p_filesz as usize,
-                            index,
-                            Source::CellDep,
-                        )?;
-                        max_consumed_size = max(max_consumed_size, vaddr + memsz);
-                    } else {
-                        let filesz = ph.p_filesz as usize;
-                        let size = ph.p_vaddr as usize + filesz;
-                        let consumed_end: usize = roundup_shift(size, RISCV_PGSIZE_SHIFT);
-                        if consumed_end > aligned_size {
-                            return Err(Error::MemoryNotEnough);
-                        }
-                        let loaded_len = match load_cell_data_raw(
-                            aligned_addr.add(ph.p_vaddr as usize),
-                            filesz,
-                            ph.p_offset as usize,
-                            index,
-                            Source::CellDep,
-                        ) {
-                            Ok(len) => len,
-                            Err(SysError::LengthNotEnough(_)) => filesz,
-                            Err(err) => return Err(err.into()),
-                        };
-                        if loaded_len < filesz {
-                            return Err(Error::InvalidElf);
-                        }
-                        max_consumed_size = max(max_consumed_size, consumed_end);
-                    }
+                if ph.p_filesz > ph.p_memsz {
+                    return Err(Error::InvalidElf);
                 }
-            }
-
-            // Parse sectioin header & relocation headers,
-            // Perform necessary relocations.
-
-            let mut section_hdrs: [Elf64Shdr; 32] = Default::default();
-            let len = size_of::<Elf64Shdr>() * hdr.e_shnum as usize;
-            let loaded_len = {
-                let ptr = section_hdrs.as_mut_ptr();
-                match load_cell_data_raw(
-                    ptr.cast(),
-                    len,
-                    hdr.e_shoff as usize,
-                    index,
-                    Source::CellDep,
-                ) {
-                    Ok(len) => len,
-                    Err(SysError::LengthNotEnough(_)) => len,
-                    Err(err) => return Err(err.into()),
+                if (ph.p_flags as usize & PF_X) != 0 {
+                    let prepad = ph.p_vaddr as usize % RISCV_PGSIZE;
+                    if (ph.p_offset as usize) < prepad {
+                        return Err(Error::InvalidElf);
+                    }
+                    let vaddr = ph.p_vaddr as usize - prepad;
+                    let memsz = roundup_shift(prepad + ph.p_memsz as usize, RISCV_PGSIZE_SHIFT);
+                    load_cell_code(
+                        aligned_addr.add(vaddr),
+                        memsz,
+                        ph.p_offset as usize - prepad,
+                        ph.p_filesz as usize + prepad,
+                        index,
+                        Source::CellDep,
+                    )?;
+                    consumed_size = max(consumed_size, vaddr + memsz);
+                } else {
+                    let vaddr = ph.p_vaddr as usize;
+                    let filesz = ph.p_filesz as usize;
+                    let memsz = ph.p_memsz as usize;
+                    load_exact(aligned_addr.add(vaddr), filesz, ph.p_offset as usize, index)?;
+                    // the buffer might be reused, .bss must be cleared explicitly
+                    aligned_addr
+                        .add(vaddr + filesz)
+                        .write_bytes(0, memsz - filesz);
+                    consumed_size = max(
+                        consumed_size,
+                        roundup_shift(vaddr + memsz, RISCV_PGSIZE_SHIFT),
+                    );
                 }
-            };
-            if loaded_len < len {
-                return Err(Error::InvalidElf);
             }
 
-            // First load shstrtab tab, this is temporary code only needed in ELF loading
-            // phase here.
-            let shshrtab = &section_hdrs[hdr.e_shstrndx as usize];
-            let mut shrtab = [0u8; 4096];
-            if shshrtab.sh_size > 4096 {
+            // Parse dynamic section
+            let (dynamic_vaddr, dynamic_size) = dynamic.ok_or(Error::InvalidElf)?;
+            if dynamic_vaddr + dynamic_size > consumed_size {
                 return Err(Error::InvalidElf);
             }
-            let shrtab_len = shshrtab.sh_size as usize;
-            let _loaded_len = {
-                let ptr = shrtab.as_mut_ptr();
-                match load_cell_data_raw(
-                    ptr.cast(),
-                    shrtab_len,
-                    shshrtab.sh_offset as usize,
-                    index,
-                    Source::CellDep,
-                ) {
-                    Ok(len) => len,
-                    Err(SysError::LengthNotEnough(_)) => len,
-                    Err(err) => return Err(err.into()),
+            let dynamic_entries = aligned_addr.add(dynamic_vaddr).cast::<Elf64Dyn>();
+            let mut rela = (0, 0);
+            let mut jmprel = (0, 0);
+            for i in 0..dynamic_size / size_of::<Elf64Dyn>() {
+                let entry = &*dynamic_entries.add(i);
+                let value = entry.d_val as usize;
+                if matches!(
+                    entry.d_tag,
+                    DT_HASH | DT_GNU_HASH | DT_STRTAB | DT_SYMTAB | DT_RELA | DT_JMPREL
+                ) && value >= consumed_size
+                {
+                    return Err(Error::InvalidElf);
                 }
-            };
-            if shrtab_len < shshrtab.sh_size as usize {
-                return Err(Error::InvalidElf);
-            }
-            for sh in &section_hdrs[0..hdr.e_shnum as usize] {
-                if sh.sh_type as usize == SHT_RELA {
-                    if sh.sh_entsize as usize != size_of::<Elf64Rela>() {
+                match entry.d_tag {
+                    DT_NULL => break,
+                    DT_HASH => library.hash = aligned_addr.add(value).cast(),
+                    DT_GNU_HASH => library.gnu_hash = aligned_addr.add(value).cast(),
+                    DT_STRTAB => library.strtab = aligned_addr.add(value),
+                    DT_SYMTAB => library.symtab = aligned_addr.add(value).cast(),
+                    DT_SYMENT if value != size_of::<Elf64Sym>() => {
                         return Err(Error::InvalidElf);
                     }
-                    let mut relocation_size = (sh.sh_size / sh.sh_entsize) as usize;
-                    let mut current_offset = sh.sh_offset as usize;
-                    while relocation_size > 0 {
-                        let mut relocations: [Elf64Rela; 64] = zeroed();
-                        let load_size = min(relocation_size, 64) as usize;
-                        let load_length = load_size * size_of::<Elf64Rela>();
-                        let loaded_len = {
-                            let ptr = relocations.as_mut_ptr();
-                            match load_cell_data_raw(
-                                ptr.cast(),
-                                load_length,
-                                current_offset,
-                                index,
-                                Source::CellDep,
-                            ) {
-                                Ok(len) => len,
-                                Err(SysError::LengthNotEnough(_)) => load_length,
-                                Err(err) => return Err(err.into()),
-                            }
-                        };
-                        if loaded_len < load_length {
-                            return Err(Error::InvalidElf);
-                        }
-                        relocation_size -= load_size;
-                        current_offset += len;
-                        for r in &relocations[0..load_size] {
-                            if r.r_info as usize != R_RISCV_RELATIVE {
-                                // Only relative relocation is supported now, we might add more
-                                // later
-                                return Err(Error::InvalidElf);
-                            }
-                            aligned_addr
-                                .add(r.r_offset as usize)
-                                .cast::<u64>()
-                                .write_unaligned(
-                                    aligned_addr.offset(r.r_addend as isize) as usize as u64
-                                );
-                        }
-                    }
-                } else if sh.sh_type as usize == SHT_DYNSYM {
-                    // We assume one ELF file only has one DYNSYM section now
-                    if sh.sh_entsize as usize != size_of::<Elf64Sym>() {
+                    DT_RELAENT if value != size_of::<Elf64Rela>() => {
                         return Err(Error::InvalidElf);
                     }
-                    library.dynsyms = aligned_addr.add(sh.sh_offset as usize).cast();
-                    library.dynsym_size = (sh.sh_size / sh.sh_entsize) as usize;
-                } else if sh.sh_type as usize == SHT_STRTAB {
-                    let s = b".dynstr";
-                    if &shrtab[sh.sh_name as usize..sh.sh_name as usize + s.len()] == s {
-                        library.dynstr = aligned_addr.add(sh.sh_offset as usize);
-                    }
+                    DT_RELA => rela.0 = value,
+                    DT_RELASZ => rela.1 = value,
+                    DT_JMPREL => jmprel.0 = value,
+                    DT_PLTRELSZ => jmprel.1 = value,
+                    _ => {}
                 }
             }
-
-            if library.dynsyms.is_null() || library.dynstr.is_null() {
+            if library.symtab.is_null()
+                || library.strtab.is_null()
+                || (library.hash.is_null() && library.gnu_hash.is_null())
+            {
                 return Err(Error::InvalidElf);
             }
-            let consumed_size = max_consumed_size + RISCV_PGSIZE;
+
+            // Perform relocations
+            for (vaddr, table_size) in [rela, jmprel] {
+                relocate(
+                    aligned_addr,
+                    consumed_size,
+                    library.symtab,
+                    vaddr,
+                    table_size,
+                )?;
+            }
+
+            load_cell_by_field(
+                &mut library.data_hash,
+                0,
+                index,
+                Source::CellDep,
+                CellField::DataHash,
+            )?;
+            library.index = index;
             library.consumed_size = consumed_size;
-            return Ok(library);
+            Ok(library)
         }
     }
 }
+
+/// Index of the cell dep matching `code_hash`
+fn find_cell_dep(code_hash: &[u8], hash_type: ScriptHashType) -> Result<usize, Error> {
+    let index = match hash_type {
+        ScriptHashType::Type => match look_for_dep_with_hash2(code_hash, hash_type) {
+            Ok(index) => Some(index),
+            Err(SysError::IndexOutOfBound) => None,
+            Err(err) => return Err(err.into()),
+        },
+        _ => find_cell_by_data_hash(code_hash, Source::CellDep)?,
+    };
+    index.ok_or(Error::CellNotFound)
+}
+
+/// Memory required in a context to load the library in the cell dep at
+/// `index`
+///
+/// Only the ELF program headers are read, nothing is loaded.
+pub fn required_size(index: usize) -> Result<usize, Error> {
+    ElfHeaders::load(index)?.required_size()
+}
+
+/// Loads libraries one after another into a dynamic loading context
+///
+/// Each library is placed at the next page aligned offset after the previous
+/// one. The memory a library requires is computed from its program headers
+/// before loading, a library that doesn't fit fails with
+/// `Error::MemoryNotEnough` and leaves the arena unchanged.
+///
+/// Memory is never given back: with the W^X memory model executable pages
+/// can't be written again, so a library that fails midway (e.g. with a
+/// malformed dynamic section) still consumes its pages.
+///
+/// # Example
+///
+/// ```
+/// let mut context = unsafe { CKBDLContext::<[u8; 64 * 1024]>::new() };
+/// let mut arena = CKBDLArena::new(&mut context);
+///
+/// let lib1 = arena.load(&CODE_HASH_LIB_1).expect("load shared lib");
+/// let lib2 = arena.load_by(&TYPE_HASH_LIB_2, ScriptHashType::Type).expect("load shared lib");
+/// debug!("{} bytes left", arena.remaining());
+/// ```
+pub struct CKBDLArena<'a, T> {
+    context: &'a mut CKBDLContext<T>,
+    offset: usize,
+}
+
+impl<'a, T> CKBDLArena<'a, T> {
+    /// Create an arena using the whole context
+    pub fn new(context: &'a mut CKBDLContext<T>) -> Self {
+        CKBDLArena { context, offset: 0 }
+    }
+
+    /// Total size of the context
+    pub fn capacity(&self) -> usize {
+        size_of::<CKBDLContext<T>>()
+    }
+
+    /// Size consumed by the libraries loaded so far
+    pub fn consumed_size(&self) -> usize {
+        self.offset
+    }
+
+    /// Size still available for libraries
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.offset
+    }
+
+    /// Load a shared library from dep cells by data hash
+    pub fn load(&mut self, dep_cell_data_hash: &[u8]) -> Result<Library, Error> {
+        self.load_by(dep_cell_data_hash, ScriptHashType::Data)
+    }
+
+    /// Load a shared library from dep cells by code hash and hash type
+    pub fn load_by(
+        &mut self,
+        code_hash: &[u8],
+        hash_type: ScriptHashType,
+    ) -> Result<Library, Error> {
+        let index = find_cell_dep(code_hash, hash_type)?;
+        self.load_index(index)
+    }
+
+    /// Load a shared library from the cell dep at `index`
+    pub fn load_index(&mut self, index: usize) -> Result<Library, Error> {
+        let size = required_size(index)?;
+        if size > self.remaining() {
+            return Err(Error::MemoryNotEnough);
+        }
+        let result = self
+            .context
+            .load_index_with_offset(index, self.offset, size);
+        self.offset += size;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::library_interface! {
+        struct SharedLib: version("shared_lib_version") == 1 {
+            plus_42: extern "C" fn(usize) -> usize,
+            get_foo: extern "C" fn() -> *const u8 = "foo",
+        }
+    }
+
+    extern "C" fn plus_42(n: usize) -> usize {
+        n + 42
+    }
+
+    extern "C" fn foo() -> *const u8 {
+        c"foo".as_ptr().cast()
+    }
+
+    static VERSION: u32 = 1;
+    static NEXT_VERSION: u32 = 2;
+
+    // Exports the symbols of its table
+    struct FakeLibrary<'a>(&'a [(&'a str, *const u8)]);
+
+    impl SymbolLookup for FakeLibrary<'_> {
+        fn lookup(&self, symbol: &[u8]) -> Option<*const u8> {
+            let symbol = symbol.strip_suffix(&[0]).unwrap_or(symbol);
+            self.0
+                .iter()
+                .find(|(name, _)| name.as_bytes() == symbol)
+                .map(|(_, ptr)| *ptr)
+        }
+    }
+
+    #[test]
+    fn resolves_library_interfaces() {
+        let lib = FakeLibrary(&[
+            ("shared_lib_version", &VERSION as *const u32 as *const u8),
+            ("plus_42", plus_42 as *const u8),
+            ("foo", foo as *const u8),
+        ]);
+        let shared_lib = unsafe { SharedLib::resolve(&lib) }.unwrap();
+        assert_eq!((shared_lib.plus_42)(13), 55);
+        assert_eq!((shared_lib.get_foo)(), foo());
+    }
+
+    #[test]
+    fn reports_all_missing_symbols() {
+        let lib = FakeLibrary(&[("shared_lib_version", &VERSION as *const u32 as *const u8)]);
+        assert_eq!(
+            unsafe { SharedLib::resolve(&lib) }.err(),
+            Some(InterfaceError::MissingSymbols(alloc::vec![
+                "plus_42", "foo"
+            ]))
+        );
+        let lib = FakeLibrary(&[
+            ("shared_lib_version", &VERSION as *const u32 as *const u8),
+            ("plus_42", plus_42 as *const u8),
+        ]);
+        assert_eq!(
+            unsafe { SharedLib::resolve(&lib) }.err(),
+            Some(InterfaceError::MissingSymbols(alloc::vec!["foo"]))
+        );
+    }
+
+    #[test]
+    fn checks_versions_first() {
+        let lib = FakeLibrary(&[("plus_42", plus_42 as *const u8)]);
+        assert_eq!(
+            unsafe { SharedLib::resolve(&lib) }.err(),
+            Some(InterfaceError::MissingVersion("shared_lib_version"))
+        );
+        let lib = FakeLibrary(&[
+            (
+                "shared_lib_version",
+                &NEXT_VERSION as *const u32 as *const u8,
+            ),
+            ("plus_42", plus_42 as *const u8),
+            ("foo", foo as *const u8),
+        ]);
+        assert_eq!(
+            unsafe { SharedLib::resolve(&lib) }.err(),
+            Some(InterfaceError::VersionMismatch {
+                expected: 1,
+                actual: 2
+            })
+        );
+        assert_eq!(check_version(&lib, "shared_lib_version", 2), Ok(()));
+    }
+}
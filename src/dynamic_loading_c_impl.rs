@@ -1,12 +1,34 @@
+//! Dynamic loading based on `ckb_dlopen2` of ckb-c-stdlib.
+//!
+//! Outside of CKB-VM a library cell is replaced with a native shared library
+//! built for the host:
+//!
+//! * with `native-simulator`, the simulator opens the native binary set for
+//!   the code hash in its running setup. Libraries loaded by cell dep index
+//!   are opened by data hash, so their binary must be set for it;
+//! * with `stub-dlopen`, the library is registered with
+//!   `register_native_library` by data hash or type hash of the cell dep.
+//!
+//! Symbols are then resolved with `dlsym`, so the same `Library` and
+//! `Symbol` API can be covered by host tests.
+
+use crate::ckb_constants::{CellField, Source};
 use crate::debug;
+use crate::dynamic_loading::SymbolLookup;
 use crate::error::SysError;
+use crate::high_level::look_for_dep_with_hash2;
+use crate::syscalls::load_cell_by_field;
 use ckb_types::core::ScriptHashType;
+use core::cell::OnceCell;
 use core::ffi::c_void;
 use core::marker::PhantomData;
 use core::mem::{size_of, zeroed};
 use core::ptr::null;
 
-#[link(name = "dl-c-impl", kind = "static")]
+// On riscv64 ckb_dlopen2 comes from ckb-c-stdlib, the native simulator
+// provides its own implementation opening the native binary of the library.
+#[cfg(any(target_arch = "riscv64", feature = "native-simulator"))]
+#[cfg_attr(target_arch = "riscv64", link(name = "dl-c-impl", kind = "static"))]
 unsafe extern "C" {
     fn ckb_dlopen2(
         dep_cell_hash: *const u8,
@@ -15,10 +37,13 @@ unsafe extern "C" {
         aligned_size: usize,
         handle: *mut *const c_void,
         consumed_size: *mut usize,
-    ) -> isize;
+    ) -> core::ffi::c_int;
     fn ckb_dlsym(handle: *const c_void, symbol: *const u8) -> usize;
 }
 
+#[cfg(not(any(target_arch = "riscv64", feature = "native-simulator")))]
+use stub::{ckb_dlopen2, ckb_dlsym};
+
 /// Dynamic loading errors
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
@@ -65,22 +90,52 @@ impl<T> core::ops::Deref for Symbol<T> {
 pub struct Library {
     handle: *const c_void,
     consumed_size: usize,
+    // hash ckb_dlopen2 opened the library with
+    code_hash: [u8; 32],
+    hash_type: ScriptHashType,
+    data_hash: OnceCell<[u8; 32]>,
+    index: OnceCell<usize>,
 }
 
 impl Library {
-    fn new() -> Self {
-        Library {
-            handle: null(),
-            consumed_size: 0,
-        }
-    }
-
     /// Library consumed size
     pub fn consumed_size(&self) -> usize {
         self.consumed_size
     }
 
-    /// # Unsafe
+    /// Data hash of the cell the library was loaded from
+    ///
+    /// For a library loaded by type hash, it's loaded on the first call.
+    pub fn data_hash(&self) -> &[u8; 32] {
+        self.data_hash.get_or_init(|| {
+            if self.hash_type != ScriptHashType::Type {
+                return self.code_hash;
+            }
+            let mut data_hash = [0u8; 32];
+            load_cell_by_field(
+                &mut data_hash,
+                0,
+                self.cell_dep_index(),
+                Source::CellDep,
+                CellField::DataHash,
+            )
+            .expect("load the data hash of the library cell");
+            data_hash
+        })
+    }
+
+    /// Index of the cell dep the library was loaded from, the first one with
+    /// its hash
+    ///
+    /// The cell deps are searched on the first call.
+    pub fn cell_dep_index(&self) -> usize {
+        *self.index.get_or_init(|| {
+            look_for_dep_with_hash2(&self.code_hash, self.hash_type)
+                .expect("find the cell dep of the library")
+        })
+    }
+
+    /// # Safety
     ///
     /// Undefined behavior will happen if the type S not match the type of symbol in the shared
     /// library
@@ -89,7 +144,7 @@ impl Library {
     pub unsafe fn get<S>(&self, symbol: &[u8]) -> Option<Symbol<S>> {
         unsafe {
             let mut s = symbol.to_vec();
-            if s.len() > 0 {
+            if !s.is_empty() {
                 if s[s.len() - 1] != 0 {
                     s.push(0);
                 }
@@ -110,6 +165,12 @@ impl Library {
     }
 }
 
+impl SymbolLookup for Library {
+    fn lookup(&self, symbol: &[u8]) -> Option<*const u8> {
+        unsafe { self.get::<usize>(symbol).map(|s| *s as *const u8) }
+    }
+}
+
 const RISCV_PGSIZE_SHIFT: usize = 12;
 const RISCV_PGSIZE: usize = 1 << RISCV_PGSIZE_SHIFT; // 4096
 
@@ -118,15 +179,58 @@ const RISCV_PGSIZE: usize = 1 << RISCV_PGSIZE_SHIFT; // 4096
 pub struct CKBDLContext<T>(T);
 
 impl<T> CKBDLContext<T> {
+    /// # Safety
+    ///
+    /// Undefined behavior will happen if the type T is not a [u8; length]
     pub unsafe fn new() -> Self {
         unsafe { zeroed() }
     }
-    pub fn load_with_offset<'a>(
-        &'a mut self,
+    pub fn load_with_offset(
+        &mut self,
         code_hash: &[u8],
         hash_type: ScriptHashType,
         offset: usize,
         size: usize,
+    ) -> Result<Library, Error> {
+        // ckb_dlopen2 reads 32 bytes, a shorter hash can't match any cell dep
+        let code_hash = code_hash.try_into().unwrap_or([0u8; 32]);
+        self.open(code_hash, hash_type, offset, size)
+    }
+    ///
+    /// load library from the cell dep at `index`
+    ///
+    /// `ckb_dlopen2` only searches cell deps by hash: when an earlier cell
+    /// dep holds the same data, that one is loaded. It's the same code, and
+    /// [`Library::cell_dep_index`] reports the index of the cell actually
+    /// loaded.
+    ///
+    pub fn load_index(&mut self, index: usize) -> Result<Library, Error> {
+        self.load_index_with_offset(index, 0, size_of::<CKBDLContext<T>>())
+    }
+    pub fn load_index_with_offset(
+        &mut self,
+        index: usize,
+        offset: usize,
+        size: usize,
+    ) -> Result<Library, Error> {
+        // ckb_dlopen2 only searches by hash, the data hash of the cell at
+        // `index` identifies the same code whatever way it was looked up
+        let mut data_hash = [0u8; 32];
+        load_cell_by_field(
+            &mut data_hash,
+            0,
+            index,
+            Source::CellDep,
+            CellField::DataHash,
+        )?;
+        self.open(data_hash, ScriptHashType::Data, offset, size)
+    }
+    fn open(
+        &mut self,
+        code_hash: [u8; 32],
+        hash_type: ScriptHashType,
+        offset: usize,
+        size: usize,
     ) -> Result<Library, Error> {
         if size_of::<Library>() > RISCV_PGSIZE || size < RISCV_PGSIZE {
             return Err(Error::ContextFailure);
@@ -142,7 +246,6 @@ impl<T> CKBDLContext<T> {
         unsafe {
             let mut handle: *const c_void = null();
             let mut consumed_size: usize = 0;
-            let mut library = Library::new();
             let aligned_size = size;
             let aligned_addr = (&mut self.0 as *mut T).cast::<u8>().add(offset);
             let code = ckb_dlopen2(
@@ -152,29 +255,271 @@ impl<T> CKBDLContext<T> {
                 aligned_size,
                 &mut handle as *mut *const c_void,
                 &mut consumed_size as *mut usize,
-            );
+            ) as isize;
             if code != 0 {
                 debug!("warning, ckb_dlopen2 return {:?}", code);
-                return Err(Error::OpenFailed(code));
+                Err(Error::OpenFailed(code))
             } else {
-                library.handle = handle;
-                library.consumed_size = consumed_size;
-                return Ok(library);
+                Ok(Library {
+                    handle,
+                    consumed_size,
+                    code_hash,
+                    hash_type,
+                    data_hash: OnceCell::new(),
+                    index: OnceCell::new(),
+                })
             }
         }
     }
     #[deprecated(since = "0.11.0", note = "Please use load_by instead")]
-    pub fn load<'a>(&'a mut self, dep_cell_data_hash: &[u8]) -> Result<Library, Error> {
+    pub fn load(&mut self, dep_cell_data_hash: &[u8]) -> Result<Library, Error> {
         self.load_by(dep_cell_data_hash, ScriptHashType::Data)
     }
     ///
     /// load library via hash type
     ///
-    pub fn load_by<'a>(
-        &'a mut self,
+    pub fn load_by(
+        &mut self,
         code_hash: &[u8],
         hash_type: ScriptHashType,
     ) -> Result<Library, Error> {
         self.load_with_offset(code_hash, hash_type, 0, size_of::<CKBDLContext<T>>())
     }
 }
+
+#[cfg(not(any(target_arch = "riscv64", feature = "native-simulator")))]
+pub use stub::register_native_library;
+
+/// `ckb_dlopen2` and `ckb_dlsym` for the stub backend: a library cell is
+/// replaced with a native shared library registered by its hash.
+#[cfg(not(any(target_arch = "riscv64", feature = "native-simulator")))]
+mod stub {
+    extern crate std;
+
+    use super::RISCV_PGSIZE;
+    use crate::ckb_constants::{CellField, Source};
+    use crate::error::SysError;
+    use crate::high_level::look_for_dep_with_hash2;
+    use crate::syscalls::{load_cell_by_field, load_cell_data};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use ckb_types::core::ScriptHashType;
+    use core::ffi::{CStr, c_int, c_void};
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    // same error codes as ckb_dlfcn.h, and the one of a missing cell dep
+    pub(super) const CKB_INDEX_OUT_OF_BOUND: c_int = 1;
+    const ERROR_MEMORY_NOT_ENOUGH: c_int = -23;
+    const ERROR_DYNAMIC_LOADING: c_int = -24;
+
+    static NATIVE_LIBRARIES: Mutex<Vec<([u8; 32], PathBuf)>> = Mutex::new(Vec::new());
+
+    /// Register the native shared library loaded in place of a library cell
+    ///
+    /// `code_hash` is either the data hash or the type hash of the cell dep,
+    /// the library at `path` must be built for the host and export the same
+    /// symbols as the on-chain one. Registering the same hash again replaces
+    /// the previous path.
+    pub fn register_native_library(code_hash: [u8; 32], path: impl Into<PathBuf>) {
+        let path = path.into();
+        let mut libraries = NATIVE_LIBRARIES.lock().unwrap();
+        match libraries.iter_mut().find(|(hash, _)| *hash == code_hash) {
+            Some(entry) => entry.1 = path,
+            None => libraries.push((code_hash, path)),
+        }
+    }
+
+    /// Find the native library registered for the cell dep at `index`
+    fn native_library(index: usize) -> Result<Option<PathBuf>, SysError> {
+        let mut hashes = [[0u8; 32]; 2];
+        load_cell_by_field(
+            &mut hashes[0],
+            0,
+            index,
+            Source::CellDep,
+            CellField::DataHash,
+        )?;
+        let has_type = match load_cell_by_field(
+            &mut hashes[1],
+            0,
+            index,
+            Source::CellDep,
+            CellField::TypeHash,
+        ) {
+            Ok(_) => true,
+            Err(SysError::ItemMissing) => false,
+            Err(err) => return Err(err),
+        };
+        let hashes = if has_type { &hashes[..] } else { &hashes[..1] };
+        let libraries = NATIVE_LIBRARIES.lock().unwrap();
+        Ok(libraries
+            .iter()
+            .find(|(hash, _)| hashes.contains(hash))
+            .map(|(_, path)| path.clone()))
+    }
+
+    pub(super) unsafe fn ckb_dlopen2(
+        dep_cell_hash: *const u8,
+        hash_type: u8,
+        _aligned_addr: *mut u8,
+        aligned_size: usize,
+        handle: *mut *const c_void,
+        consumed_size: *mut usize,
+    ) -> c_int {
+        let code_hash = unsafe { core::slice::from_raw_parts(dep_cell_hash, 32) };
+        let hash_type = match hash_type {
+            1 => ScriptHashType::Type,
+            _ => ScriptHashType::Data,
+        };
+        let index = match look_for_dep_with_hash2(code_hash, hash_type) {
+            Ok(index) => index,
+            Err(SysError::IndexOutOfBound) => return CKB_INDEX_OUT_OF_BOUND,
+            Err(_) => return ERROR_DYNAMIC_LOADING,
+        };
+        let path = match native_library(index) {
+            Ok(Some(path)) => path,
+            _ => {
+                crate::debug!("no native library registered for {:?}", code_hash);
+                return ERROR_DYNAMIC_LOADING;
+            }
+        };
+        // The native library takes no space in the context, consume as much
+        // as the on-chain one would at least need to catch sizing mistakes.
+        let length = match load_cell_data(&mut [], 0, index, Source::CellDep) {
+            Ok(len) | Err(SysError::LengthNotEnough(len)) => len,
+            Err(_) => return ERROR_DYNAMIC_LOADING,
+        };
+        let length = length.div_ceil(RISCV_PGSIZE) * RISCV_PGSIZE;
+        if length > aligned_size {
+            return ERROR_MEMORY_NOT_ENOUGH;
+        }
+        let library = match unsafe { libloading::Library::new(&path) } {
+            Ok(library) => library,
+            Err(_err) => {
+                crate::debug!("failed to load {:?}: {}", path, _err);
+                return ERROR_DYNAMIC_LOADING;
+            }
+        };
+        unsafe {
+            // libraries are never unloaded, just like on chain
+            *handle = Box::into_raw(Box::new(library)) as *const c_void;
+            *consumed_size = length;
+        }
+        0
+    }
+
+    pub(super) unsafe fn ckb_dlsym(handle: *const c_void, symbol: *const u8) -> usize {
+        unsafe {
+            let library = &*(handle as *const libloading::Library);
+            let symbol = CStr::from_ptr(symbol as *const _);
+            match library.get::<*const c_void>(symbol.to_bytes_with_nul()) {
+                Ok(ptr) => *ptr as usize,
+                Err(_) => 0,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing", not(feature = "native-simulator")))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::high_level::load_cell_type_hash;
+    use crate::testing::{Contract, MockTransaction, ScriptGroup};
+    use ckb_types::{packed::*, prelude::*};
+    use core::ffi::CStr;
+    use std::path::PathBuf;
+
+    // the library of the simulator tests, built for the host
+    fn build_shared_lib() -> PathBuf {
+        let path =
+            std::env::temp_dir().join(std::format!("ckb-std-shared-lib-{}.so", std::process::id()));
+        let status = std::process::Command::new(std::env::var("CC").unwrap_or("cc".into()))
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&path)
+            .arg(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/test/shared-lib/shared-lib.c"
+            ))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        path
+    }
+
+    fn call_shared_lib() -> i8 {
+        let type_hash = load_cell_type_hash(0, Source::CellDep).unwrap().unwrap();
+        let mut context = unsafe { CKBDLContext::<[u8; 16 * 1024]>::new() };
+        let lib = match context.load_by(&type_hash, ScriptHashType::Type) {
+            Ok(lib) => lib,
+            Err(Error::OpenFailed(code)) => return code as i8,
+            Err(_) => return 1,
+        };
+        assert_eq!(lib.cell_dep_index(), 0);
+        assert_eq!(lib.data_hash(), &crate::testing::data_hash(&[0; 5000]));
+        // the data of the cell, rounded up to pages
+        assert_eq!(lib.consumed_size(), 8192);
+        let get_foo = unsafe { lib.get::<unsafe extern "C" fn() -> *const u8>(b"foo") }.unwrap();
+        assert_eq!(unsafe { CStr::from_ptr(get_foo() as *const _) }, c"foo");
+        assert!(unsafe { lib.get::<fn()>(b"bar") }.is_none());
+
+        let mut context = unsafe { CKBDLContext::<[u8; 16 * 1024]>::new() };
+        assert_eq!(
+            context.load_by(&[9; 32], ScriptHashType::Data).err(),
+            Some(Error::OpenFailed(stub::CKB_INDEX_OUT_OF_BOUND as isize))
+        );
+        // same data as the cell dep 0, which is loaded instead
+        let lib = context.load_index(1).unwrap();
+        assert_eq!(lib.cell_dep_index(), 0);
+        let plus_42 =
+            unsafe { lib.get::<unsafe extern "C" fn(usize) -> usize>(b"plus_42") }.unwrap();
+        unsafe { plus_42(13) as i8 }
+    }
+
+    fn small_context() -> i8 {
+        let mut context = unsafe { CKBDLContext::<[u8; 4096]>::new() };
+        match context.load_index(0) {
+            Err(Error::OpenFailed(code)) => code as i8,
+            _ => 1,
+        }
+    }
+
+    #[test]
+    fn loads_native_libraries() {
+        let type_script = Script::new_builder()
+            .code_hash([7; 32].pack())
+            .hash_type(Into::<Byte>::into(ScriptHashType::Type))
+            .build();
+        let type_hash = type_script.calc_script_hash().unpack();
+        let library = CellOutput::new_builder()
+            .capacity(1000u64)
+            .type_(ScriptOpt::new_builder().set(Some(type_script)).build())
+            .build();
+        let run = |main| {
+            let contract = Contract::new("lock", main);
+            let cell = CellOutput::new_builder()
+                .capacity(1000u64)
+                .lock(contract.script(&[]))
+                .build();
+            MockTransaction::new()
+                .cell_dep(library.clone(), [0; 5000])
+                .cell_dep(CellOutput::new_builder().build(), [0; 5000])
+                .input(cell, [])
+                .run(ScriptGroup::Lock(0), &contract)
+        };
+
+        // ERROR_DYNAMIC_LOADING
+        assert_eq!(run(call_shared_lib).exit_code, -24);
+
+        let path = build_shared_lib();
+        register_native_library(type_hash, &path);
+        let result = run(call_shared_lib);
+        std::fs::remove_file(&path).unwrap();
+        assert!(!result.panicked, "{:?}", result.debug);
+        assert_eq!(result.exit_code, 13 + 42);
+        // ERROR_MEMORY_NOT_ENOUGH
+        assert_eq!(run(small_context).exit_code, -23);
+    }
+}
@@ -1,5 +1,48 @@
+/// Values a main function can return, converted to the exit code of the
+/// script
+///
+/// Implemented for `i8`, `()` (always 0) and `Result<(), E>` where `E`
+/// converts into `i8`: `Ok(())` exits with 0 and errors with their own code.
+/// `SysError` converts into codes in
+/// [`SYS_ERROR_EXIT_CODES`](crate::error::SYS_ERROR_EXIT_CODES).
+pub trait ExitCode {
+    /// Exit code of the script, 0 means success
+    fn exit_code(self) -> i8;
+}
+
+impl ExitCode for i8 {
+    fn exit_code(self) -> i8 {
+        self
+    }
+}
+
+impl ExitCode for () {
+    fn exit_code(self) -> i8 {
+        0
+    }
+}
+
+impl<E: Into<i8>> ExitCode for Result<(), E> {
+    fn exit_code(self) -> i8 {
+        match self {
+            Ok(()) => 0,
+            Err(err) => err.into(),
+        }
+    }
+}
+
 /// Define program entry point (`_start` function) and lang items (panic handler, etc.).
 ///
+/// The main function returns either an `i8` exit code or a
+/// `Result<(), E>` where `E: Into<i8>`, see [`ExitCode`](crate::entry::ExitCode).
+/// Exit codes 1 to 15 are reserved for syscall errors, see
+/// [`SYS_ERROR_EXIT_CODES`](crate::error::SYS_ERROR_EXIT_CODES).
+///
+/// Functions listed in `before` run in order before main, e.g. to initialize
+/// a logger or check the allocator. They return any [`ExitCode`](crate::entry::ExitCode)
+/// type too: the script exits with the first non-zero code without calling
+/// main.
+///
 /// # Examples
 ///
 /// Simple main function:
@@ -11,9 +54,27 @@
 ///    0
 /// }
 /// ```
+///
+/// Main function returning a `Result`, with a hook:
+///
+/// ```
+/// entry!(main, before = [init_logger]);
+///
+/// fn init_logger() {
+///     ckb_std::logger::init().unwrap();
+/// }
+///
+/// fn main() -> Result<(), Error> {
+///     let script = load_script()?;
+///     Ok(())
+/// }
+/// ```
 #[macro_export]
 macro_rules! entry {
     ($main:path) => {
+        $crate::entry!($main, before = []);
+    };
+    ($main:path, before = [$($hook:path),* $(,)?]) => {
         extern crate alloc;
 
         #[cfg(not(target_arch = "riscv64"))]
@@ -30,7 +91,13 @@ macro_rules! entry {
         ) -> i8 {
             let argv = core::slice::from_raw_parts(argv, argc as usize);
             $crate::env::set_argv(argv);
-            $main()
+            $(
+                let code = $crate::entry::ExitCode::exit_code($hook());
+                if code != 0 {
+                    return code;
+                }
+            )*
+            $crate::entry::ExitCode::exit_code($main())
         }
 
         // Use global_asm so the compiler won't insert function prologue in _start.
@@ -54,6 +121,7 @@ macro_rules! entry {
         #[panic_handler]
         fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
             $crate::debug!("{}", panic_info);
+            $crate::asserts::__report_panic(panic_info);
             $crate::syscalls::exit(unsafe { $crate::asserts::__PANIC_EXIT_CODE })
         }
     };
@@ -63,6 +131,9 @@ macro_rules! entry {
 #[macro_export]
 macro_rules! entry_simulator {
     ($main:path) => {
+        $crate::entry_simulator!($main, before = []);
+    };
+    ($main:path, before = [$($hook:path),* $(,)?]) => {
         extern crate alloc;
 
         #[unsafe(no_mangle)]
@@ -77,7 +148,13 @@ macro_rules! entry_simulator {
                 core::slice::from_raw_parts(argv, argc as usize)
             };
             $crate::env::set_argv(argv);
-            $main()
+            $(
+                let code = $crate::entry::ExitCode::exit_code($hook());
+                if code != 0 {
+                    return code;
+                }
+            )*
+            $crate::entry::ExitCode::exit_code($main())
         }
 
         #[unsafe(no_mangle)]
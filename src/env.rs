@@ -41,8 +41,18 @@ impl Arg {
     }
 }
 
+#[cfg(not(feature = "stub-syscalls"))]
 static mut ARGV: &'static [Arg] = &[];
 
+// Each native process of `syscalls::stub_processes` runs on its own thread.
+#[cfg(feature = "stub-syscalls")]
+std::thread_local! {
+    static ARGV: core::cell::Cell<&'static [Arg]> = const { core::cell::Cell::new(&[]) };
+}
+
+#[cfg(feature = "stub-syscalls")]
+extern crate std;
+
 /// Returns the arguments that this program was started with (normally passed
 /// via `exec` or ckb-debugger).
 ///
@@ -50,12 +60,22 @@ static mut ARGV: &'static [Arg] = &[];
 /// type script. That has be loaded with `load_script`.)
 #[inline]
 pub fn argv() -> &'static [Arg] {
-    unsafe { ARGV }
+    #[cfg(not(feature = "stub-syscalls"))]
+    unsafe {
+        ARGV
+    }
+    #[cfg(feature = "stub-syscalls")]
+    ARGV.with(|argv| argv.get())
 }
 
 // For native-simulator and entry!.
 #[doc(hidden)]
 #[inline]
 pub unsafe fn set_argv(argv: &'static [Arg]) {
-    unsafe { ARGV = argv }
+    #[cfg(not(feature = "stub-syscalls"))]
+    unsafe {
+        ARGV = argv
+    }
+    #[cfg(feature = "stub-syscalls")]
+    ARGV.with(|current| current.set(argv))
 }
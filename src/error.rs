@@ -1,3 +1,10 @@
+/// Exit codes reserved for syscall errors
+///
+/// A `SysError` returned from main (see [`entry!`](crate::entry)) is converted
+/// to a code in this range, contracts should use other codes for their own
+/// errors.
+pub const SYS_ERROR_EXIT_CODES: core::ops::RangeInclusive<i8> = 1..=15;
+
 /// Syscall errors
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub enum SysError {
@@ -49,3 +56,137 @@ impl SysError {
         }
     }
 }
+
+/// Exit code of a syscall error
+///
+/// The errors with a syscall error number use it as exit code (1 to 9),
+/// `TypeIDError` is 10 and `Unknown` is 11. The rest of
+/// [`SYS_ERROR_EXIT_CODES`] is reserved for future errors.
+impl From<SysError> for i8 {
+    fn from(err: SysError) -> i8 {
+        use SysError::*;
+
+        match err {
+            IndexOutOfBound => 1,
+            ItemMissing => 2,
+            LengthNotEnough(_) => 3,
+            Encoding => 4,
+            WaitFailure => 5,
+            InvalidFd => 6,
+            OtherEndClosed => 7,
+            MaxVmsSpawned => 8,
+            MaxFdsCreated => 9,
+            #[cfg(feature = "type-id")]
+            TypeIDError => 10,
+            Unknown(_) => 11,
+        }
+    }
+}
+
+/// Declare the error enum of a contract
+///
+/// Besides the given variants, the enum gets one variant per `SysError`
+/// with its exit code (`IndexOutOfBound = 1` to `MaxFdsCreated = 9`,
+/// `TypeIDError = 10` and `Unknown = 11`), and implements
+/// `From<SysError>`, `From<Self> for i8`, `Debug`, `Clone`, `Copy`, `Eq`
+/// and `PartialEq`. So `?` works on syscall results and the enum can be
+/// returned from main, see [`entry!`](crate::entry).
+///
+/// Codes of the contract's own variants must be explicit, and outside of
+/// [`SYS_ERROR_EXIT_CODES`] and 0, which is checked at compile time.
+///
+/// # Example
+///
+/// ```
+/// ckb_std::contract_error! {
+///     /// Error
+///     pub enum Error {
+///         /// Script args are invalid
+///         InvalidArgs = 16,
+///         Overflow = 17,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! contract_error {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident = $code:expr
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(i8)]
+        #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+        $vis enum $name {
+            /// `SysError::IndexOutOfBound`
+            IndexOutOfBound = 1,
+            /// `SysError::ItemMissing`
+            ItemMissing = 2,
+            /// `SysError::LengthNotEnough`
+            LengthNotEnough = 3,
+            /// `SysError::Encoding`
+            Encoding = 4,
+            /// `SysError::WaitFailure`
+            WaitFailure = 5,
+            /// `SysError::InvalidFd`
+            InvalidFd = 6,
+            /// `SysError::OtherEndClosed`
+            OtherEndClosed = 7,
+            /// `SysError::MaxVmsSpawned`
+            MaxVmsSpawned = 8,
+            /// `SysError::MaxFdsCreated`
+            MaxFdsCreated = 9,
+            /// `SysError::TypeIDError`
+            TypeIDError = 10,
+            /// `SysError::Unknown`
+            Unknown = 11,
+            $(
+                $(#[$variant_meta])*
+                $variant = $code,
+            )*
+        }
+
+        $(
+            const _: () = core::assert!(
+                $code != 0
+                    && ($code < *$crate::error::SYS_ERROR_EXIT_CODES.start()
+                        || $code > *$crate::error::SYS_ERROR_EXIT_CODES.end()),
+                concat!(
+                    "exit code of ",
+                    stringify!($name),
+                    "::",
+                    stringify!($variant),
+                    " is reserved"
+                ),
+            );
+        )*
+
+        impl From<$crate::error::SysError> for $name {
+            fn from(err: $crate::error::SysError) -> Self {
+                match i8::from(err) {
+                    1 => Self::IndexOutOfBound,
+                    2 => Self::ItemMissing,
+                    3 => Self::LengthNotEnough,
+                    4 => Self::Encoding,
+                    5 => Self::WaitFailure,
+                    6 => Self::InvalidFd,
+                    7 => Self::OtherEndClosed,
+                    8 => Self::MaxVmsSpawned,
+                    9 => Self::MaxFdsCreated,
+                    10 => Self::TypeIDError,
+                    _ => Self::Unknown,
+                }
+            }
+        }
+
+        impl From<$name> for i8 {
+            fn from(err: $name) -> i8 {
+                err as i8
+            }
+        }
+    };
+}
@@ -0,0 +1,54 @@
+/// Defines a bump allocator as global allocator
+///
+/// Memory is never freed, which makes allocating cheap in cycles. It suits
+/// short-lived scripts whose allocations fit in the heap.
+///
+/// # Example
+///
+/// ```
+/// // define global allocator, the default heap size is 520KB, as much as
+/// // both heaps of `default_alloc!`
+/// bump_alloc!()
+///
+/// // User can invoke macro with an argument to customize the heap size
+/// bump_alloc!(520 * 1024)
+///
+/// // Like `default_alloc!`, a trailing `oom = ...` exits with a dedicated code
+/// // when the heap is exhausted, see `ckb_std::allocator::OomExit`
+/// bump_alloc!(oom = OomConfig::new())
+/// bump_alloc!(520 * 1024, oom = OomConfig::new().exit_code(-100))
+/// ```
+#[macro_export]
+macro_rules! bump_alloc {
+    () => {
+        $crate::bump_alloc!({ 520 * 1024 });
+    };
+    (oom = $oom:expr) => {
+        $crate::bump_alloc!({ 520 * 1024 }, oom = $oom);
+    };
+    ($heap_size:expr, oom = $oom:expr) => {
+        $crate::bump_alloc!(@heap $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::OomExit<$crate::allocator::BumpAlloc> = unsafe {
+            $crate::allocator::OomExit::new(
+                $crate::allocator::BumpAlloc::new(_HEAP.0.as_ptr(), $heap_size),
+                $heap_size,
+                $oom,
+            )
+        };
+    };
+    (@heap $heap_size:expr) => {
+        #[repr(align(64))]
+        struct _AlignedHeap<const N: usize>([u8; N]);
+
+        static mut _HEAP: _AlignedHeap<{ $heap_size }> = _AlignedHeap([0u8; $heap_size]);
+    };
+    ($heap_size:expr) => {
+        $crate::bump_alloc!(@heap $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::BumpAlloc =
+            unsafe { $crate::allocator::BumpAlloc::new(_HEAP.0.as_ptr(), $heap_size) };
+    };
+}
@@ -16,12 +16,95 @@
 /// // The default heap size arguments are:
 /// // (fixed heap size 4KB, dynamic heap size 516KB, dynamic heap min memory block 64B)
 /// default_alloc!(4 * 1024, 516 * 1024, 64)
+///
+/// // With a leading `stats`, the allocator records the usage of both heaps,
+/// // the script prints them itself, see `ckb_std::allocator::stats`
+/// default_alloc!(stats)
+/// default_alloc!(stats, 4 * 1024, 516 * 1024, 64)
+///
+/// // With a trailing `oom = ...`, the script prints the failed request and
+/// // exits with a dedicated code when the heap is exhausted, see
+/// // `ckb_std::allocator::OomExit`. Fallible allocations exit too.
+/// default_alloc!(oom = OomConfig::new())
+/// default_alloc!(4 * 1024, 516 * 1024, 64, oom = OomConfig::new().exit_code(-100))
+/// default_alloc!(stats, 4 * 1024, 516 * 1024, 64, oom = OomConfig::new())
 /// ```
 #[macro_export]
 macro_rules! default_alloc {
     () => {
         $crate::default_alloc!({ 4 * 1024 }, { 516 * 1024 }, 64);
     };
+    (stats) => {
+        $crate::default_alloc!(stats, { 4 * 1024 }, { 516 * 1024 }, 64);
+    };
+    (oom = $oom:expr) => {
+        $crate::default_alloc!({ 4 * 1024 }, { 516 * 1024 }, 64, oom = $oom);
+    };
+    (stats, oom = $oom:expr) => {
+        $crate::default_alloc!(stats, { 4 * 1024 }, { 516 * 1024 }, 64, oom = $oom);
+    };
+    (stats, $fixed_block_heap_size:expr, $heap_size:expr, $min_block_size:expr) => {
+        $crate::default_alloc!(@heaps $fixed_block_heap_size, $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::StatsAlloc<$crate::buddy_alloc::NonThreadsafeAlloc> =
+            unsafe { $crate::default_alloc!(@stats $fixed_block_heap_size, $heap_size, $min_block_size) };
+    };
+    (stats, $fixed_block_heap_size:expr, $heap_size:expr, $min_block_size:expr, oom = $oom:expr) => {
+        $crate::default_alloc!(@heaps $fixed_block_heap_size, $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::OomExit<
+            $crate::allocator::StatsAlloc<$crate::buddy_alloc::NonThreadsafeAlloc>,
+        > = unsafe {
+            $crate::allocator::OomExit::new(
+                $crate::default_alloc!(@stats $fixed_block_heap_size, $heap_size, $min_block_size),
+                $fixed_block_heap_size + $heap_size,
+                $oom,
+            )
+        };
+    };
+    ($fixed_block_heap_size:expr, $heap_size:expr, $min_block_size:expr, oom = $oom:expr) => {
+        $crate::default_alloc!(@heaps $fixed_block_heap_size, $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::OomExit<$crate::buddy_alloc::NonThreadsafeAlloc> = unsafe {
+            $crate::allocator::OomExit::new(
+                $crate::default_alloc!(@alloc $fixed_block_heap_size, $heap_size, $min_block_size),
+                $fixed_block_heap_size + $heap_size,
+                $oom,
+            )
+        };
+    };
+    (@heaps $fixed_block_heap_size:expr, $heap_size:expr) => {
+        #[repr(align(64))]
+        struct _AlignedHeap<const N: usize>([u8; N]);
+
+        static mut _BUDDY_HEAP: _AlignedHeap<{ $heap_size }> = _AlignedHeap([0u8; $heap_size]);
+        static mut _FIXED_BLOCK_HEAP: _AlignedHeap<{ $fixed_block_heap_size }> =
+            _AlignedHeap([0u8; $fixed_block_heap_size]);
+    };
+    (@alloc $fixed_block_heap_size:expr, $heap_size:expr, $min_block_size:expr) => {{
+        let fast_param = $crate::buddy_alloc::FastAllocParam::new(
+            _FIXED_BLOCK_HEAP.0.as_ptr(),
+            $fixed_block_heap_size,
+        );
+        let buddy_param = $crate::buddy_alloc::BuddyAllocParam::new_with_zero_filled(
+            _BUDDY_HEAP.0.as_ptr(),
+            $heap_size,
+            $min_block_size,
+        );
+        $crate::buddy_alloc::NonThreadsafeAlloc::new(fast_param, buddy_param)
+    }};
+    (@stats $fixed_block_heap_size:expr, $heap_size:expr, $min_block_size:expr) => {
+        $crate::allocator::StatsAlloc::new(
+            $crate::default_alloc!(@alloc $fixed_block_heap_size, $heap_size, $min_block_size),
+            _FIXED_BLOCK_HEAP.0.as_ptr(),
+            $fixed_block_heap_size,
+            $heap_size,
+            $min_block_size,
+        )
+    };
     ($fixed_block_heap_size:expr, $heap_size:expr, $min_block_size:expr) => {
         #[repr(align(64))]
         struct _AlignedHeap<const N: usize>([u8; N]);
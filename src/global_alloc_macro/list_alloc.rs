@@ -0,0 +1,54 @@
+/// Defines a linked list allocator as global allocator
+///
+/// Freed blocks are merged with their free neighbours, which suits
+/// workloads where the buddy allocator of `default_alloc!` fragments.
+///
+/// # Example
+///
+/// ```
+/// // define global allocator, the default heap size is 520KB, as much as
+/// // both heaps of `default_alloc!`
+/// list_alloc!()
+///
+/// // User can invoke macro with an argument to customize the heap size
+/// list_alloc!(520 * 1024)
+///
+/// // Like `default_alloc!`, a trailing `oom = ...` exits with a dedicated code
+/// // when the heap is exhausted, see `ckb_std::allocator::OomExit`
+/// list_alloc!(oom = OomConfig::new())
+/// list_alloc!(520 * 1024, oom = OomConfig::new().exit_code(-100))
+/// ```
+#[macro_export]
+macro_rules! list_alloc {
+    () => {
+        $crate::list_alloc!({ 520 * 1024 });
+    };
+    (oom = $oom:expr) => {
+        $crate::list_alloc!({ 520 * 1024 }, oom = $oom);
+    };
+    ($heap_size:expr, oom = $oom:expr) => {
+        $crate::list_alloc!(@heap $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::OomExit<$crate::allocator::ListAlloc> = unsafe {
+            $crate::allocator::OomExit::new(
+                $crate::allocator::ListAlloc::new(_HEAP.0.as_ptr(), $heap_size),
+                $heap_size,
+                $oom,
+            )
+        };
+    };
+    (@heap $heap_size:expr) => {
+        #[repr(align(64))]
+        struct _AlignedHeap<const N: usize>([u8; N]);
+
+        static mut _HEAP: _AlignedHeap<{ $heap_size }> = _AlignedHeap([0u8; $heap_size]);
+    };
+    ($heap_size:expr) => {
+        $crate::list_alloc!(@heap $heap_size);
+
+        #[global_allocator]
+        static ALLOC: $crate::allocator::ListAlloc =
+            unsafe { $crate::allocator::ListAlloc::new(_HEAP.0.as_ptr(), $heap_size) };
+    };
+}
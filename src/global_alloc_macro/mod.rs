@@ -1 +1,4 @@
+pub mod bump_alloc;
 pub mod default_alloc;
+pub mod list_alloc;
+pub mod vm_memory_alloc;
@@ -0,0 +1,47 @@
+/// Defines a global allocator using the VM memory left between the loaded
+/// image and the stack
+///
+/// No heap is reserved in `.bss`: the heap starts after the binary and stops
+/// a stack reserve below the end of the VM memory, so small binaries get
+/// more heap. The start is the `_end` symbol of the linker script used, the
+/// bounds are resolved on the first allocation. A stack found below the
+/// heap on an allocation exits the script, see
+/// `ckb_std::allocator::VmMemoryAlloc`.
+///
+/// # Example
+///
+/// ```
+/// // define global allocator, 512KB are left to the stack
+/// vm_memory_alloc!()
+///
+/// // User can invoke macro with an argument to customize the stack reserve
+/// vm_memory_alloc!(256 * 1024)
+///
+/// // Like `default_alloc!`, a trailing `oom = ...` exits with a dedicated code
+/// // when the heap is exhausted, see `ckb_std::allocator::OomExit`
+/// vm_memory_alloc!(oom = OomConfig::new())
+/// vm_memory_alloc!(256 * 1024, oom = OomConfig::new().exit_code(-100))
+/// ```
+#[macro_export]
+macro_rules! vm_memory_alloc {
+    () => {
+        $crate::vm_memory_alloc!(512 * 1024);
+    };
+    (oom = $oom:expr) => {
+        $crate::vm_memory_alloc!(512 * 1024, oom = $oom);
+    };
+    ($stack_reserve:expr, oom = $oom:expr) => {
+        #[global_allocator]
+        static ALLOC: $crate::allocator::OomExit<$crate::allocator::VmMemoryAlloc> =
+            $crate::allocator::OomExit::new(
+                $crate::allocator::VmMemoryAlloc::new($stack_reserve),
+                0,
+                $oom,
+            );
+    };
+    ($stack_reserve:expr) => {
+        #[global_allocator]
+        static ALLOC: $crate::allocator::VmMemoryAlloc =
+            $crate::allocator::VmMemoryAlloc::new($stack_reserve);
+    };
+}
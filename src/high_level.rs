@@ -1,8 +1,9 @@
 use crate::ckb_constants::*;
+use crate::ctx::GLOBAL;
 use crate::error::SysError;
 use crate::syscalls;
 use alloc::{ffi::CString, string::String, vec, vec::Vec};
-use ckb_types::{core::ScriptHashType, packed::*, prelude::*};
+use ckb_types::{core::ScriptHashType, packed::*};
 use core::convert::Infallible;
 use core::ffi::CStr;
 use core::fmt::Write;
@@ -23,10 +24,7 @@ pub const BUF_SIZE: usize = 256;
 /// let tx_hash = load_tx_hash().unwrap();
 /// ```
 pub fn load_tx_hash() -> Result<[u8; 32], SysError> {
-    let mut hash = [0u8; 32];
-    let len = syscalls::load_tx_hash(&mut hash, 0)?;
-    debug_assert_eq!(hash.len(), len);
-    Ok(hash)
+    GLOBAL.load_tx_hash()
 }
 
 /// Load script hash
@@ -39,29 +37,7 @@ pub fn load_tx_hash() -> Result<[u8; 32], SysError> {
 /// let script_hash = load_script_hash().unwrap();
 /// ```
 pub fn load_script_hash() -> Result<[u8; 32], SysError> {
-    let mut hash = [0u8; 32];
-    let len = syscalls::load_script_hash(&mut hash, 0)?;
-    debug_assert_eq!(hash.len(), len);
-    Ok(hash)
-}
-
-/// Common method to fully load data from syscall
-fn load_data<F: Fn(&mut [u8], usize) -> Result<usize, SysError>>(
-    syscall: F,
-) -> Result<Vec<u8>, SysError> {
-    let mut buf = [0u8; BUF_SIZE];
-    match syscall(&mut buf, 0) {
-        Ok(len) => Ok(buf[..len].to_vec()),
-        Err(SysError::LengthNotEnough(actual_size)) => {
-            let mut data = vec![0; actual_size];
-            let loaded_len = buf.len();
-            data[..loaded_len].copy_from_slice(&buf);
-            let len = syscall(&mut data[loaded_len..], loaded_len)?;
-            debug_assert_eq!(len + loaded_len, actual_size);
-            Ok(data)
-        }
-        Err(err) => Err(err),
-    }
+    GLOBAL.load_script_hash()
 }
 
 /// Load cell
@@ -82,12 +58,7 @@ fn load_data<F: Fn(&mut [u8], usize) -> Result<usize, SysError>>(
 /// **Note:** This function can panic if the underlying data is too large,
 /// potentially causing an out-of-memory error.
 pub fn load_cell(index: usize, source: Source) -> Result<CellOutput, SysError> {
-    let data = load_data(|buf, offset| syscalls::load_cell(buf, offset, index, source))?;
-
-    match CellOutputReader::verify(&data, false) {
-        Ok(()) => Ok(CellOutput::new_unchecked(data.into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_cell(index, source)
 }
 
 /// Load input
@@ -105,13 +76,7 @@ pub fn load_cell(index: usize, source: Source) -> Result<CellOutput, SysError> {
 /// let input = load_input(0, Source::Input).unwrap();
 /// ```
 pub fn load_input(index: usize, source: Source) -> Result<CellInput, SysError> {
-    let mut data = [0u8; CellInput::TOTAL_SIZE];
-    syscalls::load_input(&mut data, 0, index, source)?;
-
-    match CellInputReader::verify(&data, false) {
-        Ok(()) => Ok(CellInput::new_unchecked(data.to_vec().into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_input(index, source)
 }
 
 /// Load header
@@ -129,13 +94,7 @@ pub fn load_input(index: usize, source: Source) -> Result<CellInput, SysError> {
 /// let header = load_header(0, Source::HeaderDep).unwrap();
 /// ```
 pub fn load_header(index: usize, source: Source) -> Result<Header, SysError> {
-    let mut data = [0u8; Header::TOTAL_SIZE];
-    syscalls::load_header(&mut data, 0, index, source)?;
-
-    match HeaderReader::verify(&data, false) {
-        Ok(()) => Ok(Header::new_unchecked(data.to_vec().into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_header(index, source)
 }
 
 /// Load witness
@@ -156,7 +115,7 @@ pub fn load_header(index: usize, source: Source) -> Result<Header, SysError> {
 /// **Note:** This function can panic if the underlying data is too large,
 /// potentially causing an out-of-memory error.
 pub fn load_witness(index: usize, source: Source) -> Result<Vec<u8>, SysError> {
-    load_data(|buf, offset| syscalls::load_witness(buf, offset, index, source))
+    GLOBAL.load_witness(index, source)
 }
 
 /// Load witness args
@@ -177,12 +136,7 @@ pub fn load_witness(index: usize, source: Source) -> Result<Vec<u8>, SysError> {
 /// **Note:** This function can panic if the underlying data is too large,
 /// potentially causing an out-of-memory error.
 pub fn load_witness_args(index: usize, source: Source) -> Result<WitnessArgs, SysError> {
-    let data = load_data(|buf, offset| syscalls::load_witness(buf, offset, index, source))?;
-
-    match WitnessArgsReader::verify(&data, false) {
-        Ok(()) => Ok(WitnessArgs::new_unchecked(data.into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_witness_args(index, source)
 }
 
 /// Load transaction
@@ -198,12 +152,7 @@ pub fn load_witness_args(index: usize, source: Source) -> Result<WitnessArgs, Sy
 /// **Note:** This function can panic if the underlying data is too large,
 /// potentially causing an out-of-memory error.
 pub fn load_transaction() -> Result<Transaction, SysError> {
-    let data = load_data(|buf, offset| syscalls::load_transaction(buf, offset))?;
-
-    match TransactionReader::verify(&data, false) {
-        Ok(()) => Ok(Transaction::new_unchecked(data.into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_transaction()
 }
 
 /// Load cell capacity
@@ -221,10 +170,7 @@ pub fn load_transaction() -> Result<Transaction, SysError> {
 /// let capacity = syscalls::load_cell_capacity(index, source).unwrap();
 /// ```
 pub fn load_cell_capacity(index: usize, source: Source) -> Result<u64, SysError> {
-    let mut buf = [0u8; 8];
-    let len = syscalls::load_cell_by_field(&mut buf, 0, index, source, CellField::Capacity)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(u64::from_le_bytes(buf))
+    GLOBAL.load_cell_capacity(index, source)
 }
 
 /// Load cell occupied capacity
@@ -240,11 +186,7 @@ pub fn load_cell_capacity(index: usize, source: Source) -> Result<u64, SysError>
 /// let occupied_capacity = load_cell_occupied_capacity(index, source).unwrap();
 /// ```
 pub fn load_cell_occupied_capacity(index: usize, source: Source) -> Result<u64, SysError> {
-    let mut buf = [0u8; 8];
-    let len =
-        syscalls::load_cell_by_field(&mut buf, 0, index, source, CellField::OccupiedCapacity)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(u64::from_le_bytes(buf))
+    GLOBAL.load_cell_occupied_capacity(index, source)
 }
 
 /// Load cell data hash
@@ -260,10 +202,7 @@ pub fn load_cell_occupied_capacity(index: usize, source: Source) -> Result<u64,
 /// let data_hash = load_cell_data_hash(index, source).unwrap();
 /// ```
 pub fn load_cell_data_hash(index: usize, source: Source) -> Result<[u8; 32], SysError> {
-    let mut buf = [0u8; 32];
-    let len = syscalls::load_cell_by_field(&mut buf, 0, index, source, CellField::DataHash)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(buf)
+    GLOBAL.load_cell_data_hash(index, source)
 }
 
 /// Load cell lock hash
@@ -279,10 +218,7 @@ pub fn load_cell_data_hash(index: usize, source: Source) -> Result<[u8; 32], Sys
 /// let lock_hash = load_cell_lock_hash(index, source).unwrap();
 /// ```
 pub fn load_cell_lock_hash(index: usize, source: Source) -> Result<[u8; 32], SysError> {
-    let mut buf = [0u8; 32];
-    let len = syscalls::load_cell_by_field(&mut buf, 0, index, source, CellField::LockHash)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(buf)
+    GLOBAL.load_cell_lock_hash(index, source)
 }
 
 /// Load cell type hash
@@ -300,15 +236,7 @@ pub fn load_cell_lock_hash(index: usize, source: Source) -> Result<[u8; 32], Sys
 /// let type_hash = load_cell_type_hash(index, source).unwrap().unwrap();
 /// ```
 pub fn load_cell_type_hash(index: usize, source: Source) -> Result<Option<[u8; 32]>, SysError> {
-    let mut buf = [0u8; 32];
-    match syscalls::load_cell_by_field(&mut buf, 0, index, source, CellField::TypeHash) {
-        Ok(len) => {
-            debug_assert_eq!(len, buf.len());
-            Ok(Some(buf))
-        }
-        Err(SysError::ItemMissing) => Ok(None),
-        Err(err) => Err(err),
-    }
+    GLOBAL.load_cell_type_hash(index, source)
 }
 
 /// Load cell lock
@@ -326,14 +254,7 @@ pub fn load_cell_type_hash(index: usize, source: Source) -> Result<Option<[u8; 3
 /// let lock = load_cell_lock(index, source).unwrap();
 /// ```
 pub fn load_cell_lock(index: usize, source: Source) -> Result<Script, SysError> {
-    let data = load_data(|buf, offset| {
-        syscalls::load_cell_by_field(buf, offset, index, source, CellField::Lock)
-    })?;
-
-    match ScriptReader::verify(&data, false) {
-        Ok(()) => Ok(Script::new_unchecked(data.into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_cell_lock(index, source)
 }
 
 /// Load cell type
@@ -351,18 +272,7 @@ pub fn load_cell_lock(index: usize, source: Source) -> Result<Script, SysError>
 /// let type_script = load_cell_type(index, source).unwrap().unwrap();
 /// ```
 pub fn load_cell_type(index: usize, source: Source) -> Result<Option<Script>, SysError> {
-    let data = match load_data(|buf, offset| {
-        syscalls::load_cell_by_field(buf, offset, index, source, CellField::Type)
-    }) {
-        Ok(data) => data,
-        Err(SysError::ItemMissing) => return Ok(None),
-        Err(err) => return Err(err),
-    };
-
-    match ScriptReader::verify(&data, false) {
-        Ok(()) => Ok(Some(Script::new_unchecked(data.into()))),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_cell_type(index, source)
 }
 
 // Load header epoch number
@@ -378,10 +288,7 @@ pub fn load_cell_type(index: usize, source: Source) -> Result<Option<Script>, Sy
 /// let epoch_number = load_header_epoch_number(index, source).unwrap();
 /// ```
 pub fn load_header_epoch_number(index: usize, source: Source) -> Result<u64, SysError> {
-    let mut buf = [0u8; 8];
-    let len = syscalls::load_header_by_field(&mut buf, 0, index, source, HeaderField::EpochNumber)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(u64::from_le_bytes(buf))
+    GLOBAL.load_header_epoch_number(index, source)
 }
 
 /// Load header epoch start block number
@@ -397,16 +304,7 @@ pub fn load_header_epoch_number(index: usize, source: Source) -> Result<u64, Sys
 /// let epoch_start_block_number = load_header_epoch_start_block_number(index, source).unwrap();
 /// ```
 pub fn load_header_epoch_start_block_number(index: usize, source: Source) -> Result<u64, SysError> {
-    let mut buf = [0u8; 8];
-    let len = syscalls::load_header_by_field(
-        &mut buf,
-        0,
-        index,
-        source,
-        HeaderField::EpochStartBlockNumber,
-    )?;
-    debug_assert_eq!(len, buf.len());
-    Ok(u64::from_le_bytes(buf))
+    GLOBAL.load_header_epoch_start_block_number(index, source)
 }
 
 /// Load header epoch length
@@ -422,10 +320,7 @@ pub fn load_header_epoch_start_block_number(index: usize, source: Source) -> Res
 /// let epoch_length = load_header_epoch_length(index, source).unwrap();
 /// ```
 pub fn load_header_epoch_length(index: usize, source: Source) -> Result<u64, SysError> {
-    let mut buf = [0u8; 8];
-    let len = syscalls::load_header_by_field(&mut buf, 0, index, source, HeaderField::EpochLength)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(u64::from_le_bytes(buf))
+    GLOBAL.load_header_epoch_length(index, source)
 }
 
 /// Load input since
@@ -441,10 +336,7 @@ pub fn load_header_epoch_length(index: usize, source: Source) -> Result<u64, Sys
 /// let since = load_input_since(index, source).unwrap();
 /// ```
 pub fn load_input_since(index: usize, source: Source) -> Result<u64, SysError> {
-    let mut buf = [0u8; 8];
-    let len = syscalls::load_input_by_field(&mut buf, 0, index, source, InputField::Since)?;
-    debug_assert_eq!(len, buf.len());
-    Ok(u64::from_le_bytes(buf))
+    GLOBAL.load_input_since(index, source)
 }
 
 /// Load input out point
@@ -460,13 +352,7 @@ pub fn load_input_since(index: usize, source: Source) -> Result<u64, SysError> {
 /// let out_point = load_input_out_point(index, source).unwrap();
 /// ```
 pub fn load_input_out_point(index: usize, source: Source) -> Result<OutPoint, SysError> {
-    let mut data = [0u8; OutPoint::TOTAL_SIZE];
-    syscalls::load_input_by_field(&mut data, 0, index, source, InputField::OutPoint)?;
-
-    match OutPointReader::verify(&data, false) {
-        Ok(()) => Ok(OutPoint::new_unchecked(data.to_vec().into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_input_out_point(index, source)
 }
 
 /// Load cell data
@@ -485,7 +371,137 @@ pub fn load_input_out_point(index: usize, source: Source) -> Result<OutPoint, Sy
 /// **Note:** This function can panic if the underlying data is too large,
 /// potentially causing an out-of-memory error.
 pub fn load_cell_data(index: usize, source: Source) -> Result<Vec<u8>, SysError> {
-    load_data(|buf, offset| syscalls::load_cell_data(buf, offset, index, source))
+    GLOBAL.load_cell_data(index, source)
+}
+
+/// Fixed capacity buffer holding up to `N` bytes, returned by the
+/// allocation-free loaders such as [`load_cell_data_into`]
+///
+/// It dereferences to the loaded bytes.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ArrayBuf<const N: usize> {
+    data: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> ArrayBuf<N> {
+    /// An empty buffer
+    pub const fn new() -> Self {
+        ArrayBuf {
+            data: [0u8; N],
+            len: 0,
+        }
+    }
+
+    /// Maximum number of bytes
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Loaded bytes
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    // `f` loads like a syscall, failing with `LengthNotEnough` if the data
+    // doesn't fit
+    pub(crate) fn load_with<F: FnOnce(&mut [u8]) -> Result<usize, SysError>>(
+        f: F,
+    ) -> Result<Self, SysError> {
+        let mut buf = Self::new();
+        buf.len = f(&mut buf.data)?;
+        Ok(buf)
+    }
+}
+
+impl<const N: usize> Default for ArrayBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::ops::Deref for ArrayBuf<N> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for ArrayBuf<N> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for ArrayBuf<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&Hex(self.as_slice()), f)
+    }
+}
+
+/// Load cell data without allocating
+///
+/// Return the data, or `SysError::LengthNotEnough` with the data length if
+/// it's longer than `N`
+///
+/// # Arguments
+///
+/// * `index` - index
+/// * `source` - source
+///
+/// # Example
+///
+/// ```
+/// let data = load_cell_data_into::<16>(0, Source::GroupInput)?;
+/// let amount = u128::from_le_bytes(data[..].try_into().unwrap());
+/// ```
+pub fn load_cell_data_into<const N: usize>(
+    index: usize,
+    source: Source,
+) -> Result<ArrayBuf<N>, SysError> {
+    GLOBAL.load_cell_data_into(index, source)
+}
+
+/// Load the args of the current script without allocating
+///
+/// Only the args are loaded, at the offset given by the script header.
+/// Return the args, or `SysError::LengthNotEnough` with the args length if
+/// they're longer than `N`
+///
+/// # Example
+///
+/// ```
+/// let args = load_script_args_into::<20>()?;
+/// ```
+pub fn load_script_args_into<const N: usize>() -> Result<ArrayBuf<N>, SysError> {
+    GLOBAL.load_script_args_into()
+}
+
+/// Load witness args into `buf` without allocating
+///
+/// Return a reader borrowing `buf`, `SysError::LengthNotEnough` with the
+/// witness length if `buf` is too small, or `SysError::Encoding` if the
+/// witness isn't a `WitnessArgs`
+///
+/// # Arguments
+///
+/// * `buf` - buffer receiving the witness
+/// * `index` - index
+/// * `source` - source
+///
+/// # Example
+///
+/// ```
+/// let mut buf = [0u8; 1024];
+/// let witness_args = load_witness_args_into(&mut buf, 0, Source::GroupInput)?;
+/// let lock = witness_args.lock().to_opt().ok_or(Error::Encoding)?.raw_data();
+/// ```
+pub fn load_witness_args_into(
+    buf: &mut [u8],
+    index: usize,
+    source: Source,
+) -> Result<WitnessArgsReader<'_>, SysError> {
+    GLOBAL.load_witness_args_into(buf, index, source)
 }
 
 /// Load script
@@ -496,12 +512,7 @@ pub fn load_cell_data(index: usize, source: Source) -> Result<Vec<u8>, SysError>
 /// let script = load_script().unwrap();
 /// ```
 pub fn load_script() -> Result<Script, SysError> {
-    let data = load_data(|buf, offset| syscalls::load_script(buf, offset))?;
-
-    match ScriptReader::verify(&data, false) {
-        Ok(()) => Ok(Script::new_unchecked(data.into())),
-        Err(_err) => Err(SysError::Encoding),
-    }
+    GLOBAL.load_script()
 }
 
 /// QueryIter
@@ -578,19 +589,7 @@ impl<T, F: Fn(usize, Source) -> Result<T, SysError>> Iterator for QueryIter<F> {
 /// return the index of the first cell we found, otherwise return None.
 ///
 pub fn find_cell_by_data_hash(data_hash: &[u8], source: Source) -> Result<Option<usize>, SysError> {
-    let mut buf = [0u8; 32];
-    for i in 0.. {
-        let len = match syscalls::load_cell_by_field(&mut buf, 0, i, source, CellField::DataHash) {
-            Ok(len) => len,
-            Err(SysError::IndexOutOfBound) => break,
-            Err(err) => return Err(err),
-        };
-        debug_assert_eq!(len, buf.len());
-        if data_hash == &buf[..] {
-            return Ok(Some(i));
-        }
-    }
-    Ok(None)
+    GLOBAL.find_cell_by_data_hash(data_hash, source)
 }
 
 /// Look for a dep cell with specific code hash, code_hash should be a buffer
@@ -600,53 +599,108 @@ pub fn look_for_dep_with_hash2(
     code_hash: &[u8],
     hash_type: ScriptHashType,
 ) -> Result<usize, SysError> {
-    let field = match hash_type {
-        ScriptHashType::Type => CellField::TypeHash,
-        _ => CellField::DataHash,
-    };
-    let mut current: usize = 0;
-    loop {
-        let mut buf = [0u8; 32];
-        match syscalls::load_cell_by_field(&mut buf, 0, current, Source::CellDep, field) {
-            Ok(len) => {
-                debug_assert_eq!(len, buf.len());
-                if buf == code_hash {
-                    return Ok(current);
-                }
-            }
-            Err(SysError::ItemMissing) => {}
-            Err(SysError::IndexOutOfBound) => {
-                return Err(SysError::IndexOutOfBound);
-            }
-            Err(err) => {
-                return Err(err);
-            }
-        }
-        current += 1;
-    }
+    GLOBAL.look_for_dep_with_hash2(code_hash, hash_type)
 }
 
 pub fn look_for_dep_with_data_hash(data_hash: &[u8]) -> Result<usize, SysError> {
-    look_for_dep_with_hash2(data_hash, ScriptHashType::Data)
+    GLOBAL.look_for_dep_with_data_hash(data_hash)
 }
 
+/// Encode `data` as a lowercase hex C string
 pub fn encode_hex(data: &[u8]) -> CString {
     let mut s = String::with_capacity(data.len() * 2);
-    for &b in data {
-        write!(&mut s, "{:02x}", b).unwrap();
-    }
+    write!(&mut s, "{}", Hex(data)).unwrap();
     CString::new(s).unwrap()
 }
 
+/// Decode a hex C string, `SysError::Encoding` is returned on odd length or
+/// any non hex character
 pub fn decode_hex(data: &CStr) -> Result<Vec<u8>, SysError> {
-    let data = data.to_str().unwrap();
-    if data.len() & 1 != 0 {
+    let data = data.to_bytes();
+    let mut buf = vec![0u8; data.len() / 2];
+    decode_hex_into(data, &mut buf)?;
+    Ok(buf)
+}
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+/// Encode `data` as lowercase hex into `buf` without allocating
+///
+/// Return the encoded string, or `SysError::LengthNotEnough` with the required
+/// length if `buf` is too small.
+///
+/// # Example
+///
+/// ```
+/// let mut buf = [0u8; 64];
+/// let hex = encode_hex_into(&load_tx_hash()?, &mut buf)?;
+/// ```
+pub fn encode_hex_into<'a>(data: &[u8], buf: &'a mut [u8]) -> Result<&'a str, SysError> {
+    let len = data.len() * 2;
+    if buf.len() < len {
+        return Err(SysError::LengthNotEnough(len));
+    }
+    for (i, b) in data.iter().enumerate() {
+        buf[i * 2] = HEX_CHARS[(b >> 4) as usize];
+        buf[i * 2 + 1] = HEX_CHARS[(b & 0xf) as usize];
+    }
+    // only ascii characters are written
+    Ok(core::str::from_utf8(&buf[..len]).unwrap())
+}
+
+fn hex_value(c: u8) -> Result<u8, SysError> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(SysError::Encoding),
+    }
+}
+
+/// Decode hex (upper or lower case) into `buf` without allocating
+///
+/// Return the decoded length. `SysError::Encoding` is returned on odd length
+/// or any non hex character, `SysError::LengthNotEnough` with the required
+/// length if `buf` is too small.
+pub fn decode_hex_into(hex: &[u8], buf: &mut [u8]) -> Result<usize, SysError> {
+    if hex.len() & 1 != 0 {
         return Err(SysError::Encoding);
     }
-    (0..data.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&data[i..i + 2], 16).map_err(|_| SysError::Encoding))
-        .collect()
+    let len = hex.len() / 2;
+    if buf.len() < len {
+        return Err(SysError::LengthNotEnough(len));
+    }
+    for (i, pair) in hex.chunks_exact(2).enumerate() {
+        buf[i] = (hex_value(pair[0])? << 4) | hex_value(pair[1])?;
+    }
+    Ok(len)
+}
+
+/// Display bytes as lowercase hex without allocating
+///
+/// # Example
+///
+/// ```
+/// let tx_hash = load_tx_hash()?;
+/// debug!("tx hash: {}", Hex(&tx_hash));
+/// ```
+#[derive(Clone, Copy)]
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl core::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut buf = [0u8; 64];
+        for chunk in self.0.chunks(buf.len() / 2) {
+            f.write_str(encode_hex_into(chunk, &mut buf).map_err(|_| core::fmt::Error)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Debug for Hex<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "0x{}", self)
+    }
 }
 
 /// Exec a cell in cell dep.
@@ -3,10 +3,17 @@
 //! # Modules
 //!
 //! * `high_level` module: defines high level syscall API
+//! * `ctx` module: high level syscall API over an explicit syscall implementation
+//! * `testing` module: runs scripts against mock transactions on the stub or native-simulator backend
+//! * `process` module: spawns and wires up multiple child processes
 //! * `syscalls` module: defines low level [CKB syscalls](https://github.com/nervosnetwork/rfcs/blob/master/rfcs/0009-vm-syscalls/0009-vm-syscalls.md)
 //! * `debug!` macro: a `println!` like macro helps debugging
+//! * `debug_kv!` macro: debug output as `key=value` pairs
 //! * `entry!` macro: defines contract entry point
 //! * `default_alloc!` and `libc_alloc!` macro: defines global allocator for no-std rust
+//! * `bump_alloc!` and `list_alloc!` macro: define a bump or a linked list global allocator
+//! * `vm_memory_alloc!` macro: defines a global allocator on the VM memory between the binary and the stack
+//! * `allocator` module: allocators behind the macros and heap usage statistics of `default_alloc!(stats)`
 
 #![cfg_attr(not(feature = "native-simulator"), no_std)]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
@@ -15,6 +22,8 @@ extern crate alloc;
 
 pub mod asserts;
 pub mod ckb_constants;
+#[cfg(feature = "ckb-types")]
+pub mod ctx;
 #[doc(hidden)]
 pub mod debug;
 #[doc(hidden)]
@@ -25,14 +34,35 @@ pub mod error;
 pub mod global_alloc_macro;
 #[cfg(feature = "ckb-types")]
 pub mod high_level;
+#[cfg(feature = "panic-report")]
+pub mod panic_report;
+#[cfg(feature = "ckb-types")]
+pub mod process;
 pub mod since;
 pub mod syscalls;
+#[cfg(all(
+    feature = "testing",
+    any(feature = "stub-syscalls", feature = "native-simulator")
+))]
+pub mod testing;
 
 #[cfg(feature = "ckb-types")]
 pub use ckb_types;
+// used by the macros, so that callers don't need `extern crate alloc`
+#[doc(hidden)]
+pub extern crate alloc as __alloc;
+#[cfg(feature = "allocator")]
+pub mod allocator;
 #[cfg(feature = "ckb-types")]
 pub mod dynamic_loading;
-#[cfg(all(target_arch = "riscv64", feature = "dlopen-c"))]
+#[cfg(all(
+    feature = "dlopen-c",
+    any(
+        target_arch = "riscv64",
+        feature = "native-simulator",
+        feature = "stub-dlopen"
+    )
+))]
 pub mod dynamic_loading_c_impl;
 #[cfg(feature = "allocator")]
 pub use buddy_alloc;
@@ -1,59 +1,142 @@
+//! A logger printing records with `syscalls::debug`.
+//!
+//! Records can be stripped at compile time with the `log-max-level-*` and
+//! `log-release-max-level-*` features (e.g. `log-release-max-level-warn`),
+//! which forward to the `max_level_*` features of the `log` crate: the
+//! formatting code of disabled levels is then left out of the binary.
 extern crate alloc;
 
 use crate::syscalls;
 use alloc::format;
+use core::sync::atomic::{AtomicU8, Ordering};
 use log::{Level, Metadata, Record};
 use log::{LevelFilter, SetLoggerError};
 
+/// How the source location of a record is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Path as given by the compiler, e.g. `contracts/foo/src/entry.rs:42`
+    Full,
+    /// File name only, e.g. `entry.rs:42`
+    FileName,
+    /// No location
+    Off,
+}
+
+/// Logger configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    level: LevelFilter,
+    color: bool,
+    path_style: PathStyle,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
+}
+
+impl Config {
+    /// All levels, colored, with full paths, which is what [`init`] uses
+    pub const fn new() -> Self {
+        Config {
+            level: LevelFilter::Trace,
+            color: true,
+            path_style: PathStyle::Full,
+        }
+    }
+
+    /// Records above `level` are discarded
+    pub const fn level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Color levels with ANSI escape codes
+    pub const fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// How source locations are printed
+    pub const fn path_style(mut self, path_style: PathStyle) -> Self {
+        self.path_style = path_style;
+        self
+    }
+}
+
 struct SimpleLogger;
 
+// Color and path style of the installed logger, the level is kept by the
+// `log` crate. Only written by the `init_with_config` call installing the
+// logger, records logged before it's stored use the default style.
+static STYLE: AtomicU8 = AtomicU8::new(encode_style(Config::new()));
+
+const fn encode_style(config: Config) -> u8 {
+    let path_style = match config.path_style {
+        PathStyle::Full => 0,
+        PathStyle::FileName => 1,
+        PathStyle::Off => 2,
+    };
+    (path_style << 1) | config.color as u8
+}
+
+fn config() -> Config {
+    let style = STYLE.load(Ordering::Relaxed);
+    let path_style = match style >> 1 {
+        0 => PathStyle::Full,
+        1 => PathStyle::FileName,
+        _ => PathStyle::Off,
+    };
+    Config::new()
+        .level(log::max_level())
+        .color(style & 1 == 1)
+        .path_style(path_style)
+}
+
 impl log::Log for SimpleLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
         let metadata = record.metadata();
-        if self.enabled(metadata) {
-            let level = metadata.level();
-            match level {
-                Level::Error => syscalls::debug(format!(
-                    "[\x1b[31m{}\x1b[0m {}:{}] {}",
-                    record.level(),
-                    record.file().unwrap_or("???"),
-                    record.line().unwrap_or(0),
-                    record.args()
-                )),
-                Level::Warn => syscalls::debug(format!(
-                    "[\x1b[33m{}\x1b[0m  {}:{}] {}",
-                    record.level(),
-                    record.file().unwrap_or("???"),
-                    record.line().unwrap_or(0),
-                    record.args()
-                )),
-                Level::Info => syscalls::debug(format!(
-                    "[\x1b[32m{}\x1b[0m  {}:{}] {}",
-                    record.level(),
-                    record.file().unwrap_or("???"),
-                    record.line().unwrap_or(0),
-                    record.args()
-                )),
-                Level::Debug => syscalls::debug(format!(
-                    "[\x1b[34m{}\x1b[0m {}:{}] {}",
-                    record.level(),
-                    record.file().unwrap_or("???"),
-                    record.line().unwrap_or(0),
-                    record.args()
-                )),
-                Level::Trace => syscalls::debug(format!(
-                    "[\x1b[36m{}\x1b[0m {}:{}] {}",
-                    record.level(),
-                    record.file().unwrap_or("???"),
-                    record.line().unwrap_or(0),
-                    record.args()
-                )),
-            }
+        if !self.enabled(metadata) {
+            return;
         }
+        let config = config();
+        let level = metadata.level();
+        // pad to the longest level name
+        let padding = if level.as_str().len() < 5 { " " } else { "" };
+        let level = if config.color {
+            let color = match level {
+                Level::Error => 31,
+                Level::Warn => 33,
+                Level::Info => 32,
+                Level::Debug => 34,
+                Level::Trace => 36,
+            };
+            format!("\x1b[{}m{}\x1b[0m{}", color, level, padding)
+        } else {
+            format!("{}{}", level, padding)
+        };
+        let file = record.file().unwrap_or("???");
+        let file = match config.path_style {
+            PathStyle::Full => file,
+            PathStyle::FileName => file.rsplit(['/', '\\']).next().unwrap_or(file),
+            PathStyle::Off => {
+                syscalls::debug(format!("[{}] {}", level, record.args()));
+                return;
+            }
+        };
+        syscalls::debug(format!(
+            "[{} {}:{}] {}",
+            level,
+            file,
+            record.line().unwrap_or(0),
+            record.args()
+        ));
     }
 
     fn flush(&self) {}
@@ -61,6 +144,80 @@ impl log::Log for SimpleLogger {
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
+/// Initialize the logger with all levels enabled
 pub fn init() -> Result<(), SetLoggerError> {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Trace))
+    init_with_config(Config::new())
+}
+
+/// Initialize the logger, records above `level` are discarded
+///
+/// The level can be changed later with `log::set_max_level`.
+pub fn init_with_level(level: LevelFilter) -> Result<(), SetLoggerError> {
+    init_with_config(Config::new().level(level))
+}
+
+/// Initialize the logger with a configuration
+///
+/// # Example
+///
+/// ```
+/// use ckb_std::logger::{self, Config, PathStyle};
+///
+/// logger::init_with_config(
+///     Config::new()
+///         .level(log::LevelFilter::Info)
+///         .color(false)
+///         .path_style(PathStyle::FileName),
+/// )
+/// .unwrap();
+/// ```
+pub fn init_with_config(config: Config) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER).map(|()| {
+        STYLE.store(encode_style(config), Ordering::Relaxed);
+        log::set_max_level(config.level)
+    })
+}
+
+#[cfg(all(test, feature = "stub-syscalls"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::syscalls::{traits::SyscallImpls, with_impls};
+    use alloc::{string::String, vec::Vec};
+    use core::ffi::CStr;
+    use std::sync::{Arc, Mutex};
+
+    struct Captured(Arc<Mutex<Vec<String>>>);
+
+    impl SyscallImpls for Captured {
+        fn debug(&self, s: &CStr) {
+            let message = s.to_string_lossy().into_owned();
+            self.0.lock().unwrap().push(message);
+        }
+    }
+
+    // the logger is global, so this is the only test installing it
+    #[test]
+    fn init_with_level_filters_records() {
+        init_with_level(LevelFilter::Info).unwrap();
+        // the logger can't be installed twice, nor its style changed
+        assert!(init_with_config(Config::new().color(false)).is_err());
+        assert_eq!(log::max_level(), LevelFilter::Info);
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        with_impls(Captured(captured.clone()), || {
+            log::info!("kept");
+            log::debug!("dropped");
+            log::set_max_level(LevelFilter::Debug);
+            log::debug!("kept too");
+            log::trace!("dropped");
+        });
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 2);
+        assert!(captured[0].starts_with("[\x1b[32mINFO\x1b[0m  src/logger.rs:"));
+        assert!(captured[0].ends_with("] kept"));
+        assert!(captured[1].starts_with("[\x1b[34mDEBUG\x1b[0m src/logger.rs:"));
+        assert!(captured[1].ends_with("] kept too"));
+    }
 }
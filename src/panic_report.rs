@@ -0,0 +1,425 @@
+//! Compact panic reports (feature `panic-report`).
+//!
+//! The panic handler defined by [`entry!`](crate::entry) prints the full
+//! `PanicInfo` with `debug!`, which is compiled out of release builds. With
+//! this feature it also emits one line through `syscalls::debug`, even in
+//! release builds:
+//!
+//! ```text
+//! panic file=6c1e2b4a line=42 code=-1
+//! ```
+//!
+//! `file` is the FNV-1a hash of the source path (as `core::panic::Location`
+//! reports it), `line` the line number and `code` the exit code of the
+//! script, see [`asserts`](crate::asserts). Only the hash is embedded so the
+//! binary doesn't grow with file names.
+//!
+//! On the host, [`LocationTable`] scans the sources of a contract to map the
+//! hashes back to files and to list the assertions with explicit exit codes.
+//! It's typically generated in `build.rs`:
+//!
+//! ```ignore
+//! fn main() {
+//!     let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+//!     // paths in panic locations are relative to the workspace root
+//!     let workspace_root = std::path::Path::new(&manifest_dir).join("..");
+//!     let table = ckb_std::panic_report::LocationTable::scan(
+//!         &workspace_root,
+//!         &std::path::Path::new(&manifest_dir).join("src"),
+//!     )
+//!     .unwrap();
+//!     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("panic-locations.txt");
+//!     table.write_to(std::fs::File::create(out).unwrap()).unwrap();
+//! }
+//! ```
+
+use crate::debug::StackWriter;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+/// 32 bits FNV-1a hash, used to identify source files in panic reports
+pub const fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Emit the compact report of a panic, called by the panic handler of
+/// [`entry!`](crate::entry)
+///
+/// The line is formatted on the stack: the panic may come from an exhausted
+/// heap, and the contract may have no allocator at all.
+pub fn report(info: &PanicInfo) {
+    let code = crate::asserts::panic_exit_code();
+    let (file, line) = match info.location() {
+        Some(location) => (fnv1a(location.file().as_bytes()), location.line()),
+        None => (0, 0),
+    };
+    let mut w = StackWriter::<64>::new();
+    let _ = write_report(&mut w, file, line, code);
+    crate::syscalls::debug_c_str(w.as_c_str());
+}
+
+fn write_report(w: &mut impl Write, file: u32, line: u32, code: i8) -> fmt::Result {
+    write!(w, "panic file={:08x} line={} code={}", file, line, code)
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+pub use host::*;
+
+#[cfg(not(target_arch = "riscv64"))]
+mod host {
+    extern crate std;
+
+    use super::fnv1a;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use std::collections::BTreeMap;
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    /// Macros of `ckb_std` taking an exit code as first argument
+    const MACROS: [&str; 3] = ["assert", "assert_eq", "assert_ne"];
+
+    /// Functions of `ckb_std::asserts` taking an exit code as first argument
+    const FUNCTIONS: [&str; 6] = [
+        "expect_result",
+        "expect_err_result",
+        "unwrap_result",
+        "unwrap_err_result",
+        "expect_option",
+        "unwrap_option",
+    ];
+
+    fn is_ident_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_'
+    }
+
+    /// Text after the first `name` of `line` followed by `open`, where `name`
+    /// is a whole identifier and `path_ok` accepts the path it's written with
+    fn find_call<'a>(
+        line: &'a str,
+        name: &str,
+        open: &str,
+        path_ok: impl Fn(&str) -> bool,
+    ) -> Option<&'a str> {
+        let mut from = 0;
+        while let Some(found) = line[from..].find(name) {
+            let start = from + found;
+            from = start + name.len();
+            let (before, after) = (&line[..start], &line[from..]);
+            if before.ends_with(is_ident_char) || after.starts_with(is_ident_char) {
+                continue;
+            }
+            let path = &before[before
+                .trim_end_matches(|c| is_ident_char(c) || c == ':')
+                .len()..];
+            if let Some(args) = after.strip_prefix(open).filter(|_| path_ok(path)) {
+                return Some(args);
+            }
+        }
+        None
+    }
+
+    /// Name of the `ckb_std` macro `name` in `source`, if it's imported:
+    /// `use ckb_std::assert;` or `use ckb_std::{assert as check, debug};`
+    fn imported_as<'a>(source: &'a str, name: &'a str) -> Option<&'a str> {
+        source
+            .match_indices("use ckb_std::")
+            .find_map(|(start, _)| {
+                let statement = &source[start..];
+                let statement = &statement[..statement.find(';').unwrap_or(statement.len())];
+                let rest = find_call(statement, name, "", |_| true)?;
+                match rest.trim_start().strip_prefix("as ") {
+                    Some(alias) => alias.trim_start().split(|c| !is_ident_char(c)).next(),
+                    None => Some(name),
+                }
+            })
+    }
+
+    /// An assertion with an explicit exit code
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Assertion {
+        /// Source of the exit code argument, e.g. `Error::InvalidArgs`
+        pub code: String,
+        /// Source file, relative to the workspace root
+        pub file: String,
+        /// Line number, starting from 1
+        pub line: u32,
+    }
+
+    /// Source files by hash, and assertions with explicit exit codes
+    #[derive(Debug, Default)]
+    pub struct LocationTable {
+        /// Source files by the hash used in panic reports
+        pub files: BTreeMap<u32, String>,
+        /// Assertions in declaration order
+        pub assertions: Vec<Assertion>,
+    }
+
+    impl LocationTable {
+        /// Scan the `.rs` files in `dir` recursively
+        ///
+        /// `base` is the directory `core::panic::Location` paths are relative
+        /// to, for cargo it's the workspace root. Assertions are found with a
+        /// plain text search, codes computed at runtime are reported as
+        /// written.
+        pub fn scan(base: &Path, dir: &Path) -> io::Result<Self> {
+            let mut table = LocationTable::default();
+            table.scan_dir(base, dir)?;
+            Ok(table)
+        }
+
+        fn scan_dir(&mut self, base: &Path, dir: &Path) -> io::Result<()> {
+            let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.path());
+            for entry in entries {
+                let path = entry.path();
+                if path.is_dir() {
+                    self.scan_dir(base, &path)?;
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    let source = std::fs::read_to_string(&path)?;
+                    let file = relative_path(base, &path);
+                    self.add_file(&file, &source);
+                }
+            }
+            Ok(())
+        }
+
+        /// Add a source file, `file` is the path as reported in panics
+        ///
+        /// The macros are found when called as `ckb_std::assert!(` or under
+        /// the name they're imported with from `ckb_std`, the `core` macros of
+        /// the same names don't take an exit code. The functions are found
+        /// with any path.
+        pub fn add_file(&mut self, file: &str, source: &str) {
+            self.files.insert(fnv1a(file.as_bytes()), file.to_string());
+            let imported: Vec<&str> = MACROS
+                .iter()
+                .filter_map(|name| imported_as(source, name))
+                .collect();
+            for (i, line) in source.lines().enumerate() {
+                let args = MACROS
+                    .iter()
+                    .find_map(|name| {
+                        find_call(line, name, "!(", |path| {
+                            path.trim_start_matches("::") == "ckb_std::"
+                        })
+                    })
+                    .or_else(|| {
+                        imported
+                            .iter()
+                            .find_map(|name| find_call(line, name, "!(", str::is_empty))
+                    })
+                    .or_else(|| {
+                        FUNCTIONS
+                            .iter()
+                            .find_map(|name| find_call(line, name, "(", |_| true))
+                    });
+                if let Some(args) = args {
+                    let code = args.split(',').next().unwrap_or_default().trim();
+                    self.assertions.push(Assertion {
+                        code: code.to_string(),
+                        file: file.to_string(),
+                        line: i as u32 + 1,
+                    });
+                }
+            }
+        }
+
+        /// Source file of a hash found in a panic report
+        pub fn file(&self, hash: u32) -> Option<&str> {
+            self.files.get(&hash).map(|file| file.as_str())
+        }
+
+        /// Assertions using `code`, as written in the source
+        pub fn assertions_with_code<'a>(
+            &'a self,
+            code: &'a str,
+        ) -> impl Iterator<Item = &'a Assertion> + 'a {
+            self.assertions.iter().filter(move |a| a.code == code)
+        }
+
+        /// Turn a report line into `file:line code=...`
+        ///
+        /// Returns None if the line isn't a panic report.
+        pub fn decode(&self, report: &str) -> Option<String> {
+            let report = report.trim().strip_prefix("panic ")?;
+            let mut file = None;
+            let mut line = None;
+            let mut code = None;
+            for field in report.split_whitespace() {
+                match field.split_once('=')? {
+                    ("file", value) => file = u32::from_str_radix(value, 16).ok(),
+                    ("line", value) => line = Some(value),
+                    ("code", value) => code = Some(value),
+                    _ => {}
+                }
+            }
+            let hash = file?;
+            let file = match self.file(hash) {
+                Some(file) => file.to_string(),
+                None => alloc::format!("<unknown {:08x}>", hash),
+            };
+            Some(alloc::format!("{}:{} code={}", file, line?, code?))
+        }
+
+        /// Write the table as text: one `file <hash> <path>` line per file,
+        /// then one `assert <path>:<line> <code>` line per assertion
+        pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+            for (hash, file) in &self.files {
+                writeln!(w, "file {:08x} {}", hash, file)?;
+            }
+            for a in &self.assertions {
+                writeln!(w, "assert {}:{} {}", a.file, a.line, a.code)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// `path` relative to `base` with `/` separators, like rustc reports it
+    fn relative_path(base: &Path, path: &Path) -> String {
+        let base = base.canonicalize().unwrap_or_else(|_| base.to_path_buf());
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let relative = path.strip_prefix(&base).unwrap_or(&path);
+        relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use std::path::PathBuf;
+
+    const SOURCE: &str = r#"use ckb_std::asserts::{expect_result, unwrap_option};
+
+fn main() -> i8 {
+    ckb_std::assert!(Error::InvalidArgs, args.len() == 20);
+    ckb_std::assert_eq!(-5, a, b, "lengths");
+    let x = unwrap_option(Error::ItemMissing as i8, value);
+    core::assert!(x > 0);
+    expect_result(7, load_script(), "script");
+    0
+}
+"#;
+
+    #[test]
+    fn report_fits_on_the_stack() {
+        let mut w = StackWriter::<64>::new();
+        write_report(&mut w, u32::MAX, u32::MAX, i8::MIN).unwrap();
+        assert_eq!(
+            w.as_c_str().to_str().unwrap(),
+            "panic file=ffffffff line=4294967295 code=-128"
+        );
+    }
+
+    #[test]
+    fn scans_assertions() {
+        let mut table = LocationTable::default();
+        table.add_file("contracts/lock/src/main.rs", SOURCE);
+        let assertions: Vec<(&str, u32)> = table
+            .assertions
+            .iter()
+            .map(|a| (a.code.as_str(), a.line))
+            .collect();
+        assert_eq!(
+            assertions,
+            [
+                ("Error::InvalidArgs", 4),
+                ("-5", 5),
+                ("Error::ItemMissing as i8", 6),
+                ("7", 8)
+            ]
+        );
+        assert_eq!(table.assertions_with_code("-5").count(), 1);
+        assert_eq!(table.assertions[0].file, "contracts/lock/src/main.rs");
+    }
+
+    #[test]
+    fn matches_whole_identifiers() {
+        let source = r#"use ckb_std::{
+    assert,
+    assert_eq as check_eq,
+};
+
+fn main() -> i8 {
+    my_unwrap_option(3, value);
+    assert!(4, args.len() == 20);
+    check_eq!(5, a, b);
+    core::assert!(x > 0);
+    assert_eq!(a, b);
+    ckb_std::asserts::unwrap_option(6, value);
+    0
+}
+"#;
+        let mut table = LocationTable::default();
+        table.add_file("src/main.rs", source);
+        let assertions: Vec<(&str, u32)> = table
+            .assertions
+            .iter()
+            .map(|a| (a.code.as_str(), a.line))
+            .collect();
+        assert_eq!(assertions, [("4", 8), ("5", 9), ("6", 12)]);
+    }
+
+    #[test]
+    fn decodes_reports() {
+        let mut table = LocationTable::default();
+        table.add_file("src/main.rs", SOURCE);
+        let hash = fnv1a(b"src/main.rs");
+        let mut report = String::new();
+        write_report(&mut report, hash, 4, -1).unwrap();
+        assert_eq!(table.decode(&report).unwrap(), "src/main.rs:4 code=-1");
+        assert_eq!(
+            table.decode("panic file=00000001 line=2 code=3").unwrap(),
+            "<unknown 00000001>:2 code=3"
+        );
+        assert_eq!(table.decode("out of memory size=8"), None);
+
+        let mut text = Vec::new();
+        table.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with(&std::format!("file {:08x} src/main.rs\n", hash)));
+        assert!(text.contains("assert src/main.rs:5 -5\n"));
+    }
+
+    #[test]
+    fn scans_directories() {
+        let base: PathBuf =
+            std::env::temp_dir().join(std::format!("ckb-std-panic-report-{}", std::process::id()));
+        let dir = base.join("lock").join("src");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("main.rs"), SOURCE).unwrap();
+        std::fs::write(
+            dir.join("nested").join("util.rs"),
+            "ckb_std::assert!(9, ok);\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "ckb_std::assert!(1, ok);\n").unwrap();
+
+        let table = LocationTable::scan(&base, &dir).unwrap();
+        std::fs::remove_dir_all(&base).unwrap();
+        let files: Vec<&str> = table.files.values().map(String::as_str).collect();
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&"lock/src/main.rs"));
+        assert!(files.contains(&"lock/src/nested/util.rs"));
+        let last = table.assertions.last().unwrap();
+        assert_eq!(
+            (last.file.as_str(), last.line, last.code.as_str()),
+            ("lock/src/nested/util.rs", 1, "9")
+        );
+    }
+}
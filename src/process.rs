@@ -0,0 +1,760 @@
+//! Orchestration helpers for scripts composed of several spawned children.
+//!
+//! The raw `spawn`, `pipe` and `wait` syscalls leave all file descriptor
+//! bookkeeping to the caller: which end of each pipe goes to which child,
+//! which ends the parent keeps, and in which order children are waited. This
+//! module wraps the common topologies:
+//!
+//! * [`ProcessGroup::spawn_piped`]: a single child with a bidirectional
+//!   channel to the parent.
+//! * [`ProcessGroup::fan_out`]: several independent children, each with its
+//!   own channel to the parent (also usable for fan-in).
+//! * [`ProcessGroup::pipeline`]: children chained so that the output of one
+//!   child is the input of the next one.
+//!
+//! [`ReadMux`] serves several children from one process by reading their fds
+//! in a fair round-robin loop.
+//!
+//! Every child receives its fds through `inherited_fds` in the order
+//! `[read_fd, write_fd]`, and can retrieve them with
+//! [`high_level::inherited_fds`](crate::high_level::inherited_fds).
+//!
+//! # Example
+//!
+//! ```
+//! use ckb_std::process::{Command, ProcessGroup};
+//!
+//! let mut group = ProcessGroup::new();
+//! let (stdin, stdout) = group.pipeline(&[
+//!     Command::new(&DECODER_CODE_HASH, ScriptHashType::Data1),
+//!     Command::new(&VERIFIER_CODE_HASH, ScriptHashType::Data1).arg(c"strict"),
+//! ])?;
+//! syscalls::write(stdin, &witness)?;
+//! group.close(stdin)?;
+//! let len = syscalls::read(stdout, &mut buf)?;
+//! // returns the index of the first child exiting with a non-zero code
+//! group.wait_all_success()?;
+//! ```
+
+use crate::error::SysError;
+use crate::high_level::spawn_cell;
+use crate::syscalls;
+use alloc::vec::Vec;
+use ckb_types::core::ScriptHashType;
+use core::ffi::CStr;
+
+/// Maximum number of VMs that can be alive in one script group, including the
+/// root process.
+pub const MAX_VMS_COUNT: usize = 16;
+
+/// Maximum number of file descriptors a script group can create. Each pipe
+/// creates 2 file descriptors.
+pub const MAX_FDS_CREATED: usize = 64;
+
+/// Process orchestration errors
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Error {
+    /// Creating a pipe failed
+    Pipe(SysError),
+    /// Spawning the child at `index` failed. When a topology is rejected
+    /// before anything is spawned, `index` is the one its first child would
+    /// have had.
+    Spawn { index: usize, error: SysError },
+    /// Waiting for the child at `index` failed, with `IndexOutOfBound` if
+    /// there is no such child
+    Wait { index: usize, error: SysError },
+    /// The child at `index` exited with a non-zero exit code
+    ExitCode { index: usize, code: i8 },
+    /// Closing a file descriptor failed
+    Close(SysError),
+    /// A pipeline needs at least one command
+    EmptyPipeline,
+}
+
+/// Description of a child program located in cell deps.
+#[derive(Clone)]
+pub struct Command<'a> {
+    code_hash: &'a [u8],
+    hash_type: ScriptHashType,
+    argv: Vec<&'a CStr>,
+}
+
+impl<'a> Command<'a> {
+    /// Create a command from the code hash and hash type of a cell dep.
+    pub fn new(code_hash: &'a [u8], hash_type: ScriptHashType) -> Self {
+        Command {
+            code_hash,
+            hash_type,
+            argv: Vec::new(),
+        }
+    }
+
+    /// Append an argument.
+    pub fn arg(mut self, arg: &'a CStr) -> Self {
+        self.argv.push(arg);
+        self
+    }
+
+    /// Append several arguments.
+    pub fn args(mut self, args: &[&'a CStr]) -> Self {
+        self.argv.extend_from_slice(args);
+        self
+    }
+
+    pub fn code_hash(&self) -> &[u8] {
+        self.code_hash
+    }
+
+    pub fn hash_type(&self) -> ScriptHashType {
+        self.hash_type
+    }
+
+    pub fn argv(&self) -> &[&'a CStr] {
+        &self.argv
+    }
+}
+
+/// A child spawned by a [`ProcessGroup`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Child {
+    /// Process id returned by spawn
+    pub pid: u64,
+    /// Write end kept by the parent to send data to the child, if any
+    pub stdin: Option<u64>,
+    /// Read end kept by the parent to receive data from the child, if any
+    pub stdout: Option<u64>,
+    /// Exit code, set once the child has been waited
+    pub exit_code: Option<i8>,
+}
+
+/// A set of children spawned by the current process.
+///
+/// The group counts the VMs and fds it creates and refuses to exceed
+/// [`MAX_VMS_COUNT`] and [`MAX_FDS_CREATED`] before issuing the syscalls, so
+/// a topology that can't fit is rejected as a whole instead of failing
+/// half-way with some children already running. The counters start from the
+/// values passed to [`ProcessGroup::with_usage`] (nothing but the root
+/// process by default).
+///
+/// A syscall can still fail half-way through a topology, for instance when a
+/// code hash isn't in the cell deps. The parent then closes the pipe ends it
+/// holds for the topology, so that the children already spawned read
+/// `OtherEndClosed` instead of blocking. These children stay in the group
+/// and are waited as usual, there is no way to kill them.
+pub struct ProcessGroup {
+    children: Vec<Child>,
+    vms_count: usize,
+    fds_created: usize,
+}
+
+impl Default for ProcessGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessGroup {
+    /// Create an empty group, assuming only the root process is running and
+    /// no fd has been created yet.
+    pub fn new() -> Self {
+        Self::with_usage(1, 0)
+    }
+
+    /// Create an empty group, taking VMs and fds already in use by the script
+    /// into account.
+    pub fn with_usage(vms_count: usize, fds_created: usize) -> Self {
+        ProcessGroup {
+            children: Vec::new(),
+            vms_count,
+            fds_created,
+        }
+    }
+
+    /// Children spawned so far, in spawn order. Error indexes refer to this
+    /// order.
+    pub fn children(&self) -> &[Child] {
+        &self.children
+    }
+
+    pub fn child(&self, index: usize) -> Option<&Child> {
+        self.children.get(index)
+    }
+
+    /// Number of VMs that can still be spawned.
+    pub fn remaining_vms(&self) -> usize {
+        MAX_VMS_COUNT.saturating_sub(self.vms_count)
+    }
+
+    /// Number of fds that can still be created.
+    pub fn remaining_fds(&self) -> usize {
+        MAX_FDS_CREATED.saturating_sub(self.fds_created)
+    }
+
+    fn reserve(&self, vms: usize, pipes: usize) -> Result<(), Error> {
+        if vms > self.remaining_vms() {
+            return Err(Error::Spawn {
+                index: self.children.len(),
+                error: SysError::MaxVmsSpawned,
+            });
+        }
+        if pipes * 2 > self.remaining_fds() {
+            return Err(Error::Pipe(SysError::MaxFdsCreated));
+        }
+        Ok(())
+    }
+
+    /// Create a pipe, returning `(read_fd, write_fd)`.
+    pub fn pipe(&mut self) -> Result<(u64, u64), Error> {
+        self.reserve(0, 1)?;
+        let fds = syscalls::pipe().map_err(Error::Pipe)?;
+        self.fds_created += 2;
+        Ok(fds)
+    }
+
+    /// Spawn a child passing `inherited_fds` as-is. The ownership of these
+    /// fds is transferred to the child; the parent must not use them
+    /// afterwards.
+    ///
+    /// Return the index of the child in this group.
+    pub fn spawn(&mut self, command: &Command, inherited_fds: &[u64]) -> Result<usize, Error> {
+        self.reserve(1, 0)?;
+        let index = self.children.len();
+        let pid = spawn_cell(
+            command.code_hash,
+            command.hash_type,
+            &command.argv,
+            inherited_fds,
+        )
+        .map_err(|error| Error::Spawn { index, error })?;
+        self.vms_count += 1;
+        self.children.push(Child {
+            pid,
+            stdin: None,
+            stdout: None,
+            exit_code: None,
+        });
+        Ok(index)
+    }
+
+    /// Spawn a child connected to the parent by two pipes. The child inherits
+    /// `[read_fd, write_fd]`, the parent keeps the other ends in
+    /// [`Child::stdin`] and [`Child::stdout`].
+    ///
+    /// Return the index of the child in this group.
+    pub fn spawn_piped(&mut self, command: &Command) -> Result<usize, Error> {
+        self.reserve(1, 2)?;
+        let mut fds = Vec::new();
+        self.try_spawn_piped(command, &mut fds)
+            .map_err(|error| self.abort(&fds, error))
+    }
+
+    fn try_spawn_piped(&mut self, command: &Command, fds: &mut Vec<u64>) -> Result<usize, Error> {
+        let (child_read, parent_write) = self.pipe()?;
+        fds.extend([child_read, parent_write]);
+        let (parent_read, child_write) = self.pipe()?;
+        fds.extend([parent_read, child_write]);
+        let index = self.spawn(command, &[child_read, child_write])?;
+        let child = &mut self.children[index];
+        child.stdin = Some(parent_write);
+        child.stdout = Some(parent_read);
+        Ok(index)
+    }
+
+    /// Spawn every command with its own channel to the parent, see
+    /// [`ProcessGroup::spawn_piped`]. Resource limits are checked for the
+    /// whole set before anything is spawned.
+    ///
+    /// Return the indexes of the new children.
+    pub fn fan_out(&mut self, commands: &[Command]) -> Result<core::ops::Range<usize>, Error> {
+        self.reserve(commands.len(), commands.len() * 2)?;
+        let start = self.children.len();
+        for command in commands {
+            if let Err(error) = self.spawn_piped(command) {
+                let fds: Vec<u64> = self.children[start..]
+                    .iter()
+                    .flat_map(|child| [child.stdin, child.stdout])
+                    .flatten()
+                    .collect();
+                return Err(self.abort(&fds, error));
+            }
+        }
+        Ok(start..self.children.len())
+    }
+
+    /// Spawn the commands as a pipeline: the parent writes to the first
+    /// child, each child writes to the next one, and the last child writes
+    /// back to the parent. Resource limits are checked for the whole
+    /// pipeline before anything is spawned.
+    ///
+    /// Return `(write_fd, read_fd)` kept by the parent: the input of the
+    /// first child and the output of the last child.
+    pub fn pipeline(&mut self, commands: &[Command]) -> Result<(u64, u64), Error> {
+        if commands.is_empty() {
+            return Err(Error::EmptyPipeline);
+        }
+        self.reserve(commands.len(), commands.len() + 1)?;
+        let mut fds = Vec::new();
+        self.try_pipeline(commands, &mut fds)
+            .map_err(|error| self.abort(&fds, error))
+    }
+
+    fn try_pipeline(
+        &mut self,
+        commands: &[Command],
+        fds: &mut Vec<u64>,
+    ) -> Result<(u64, u64), Error> {
+        let (mut next_read, parent_write) = self.pipe()?;
+        fds.extend([next_read, parent_write]);
+        for (i, command) in commands.iter().enumerate() {
+            let (read, write) = self.pipe()?;
+            fds.extend([read, write]);
+            let index = self.spawn(command, &[next_read, write])?;
+            // owned by the child now
+            fds.retain(|fd| *fd != next_read && *fd != write);
+            if i == 0 {
+                self.children[index].stdin = Some(parent_write);
+            }
+            if i == commands.len() - 1 {
+                self.children[index].stdout = Some(read);
+            }
+            next_read = read;
+        }
+        Ok((parent_write, next_read))
+    }
+
+    /// Close the `fds` the parent still holds after a topology failed
+    /// half-way, and return the error of the failure.
+    fn abort(&mut self, fds: &[u64], error: Error) -> Error {
+        for fd in fds {
+            // the topology failed anyway, a fd that can't be closed is
+            // already gone
+            let _ = self.close(*fd);
+        }
+        error
+    }
+
+    /// Close a fd kept by the parent, typically the write end of a child's
+    /// input so that the child reads `OtherEndClosed` once it has consumed
+    /// all data.
+    pub fn close(&mut self, fd: u64) -> Result<(), Error> {
+        syscalls::close(fd).map_err(Error::Close)?;
+        for child in self.children.iter_mut() {
+            if child.stdin == Some(fd) {
+                child.stdin = None;
+            }
+            if child.stdout == Some(fd) {
+                child.stdout = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the child at `index`, returning its exit code. Waiting an
+    /// already waited child returns the recorded exit code.
+    pub fn wait(&mut self, index: usize) -> Result<i8, Error> {
+        let child = self.children.get_mut(index).ok_or(Error::Wait {
+            index,
+            error: SysError::IndexOutOfBound,
+        })?;
+        if let Some(code) = child.exit_code {
+            return Ok(code);
+        }
+        let code = syscalls::wait(child.pid).map_err(|error| Error::Wait { index, error })?;
+        child.exit_code = Some(code);
+        Ok(code)
+    }
+
+    /// Wait for all children in spawn order and return their exit codes.
+    ///
+    /// The parent's pipe ends are closed first: a child blocked on reading
+    /// its input would otherwise never exit.
+    pub fn wait_all(&mut self) -> Result<Vec<i8>, Error> {
+        for index in 0..self.children.len() {
+            if let Some(fd) = self.children[index].stdin {
+                self.close(fd)?;
+            }
+        }
+        (0..self.children.len())
+            .map(|index| self.wait(index))
+            .collect()
+    }
+
+    /// Wait for all children, and fail with [`Error::ExitCode`] naming the
+    /// first child (in spawn order) that exited with a non-zero code.
+    pub fn wait_all_success(&mut self) -> Result<(), Error> {
+        let codes = self.wait_all()?;
+        match codes.iter().position(|code| *code != 0) {
+            Some(index) => Err(Error::ExitCode {
+                index,
+                code: codes[index],
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Read from `fd` until `buf` is full.
+///
+/// Return `Err(SysError::OtherEndClosed)` if the writer closes its end before
+/// enough data has been received.
+pub fn read_exact(fd: u64, buf: &mut [u8]) -> Result<(), SysError> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        offset += syscalls::read(fd, &mut buf[offset..])?;
+    }
+    Ok(())
+}
+
+/// Write the whole `buf` to `fd`.
+pub fn write_all(fd: u64, buf: &[u8]) -> Result<(), SysError> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        offset += syscalls::write(fd, &buf[offset..])?;
+    }
+    Ok(())
+}
+
+/// Event returned by [`ReadMux::next`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReadEvent {
+    /// `len` bytes were read from `fd`. `len` can be 0 when the peer wrote an
+    /// empty buffer.
+    Data { fd: u64, len: usize },
+    /// The write end of `fd` was closed, `fd` is removed from the set.
+    Closed { fd: u64 },
+}
+
+/// Fair multiplexer over several read fds.
+///
+/// CKB-VM has no way to query whether a `read` would block: a reader is
+/// suspended until the peer writes or closes its end. The multiplexer
+/// therefore doesn't poll, it visits the open fds in round-robin order so
+/// that every child is served in turn, and turns `OtherEndClosed` into a
+/// [`ReadEvent::Closed`] event instead of an error.
+///
+/// To avoid deadlocks, a child served by a multiplexer must not block on its
+/// own input while the parent may be reading from it. Pipes aren't buffered:
+/// the CKB scheduler completes a write, even an empty one, once the reader
+/// reads the fd, and the read returns the bytes written, 0 for an empty
+/// write. A child with nothing to report can thus write an empty buffer: it
+/// waits for its turn, then the parent gets a `Data { len: 0, .. }` event and
+/// passes the turn to the next fd.
+///
+/// # Example
+///
+/// ```
+/// let mut mux = ReadMux::new(&[child_a_stdout, child_b_stdout]);
+/// let mut buf = [0u8; 256];
+/// while let Some(event) = mux.next(&mut buf)? {
+///     if let ReadEvent::Data { fd, len } = event {
+///         handle(fd, &buf[..len]);
+///     }
+/// }
+/// // all children have closed their output
+/// ```
+pub struct ReadMux {
+    fds: Vec<u64>,
+    cursor: usize,
+}
+
+impl ReadMux {
+    pub fn new(fds: &[u64]) -> Self {
+        ReadMux {
+            fds: fds.to_vec(),
+            cursor: 0,
+        }
+    }
+
+    /// Add a fd at the end of the round-robin order.
+    pub fn add(&mut self, fd: u64) {
+        self.fds.push(fd);
+    }
+
+    /// Remove a fd, without closing it.
+    pub fn remove(&mut self, fd: u64) {
+        if let Some(i) = self.fds.iter().position(|f| *f == fd) {
+            self.fds.remove(i);
+            if i < self.cursor {
+                self.cursor -= 1;
+            }
+        }
+    }
+
+    /// Fds which are still open, in round-robin order.
+    pub fn fds(&self) -> &[u64] {
+        &self.fds
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Read once from the next fd in round-robin order.
+    ///
+    /// Return `Ok(None)` once every fd has been closed by its writer.
+    pub fn next(&mut self, buf: &mut [u8]) -> Result<Option<ReadEvent>, SysError> {
+        if self.fds.is_empty() {
+            return Ok(None);
+        }
+        if self.cursor >= self.fds.len() {
+            self.cursor = 0;
+        }
+        let fd = self.fds[self.cursor];
+        match syscalls::read(fd, buf) {
+            Ok(len) => {
+                self.cursor += 1;
+                Ok(Some(ReadEvent::Data { fd, len }))
+            }
+            Err(SysError::OtherEndClosed) => {
+                self.fds.remove(self.cursor);
+                Ok(Some(ReadEvent::Closed { fd }))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "stub-syscalls"))]
+mod tests {
+    use super::*;
+    use crate::{
+        ckb_constants::{CellField, Source},
+        high_level::inherited_fds,
+        syscalls::{
+            stub_processes::{ExitStatus, NativeProcesses, Program},
+            traits::{Error as IoError, IoResult, SyscallImpls},
+        },
+    };
+
+    // Cell deps whose data hashes are [1; 32], [2; 32] and [3; 32]
+    struct CellDeps;
+
+    impl SyscallImpls for CellDeps {
+        fn debug(&self, _s: &CStr) {}
+
+        fn load_cell_by_field(
+            &self,
+            buf: &mut [u8],
+            _offset: usize,
+            index: usize,
+            source: Source,
+            field: CellField,
+        ) -> IoResult {
+            match (source, field) {
+                (Source::CellDep, _) if index >= 3 => IoResult::Error(IoError::IndexOutOfBound),
+                (Source::CellDep, CellField::DataHash) => {
+                    buf[..32].copy_from_slice(&[index as u8 + 1; 32]);
+                    IoResult::FullyLoaded(32)
+                }
+                _ => IoResult::Error(IoError::ItemMissing),
+            }
+        }
+    }
+
+    fn run(root: Program) -> ExitStatus {
+        NativeProcesses::new()
+            .program([1; 32], increment)
+            .program([2; 32], increment)
+            .program([3; 32], report)
+            .run_status(CellDeps, root)
+    }
+
+    const OK: ExitStatus = ExitStatus {
+        code: 0,
+        panicked: false,
+    };
+
+    // Add one to each byte read from its input, exits with 2 if its input
+    // is closed before anything is read
+    fn increment() -> i8 {
+        let [input, output] = inherited_fds()[..] else {
+            panic!("expected two fds")
+        };
+        let mut byte = [0];
+        match read_exact(input, &mut byte) {
+            Ok(()) => {}
+            Err(SysError::OtherEndClosed) => return 2,
+            Err(err) => panic!("read failed: {:?}", err),
+        }
+        write_all(output, &[byte[0] + 1]).unwrap();
+        0
+    }
+
+    // Write back the byte read from its input, then an empty buffer
+    fn report() -> i8 {
+        let [input, output] = inherited_fds()[..] else {
+            panic!("expected two fds")
+        };
+        let mut byte = [0];
+        read_exact(input, &mut byte).unwrap();
+        assert_eq!(syscalls::write(output, &byte), Ok(1));
+        assert_eq!(syscalls::write(output, &[]), Ok(0));
+        0
+    }
+
+    // Three `report` children, sent 1, 2 and 3, and their outputs
+    fn reporters(group: &mut ProcessGroup) -> [u64; 3] {
+        let commands = [command(&[3; 32]), command(&[3; 32]), command(&[3; 32])];
+        group.fan_out(&commands).unwrap();
+        for (byte, child) in group.children().iter().enumerate() {
+            write_all(child.stdin.unwrap(), &[byte as u8 + 1]).unwrap();
+        }
+        [0, 1, 2].map(|index| group.children()[index].stdout.unwrap())
+    }
+
+    fn command(code_hash: &[u8]) -> Command<'_> {
+        Command::new(code_hash, ScriptHashType::Data)
+    }
+
+    #[test]
+    fn pipelines_chain_children() {
+        let status = run(|| {
+            let mut group = ProcessGroup::new();
+            let (stdin, stdout) = group
+                .pipeline(&[command(&[1; 32]), command(&[2; 32]), command(&[1; 32])])
+                .unwrap();
+            write_all(stdin, &[10]).unwrap();
+            let mut byte = [0];
+            read_exact(stdout, &mut byte).unwrap();
+            assert_eq!(byte, [13]);
+            group.wait_all_success().unwrap();
+            assert_eq!(group.wait(2), Ok(0));
+            0
+        });
+        assert_eq!(status, OK);
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let status = run(|| {
+            let mut group = ProcessGroup::new();
+            assert_eq!(group.pipeline(&[]), Err(Error::EmptyPipeline));
+            assert_eq!(
+                group.wait(0),
+                Err(Error::Wait {
+                    index: 0,
+                    error: SysError::IndexOutOfBound
+                })
+            );
+
+            // nothing is spawned when the topology doesn't fit
+            let mut group = ProcessGroup::with_usage(MAX_VMS_COUNT - 1, 0);
+            let index = group.spawn_piped(&command(&[1; 32])).unwrap();
+            assert_eq!(index, 0);
+            assert_eq!(
+                group.fan_out(&[command(&[1; 32])]),
+                Err(Error::Spawn {
+                    index: 1,
+                    error: SysError::MaxVmsSpawned
+                })
+            );
+            let mut group = ProcessGroup::with_usage(1, MAX_FDS_CREATED - 2);
+            assert_eq!(
+                group.spawn_piped(&command(&[1; 32])),
+                Err(Error::Pipe(SysError::MaxFdsCreated))
+            );
+            assert!(group.children().is_empty());
+            0
+        });
+        assert_eq!(status, OK);
+    }
+
+    #[test]
+    fn failed_topologies_release_their_children() {
+        let status = run(|| {
+            // the second command isn't in the cell deps
+            let mut group = ProcessGroup::new();
+            let result = group.pipeline(&[command(&[1; 32]), command(&[9; 32])]);
+            assert!(matches!(result, Err(Error::Spawn { index: 1, .. })));
+            assert_eq!(group.children().len(), 1);
+            assert_eq!(group.children()[0].stdin, None);
+            // its input was closed
+            assert_eq!(group.wait(0), Ok(2));
+
+            let mut group = ProcessGroup::new();
+            let result = group.fan_out(&[command(&[2; 32]), command(&[9; 32])]);
+            assert!(matches!(result, Err(Error::Spawn { index: 1, .. })));
+            assert_eq!(group.children().len(), 1);
+            assert_eq!(group.children()[0].stdout, None);
+            assert_eq!(group.wait_all(), Ok(alloc::vec![2]));
+            0
+        });
+        assert_eq!(status, OK);
+    }
+
+    #[test]
+    fn read_mux_visits_fds_in_turn() {
+        let status = run(|| {
+            let mut group = ProcessGroup::new();
+            let [a, b, c] = reporters(&mut group);
+            let mut mux = ReadMux::new(&[a, b, c]);
+            let mut buf = [0; 8];
+            let mut events = Vec::new();
+            let mut bytes = Vec::new();
+            while let Some(event) = mux.next(&mut buf).unwrap() {
+                if let ReadEvent::Data { len, .. } = event {
+                    bytes.extend_from_slice(&buf[..len]);
+                }
+                events.push(event);
+            }
+            assert_eq!(
+                events,
+                [
+                    ReadEvent::Data { fd: a, len: 1 },
+                    ReadEvent::Data { fd: b, len: 1 },
+                    ReadEvent::Data { fd: c, len: 1 },
+                    ReadEvent::Data { fd: a, len: 0 },
+                    ReadEvent::Data { fd: b, len: 0 },
+                    ReadEvent::Data { fd: c, len: 0 },
+                    ReadEvent::Closed { fd: a },
+                    ReadEvent::Closed { fd: b },
+                    ReadEvent::Closed { fd: c },
+                ]
+            );
+            assert_eq!(bytes, [1, 2, 3]);
+            assert!(mux.is_empty());
+            group.wait_all_success().unwrap();
+            0
+        });
+        assert_eq!(status, OK);
+    }
+
+    #[test]
+    fn read_mux_keeps_its_turn_on_remove() {
+        let status = run(|| {
+            let mut group = ProcessGroup::new();
+            let [a, b, c] = reporters(&mut group);
+            let mut mux = ReadMux::new(&[a, b, c]);
+            let mut buf = [0; 8];
+            assert_eq!(
+                mux.next(&mut buf),
+                Ok(Some(ReadEvent::Data { fd: a, len: 1 }))
+            );
+            // removing a visited fd doesn't skip the next one
+            mux.remove(a);
+            assert_eq!(
+                mux.next(&mut buf),
+                Ok(Some(ReadEvent::Data { fd: b, len: 1 }))
+            );
+            assert_eq!(buf[0], 2);
+            // nor does removing one not visited yet in this round
+            mux.remove(c);
+            assert_eq!(mux.fds(), [b]);
+            assert_eq!(
+                mux.next(&mut buf),
+                Ok(Some(ReadEvent::Data { fd: b, len: 0 }))
+            );
+            assert_eq!(mux.next(&mut buf), Ok(Some(ReadEvent::Closed { fd: b })));
+            assert_eq!(mux.next(&mut buf), Ok(None));
+
+            // removed fds are left open
+            assert_eq!(syscalls::read(a, &mut buf), Ok(0));
+            assert_eq!(syscalls::read(c, &mut buf), Ok(1));
+            assert_eq!(buf[0], 3);
+            assert_eq!(syscalls::read(c, &mut buf), Ok(0));
+            group.wait_all_success().unwrap();
+            0
+        });
+        assert_eq!(status, OK);
+    }
+}
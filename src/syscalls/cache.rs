@@ -0,0 +1,478 @@
+//! A [`SyscallImpls`] wrapper memoizing the data loaded by syscalls.
+//!
+//! Transaction data doesn't change while a script runs, so the script, the
+//! tx hash, cells, inputs, headers and witnesses only need to be loaded once.
+//! [`CachedSyscalls`] keeps the full data of each load keyed by
+//! `(syscall, index, source, field)` and serves later loads, at any offset,
+//! from memory. A byte budget bounds the memory used: data that doesn't fit
+//! is loaded from the wrapped implementation every time.
+//!
+//! ```ignore
+//! use ckb_std::syscalls::{cache::CachedSyscalls, NativeSyscalls};
+//!
+//! let syscalls = CachedSyscalls::new(NativeSyscalls, 64 * 1024);
+//! ```
+//!
+//! Syscalls with side effects or changing results (`exec`, `spawn`, pipes,
+//! `current_cycles`...) are forwarded as is.
+
+use crate::{
+    ckb_constants::{self as consts, CellField, HeaderField, InputField, Place, Source},
+    syscalls::traits::{Bounds, Error, IoResult, SyscallImpls, serve},
+};
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::cell::{Cell, RefCell};
+use core::ffi::CStr;
+
+// (syscall number, index, source, field)
+type Key = (u64, u64, u64, u64);
+
+/// Caching wrapper around a [`SyscallImpls`], see the [module](self) docs
+pub struct CachedSyscalls<S> {
+    inner: S,
+    budget: usize,
+    used: Cell<usize>,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+    entries: RefCell<BTreeMap<Key, Result<Vec<u8>, Error>>>,
+}
+
+impl<S: SyscallImpls> CachedSyscalls<S> {
+    /// Wrap `inner`, caching up to `budget` bytes of data
+    pub fn new(inner: S, budget: usize) -> Self {
+        CachedSyscalls {
+            inner,
+            budget,
+            used: Cell::new(0),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+            entries: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Wrapped implementation
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Bytes of data in the cache
+    pub fn cached_bytes(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Loads served from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.get()
+    }
+
+    /// Loads forwarded to the wrapped implementation
+    pub fn misses(&self) -> usize {
+        self.misses.get()
+    }
+
+    /// Drop the cached data
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+        self.used.set(0);
+    }
+
+    // `load(buf, offset)` calls the wrapped implementation
+    fn load<F: Fn(&mut [u8], usize) -> IoResult>(
+        &self,
+        key: Key,
+        buf: &mut [u8],
+        offset: usize,
+        load: F,
+    ) -> IoResult {
+        if let Some(entry) = self.entries.borrow().get(&key) {
+            self.hits.set(self.hits.get() + 1);
+            return match entry {
+                Ok(data) => serve(data, buf, offset),
+                Err(err) => IoResult::Error(*err),
+            };
+        }
+        self.misses.set(self.misses.get() + 1);
+        let result = load(buf, offset);
+        let len = match result {
+            IoResult::Error(err) => {
+                self.entries.borrow_mut().insert(key, Err(err));
+                return result;
+            }
+            IoResult::FullyLoaded(loaded) if offset == 0 => {
+                self.insert(key, &buf[..loaded]);
+                return result;
+            }
+            // the caller's buffer misses the data before `offset`, or after
+            // the end of the buffer
+            IoResult::FullyLoaded(loaded) if loaded > 0 => offset + loaded,
+            IoResult::PartialLoaded { available, .. } => offset + available,
+            // the length of data shorter than `offset` isn't known
+            IoResult::FullyLoaded(_) => return result,
+        };
+        if self.used.get() + len <= self.budget {
+            let mut data = vec![0u8; len];
+            if load(&mut data, 0) == IoResult::FullyLoaded(len) {
+                self.insert(key, &data);
+            }
+        }
+        result
+    }
+
+    // Cache `data` if it fits in the budget
+    fn insert(&self, key: Key, data: &[u8]) {
+        if self.used.get() + data.len() > self.budget {
+            return;
+        }
+        self.used.set(self.used.get() + data.len());
+        self.entries.borrow_mut().insert(key, Ok(data.to_vec()));
+    }
+}
+
+impl<S: SyscallImpls> SyscallImpls for CachedSyscalls<S> {
+    fn syscall(&self, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, n: u64) -> u64 {
+        self.inner.syscall(a0, a1, a2, a3, a4, a5, n)
+    }
+
+    fn syscall_load(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        a3: u64,
+        a4: u64,
+        a5: u64,
+        syscall_num: u64,
+    ) -> IoResult {
+        self.inner
+            .syscall_load(buf, offset, a3, a4, a5, syscall_num)
+    }
+
+    fn debug(&self, s: &CStr) {
+        self.inner.debug(s)
+    }
+
+    fn exit(&self, code: i8) -> ! {
+        self.inner.exit(code)
+    }
+
+    fn load_cell(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let key = (consts::SYS_LOAD_CELL, index as u64, source as u64, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_cell(buf, offset, index, source)
+        })
+    }
+
+    fn load_cell_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: CellField,
+    ) -> IoResult {
+        let key = (
+            consts::SYS_LOAD_CELL_BY_FIELD,
+            index as u64,
+            source as u64,
+            field as u64,
+        );
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner
+                .load_cell_by_field(buf, offset, index, source, field)
+        })
+    }
+
+    fn load_cell_code(
+        &self,
+        buf_ptr: *mut u8,
+        len: usize,
+        content_offset: usize,
+        content_size: usize,
+        index: usize,
+        source: Source,
+    ) -> Result<(), Error> {
+        self.inner
+            .load_cell_code(buf_ptr, len, content_offset, content_size, index, source)
+    }
+
+    fn load_cell_data(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let key = (consts::SYS_LOAD_CELL_DATA, index as u64, source as u64, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_cell_data(buf, offset, index, source)
+        })
+    }
+
+    fn load_header(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let key = (consts::SYS_LOAD_HEADER, index as u64, source as u64, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_header(buf, offset, index, source)
+        })
+    }
+
+    fn load_header_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: HeaderField,
+    ) -> IoResult {
+        let key = (
+            consts::SYS_LOAD_HEADER_BY_FIELD,
+            index as u64,
+            source as u64,
+            field as u64,
+        );
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner
+                .load_header_by_field(buf, offset, index, source, field)
+        })
+    }
+
+    fn load_input(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let key = (consts::SYS_LOAD_INPUT, index as u64, source as u64, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_input(buf, offset, index, source)
+        })
+    }
+
+    fn load_input_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: InputField,
+    ) -> IoResult {
+        let key = (
+            consts::SYS_LOAD_INPUT_BY_FIELD,
+            index as u64,
+            source as u64,
+            field as u64,
+        );
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner
+                .load_input_by_field(buf, offset, index, source, field)
+        })
+    }
+
+    fn load_script(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let key = (consts::SYS_LOAD_SCRIPT, 0, 0, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_script(buf, offset)
+        })
+    }
+
+    fn load_script_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let key = (consts::SYS_LOAD_SCRIPT_HASH, 0, 0, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_script_hash(buf, offset)
+        })
+    }
+
+    fn load_transaction(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let key = (consts::SYS_LOAD_TRANSACTION, 0, 0, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_transaction(buf, offset)
+        })
+    }
+
+    fn load_tx_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        let key = (consts::SYS_LOAD_TX_HASH, 0, 0, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_tx_hash(buf, offset)
+        })
+    }
+
+    fn load_witness(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let key = (consts::SYS_LOAD_WITNESS, index as u64, source as u64, 0);
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_witness(buf, offset, index, source)
+        })
+    }
+
+    fn vm_version(&self) -> u64 {
+        self.inner.vm_version()
+    }
+
+    fn current_cycles(&self) -> u64 {
+        self.inner.current_cycles()
+    }
+
+    fn exec(
+        &self,
+        index: usize,
+        source: Source,
+        place: Place,
+        bounds: Bounds,
+        argv: &[&CStr],
+    ) -> Result<(), Error> {
+        self.inner.exec(index, source, place, bounds, argv)
+    }
+
+    fn spawn(
+        &self,
+        index: usize,
+        source: Source,
+        place: Place,
+        bounds: Bounds,
+        argv: &[&CStr],
+        inherited_fds: &[u64],
+    ) -> Result<u64, Error> {
+        self.inner
+            .spawn(index, source, place, bounds, argv, inherited_fds)
+    }
+
+    fn pipe(&self) -> Result<(u64, u64), Error> {
+        self.inner.pipe()
+    }
+
+    fn inherited_fds(&self, fds: &mut [u64]) -> Result<usize, Error> {
+        self.inner.inherited_fds(fds)
+    }
+
+    fn read(&self, fd: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.inner.read(fd, buffer)
+    }
+
+    fn write(&self, fd: u64, buffer: &[u8]) -> Result<usize, Error> {
+        self.inner.write(fd, buffer)
+    }
+
+    fn close(&self, fd: u64) -> Result<(), Error> {
+        self.inner.close(fd)
+    }
+
+    fn wait(&self, pid: u64) -> Result<i8, Error> {
+        self.inner.wait(pid)
+    }
+
+    fn process_id(&self) -> u64 {
+        self.inner.process_id()
+    }
+
+    fn load_block_extension(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let key = (
+            consts::SYS_LOAD_BLOCK_EXTENSION,
+            index as u64,
+            source as u64,
+            0,
+        );
+        self.load(key, buf, offset, |buf, offset| {
+            self.inner.load_block_extension(buf, offset, index, source)
+        })
+    }
+
+    fn debug_s(&self, s: String) {
+        self.inner.debug_s(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Witness 0 is `0..100`, witness 1 is missing
+    #[derive(Default)]
+    struct Witnesses {
+        loads: Cell<usize>,
+    }
+
+    impl SyscallImpls for Witnesses {
+        fn debug(&self, _s: &CStr) {}
+
+        fn load_witness(
+            &self,
+            buf: &mut [u8],
+            offset: usize,
+            index: usize,
+            _source: Source,
+        ) -> IoResult {
+            self.loads.set(self.loads.get() + 1);
+            match index {
+                0 => serve(&(0..100).collect::<Vec<u8>>(), buf, offset),
+                _ => IoResult::Error(Error::IndexOutOfBound),
+            }
+        }
+    }
+
+    #[test]
+    fn loads_once_into_the_callers_buffer() {
+        let cached = CachedSyscalls::new(Witnesses::default(), 1024);
+        let mut buf = [0; 128];
+        assert_eq!(
+            cached.load_witness(&mut buf, 0, 0, Source::Input),
+            IoResult::FullyLoaded(100)
+        );
+        assert_eq!(cached.inner().loads.get(), 1);
+        assert_eq!(
+            cached.load_witness(&mut buf[..10], 95, 0, Source::Input),
+            IoResult::FullyLoaded(5)
+        );
+        assert_eq!(buf[..5], [95, 96, 97, 98, 99]);
+        assert_eq!(
+            cached.load_witness(&mut buf, 0, 1, Source::Input),
+            IoResult::Error(Error::IndexOutOfBound)
+        );
+        assert_eq!(
+            cached.load_witness(&mut buf, 0, 1, Source::Input),
+            IoResult::Error(Error::IndexOutOfBound)
+        );
+        assert_eq!(cached.inner().loads.get(), 2);
+        assert_eq!((cached.hits(), cached.misses()), (2, 2));
+        assert_eq!(cached.cached_bytes(), 100);
+    }
+
+    #[test]
+    fn loads_all_data_after_partial_loads() {
+        let cached = CachedSyscalls::new(Witnesses::default(), 1024);
+        let mut buf = [0; 10];
+        assert_eq!(
+            cached.load_witness(&mut buf, 20, 0, Source::Input),
+            IoResult::PartialLoaded {
+                loaded: 10,
+                available: 80
+            }
+        );
+        assert_eq!(buf[0], 20);
+        assert_eq!(cached.inner().loads.get(), 2);
+        assert_eq!(cached.cached_bytes(), 100);
+        assert_eq!(
+            cached.load_witness(&mut buf, 0, 0, Source::Input),
+            IoResult::PartialLoaded {
+                loaded: 10,
+                available: 100
+            }
+        );
+        assert_eq!(buf[9], 9);
+        assert_eq!(cached.inner().loads.get(), 2);
+
+        // over the budget, every load is forwarded
+        let cached = CachedSyscalls::new(Witnesses::default(), 50);
+        for _ in 0..2 {
+            assert_eq!(
+                cached.load_witness(&mut [0; 128], 0, 0, Source::Input),
+                IoResult::FullyLoaded(100)
+            );
+        }
+        assert_eq!(cached.inner().loads.get(), 2);
+        assert_eq!(cached.cached_bytes(), 0);
+    }
+}
@@ -1,6 +1,7 @@
 // re-export to maintain compatible with old versions
 pub use crate::error::SysError;
 
+pub mod cache;
 mod internal;
 pub mod traits;
 
@@ -18,3 +19,5 @@ pub use simulator::*;
 mod stub;
 #[cfg(feature = "stub-syscalls")]
 pub use stub::*;
+#[cfg(feature = "stub-syscalls")]
+pub mod stub_processes;
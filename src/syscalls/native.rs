@@ -23,6 +23,18 @@ unsafe fn syscall(_a0: u64, _a1: u64, _a2: u64, _a3: u64, _a4: u64, _a5: u64, _a
     u64::MAX
 }
 
+/// [`SyscallImpls`](crate::syscalls::traits::SyscallImpls) issuing the CKB-VM
+/// syscalls directly, e.g. to wrap them in a
+/// [`CachedSyscalls`](crate::syscalls::cache::CachedSyscalls)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativeSyscalls;
+
+impl crate::syscalls::traits::SyscallImpls for NativeSyscalls {
+    fn syscall(&self, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, n: u64) -> u64 {
+        unsafe { syscall(a0, a1, a2, a3, a4, a5, n) }
+    }
+}
+
 /// Exit, this script will be terminated after the exit syscall.
 /// exit code `0` represents verification is success, others represent error code.
 pub fn exit(code: i8) -> ! {
@@ -414,6 +426,17 @@ pub fn debug(mut s: alloc::string::String) {
     }
 }
 
+/// Output debug message without allocating, e.g. when the heap is exhausted
+///
+/// # Arguments
+///
+/// * `s` - string to output
+pub fn debug_c_str(s: &CStr) {
+    unsafe {
+        syscall(s.as_ptr() as u64, 0, 0, 0, 0, 0, SYS_DEBUG);
+    }
+}
+
 /// Load cell data, read cell data
 ///
 /// Return the loaded data length or a syscall error
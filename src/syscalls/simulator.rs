@@ -184,6 +184,10 @@ pub fn debug(mut s: alloc::string::String) {
     sim::ckb_debug(c_str.as_ptr() as *const i8);
 }
 
+pub fn debug_c_str(s: &CStr) {
+    sim::ckb_debug(s.as_ptr());
+}
+
 pub fn load_cell_data_raw(
     buf_ptr: *mut u8,
     len: usize,
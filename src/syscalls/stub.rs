@@ -6,36 +6,103 @@ use crate::{
     error::SysError,
     syscalls::traits::{Bounds, SyscallImpls},
 };
-use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use core::cell::RefCell;
 use core::ffi::CStr;
 
+extern crate std;
+
 pub use crate::syscalls::internal::SpawnArgs;
 
-// We cannot use OnceCell here: some fuzzer engines(such as AFL++) reuse the
-// same process to run multiple iterations of fuzzed code. For each iteration,
-// a new object implementing SyscallImpls is likely required. In this case,
-// current value needs to be updated multiple times, which will not be possible
-// with OnceCell.
-static mut IMPLS: Option<Box<dyn SyscallImpls>> = None;
+// Each thread has its own impl, so tests running in parallel don't overwrite
+// each other's mock. We cannot use OnceCell here: some fuzzer engines(such as
+// AFL++) reuse the same process to run multiple iterations of fuzzed code. For
+// each iteration, a new object implementing SyscallImpls is likely required.
+std::thread_local! {
+    static IMPLS: RefCell<Option<Rc<dyn SyscallImpls>>> = const { RefCell::new(None) };
+}
 
 /// Initializes a new SyscallImpls trait impl.
+///
+/// The impl serves the syscalls of the current thread only, replacing the
+/// previous one. See [`with_impls`] to install one for a scope.
+///
+/// In ckb-std 1.0 and earlier, the impl served the whole process. Threads
+/// spawned by a test now start without one: they must install their own,
+/// otherwise their syscalls fail with [`NOT_INSTALLED`].
 pub fn init(impls: Box<dyn SyscallImpls>) {
-    unsafe { IMPLS = Some(impls) }
+    IMPLS.with(|current| *current.borrow_mut() = Some(Rc::from(impls)));
 }
 
-/// # Safety
+/// Run `f` with `impls` serving the syscalls of the current thread
 ///
-/// This shall be safe since CKB-VM uses a single threaded environment.
-fn get() -> &'static Box<dyn SyscallImpls> {
-    // https://doc.rust-lang.org/nightly/edition-guide/rust-2024/static-mut-references.html#no_std-one-time-initialization
-    let Some(impls) = (unsafe { &*&raw const IMPLS }) else {
-        panic!("No IMPLS provided!");
-    };
-    impls
+/// The previous impl, if any, is restored when `f` returns or panics, so
+/// scopes can nest.
+///
+/// # Example
+///
+/// ```ignore
+/// #[test]
+/// fn accepts_valid_args() {
+///     let result = ckb_std::syscalls::with_impls(MockTx::new(...), || program_entry());
+///     assert_eq!(result, 0);
+/// }
+/// ```
+pub fn with_impls<S: SyscallImpls + 'static, R>(impls: S, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Rc<dyn SyscallImpls>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            // the thread local is gone if the thread is exiting
+            let _ = IMPLS.try_with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let impls: Rc<dyn SyscallImpls> = Rc::new(impls);
+    let _restore = Restore(IMPLS.with(|current| current.borrow_mut().replace(impls)));
+    f()
+}
+
+/// Whether the current thread has a SyscallImpls installed
+pub fn installed() -> bool {
+    IMPLS.with(|current| current.borrow().is_some())
+}
+
+/// Error of the syscalls returning a `Result` when no SyscallImpls is
+/// installed on the current thread, see [`init`] and [`with_impls`]
+///
+/// `exec` returns a raw error number, [`NOT_INSTALLED_ERRNO`], which
+/// `high_level::exec_cell` turns back into this error. The syscalls without
+/// an error result, `debug`, `debug_c_str`, `exit`, `current_cycles`,
+/// `process_id` and `inherited_fds`, panic instead, and so do the C
+/// syscalls of `stub-c-syscalls`.
+pub const NOT_INSTALLED: SysError = SysError::Unknown(NOT_INSTALLED_ERRNO);
+
+/// Error number of [`NOT_INSTALLED`], never returned by CKB
+pub const NOT_INSTALLED_ERRNO: u64 = u64::MAX;
+
+// The impl is cloned out of the thread local, so it can install another one
+// while serving a syscall.
+fn try_get() -> Result<Rc<dyn SyscallImpls>, SysError> {
+    IMPLS
+        .with(|current| current.borrow().clone())
+        .ok_or(NOT_INSTALLED)
+}
+
+fn get() -> Rc<dyn SyscallImpls> {
+    match try_get() {
+        Ok(impls) => impls,
+        Err(_) => panic!(
+            "no SyscallImpls installed on thread {:?}, call ckb_std::syscalls::init or \
+             ckb_std::syscalls::with_impls before running syscalls with the stub-syscalls feature",
+            std::thread::current().name().unwrap_or("<unnamed>")
+        ),
+    }
 }
 
 pub fn close(fd: u64) -> Result<(), SysError> {
-    get().close(fd)?;
+    try_get()?.close(fd)?;
     Ok(())
 }
 
@@ -47,8 +114,16 @@ pub fn debug(s: String) {
     get().debug_s(s)
 }
 
+pub fn debug_c_str(s: &CStr) {
+    get().debug(s)
+}
+
 pub fn exec(index: usize, source: Source, place: usize, bounds: usize, argv: &[&CStr]) -> u64 {
-    let result = get().exec(
+    let impls = match try_get() {
+        Ok(impls) => impls,
+        Err(_) => return NOT_INSTALLED_ERRNO,
+    };
+    let result = impls.exec(
         index,
         source,
         Place::try_from(place as u64).unwrap(),
@@ -75,7 +150,7 @@ pub fn load_block_extension(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    get()
+    try_get()?
         .load_block_extension(buf, offset, index, source)
         .into()
 }
@@ -86,7 +161,7 @@ pub fn load_cell(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    get().load_cell(buf, offset, index, source).into()
+    try_get()?.load_cell(buf, offset, index, source).into()
 }
 
 pub fn load_cell_by_field(
@@ -96,7 +171,7 @@ pub fn load_cell_by_field(
     source: Source,
     field: CellField,
 ) -> Result<usize, SysError> {
-    get()
+    try_get()?
         .load_cell_by_field(buf, offset, index, source, field)
         .into()
 }
@@ -109,7 +184,8 @@ pub fn load_cell_code(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    let result = get().load_cell_code(buf_ptr, len, content_offset, content_size, index, source);
+    let result =
+        try_get()?.load_cell_code(buf_ptr, len, content_offset, content_size, index, source);
     match result {
         Ok(()) => Ok(len),
         Err(e) => Err(e.into()),
@@ -122,7 +198,7 @@ pub fn load_cell_data(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    get().load_cell_data(buf, offset, index, source).into()
+    try_get()?.load_cell_data(buf, offset, index, source).into()
 }
 
 pub fn load_cell_data_raw(
@@ -134,7 +210,7 @@ pub fn load_cell_data_raw(
 ) -> Result<usize, SysError> {
     let buf = unsafe { core::slice::from_raw_parts_mut(buf_ptr, len) };
 
-    get().load_cell_data(buf, offset, index, source).into()
+    try_get()?.load_cell_data(buf, offset, index, source).into()
 }
 
 pub fn load_header(
@@ -143,7 +219,7 @@ pub fn load_header(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    get().load_header(buf, offset, index, source).into()
+    try_get()?.load_header(buf, offset, index, source).into()
 }
 
 pub fn load_header_by_field(
@@ -153,7 +229,7 @@ pub fn load_header_by_field(
     source: Source,
     field: HeaderField,
 ) -> Result<usize, SysError> {
-    get()
+    try_get()?
         .load_header_by_field(buf, offset, index, source, field)
         .into()
 }
@@ -164,7 +240,7 @@ pub fn load_input(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    get().load_input(buf, offset, index, source).into()
+    try_get()?.load_input(buf, offset, index, source).into()
 }
 
 pub fn load_input_by_field(
@@ -174,25 +250,25 @@ pub fn load_input_by_field(
     source: Source,
     field: InputField,
 ) -> Result<usize, SysError> {
-    get()
+    try_get()?
         .load_input_by_field(buf, offset, index, source, field)
         .into()
 }
 
 pub fn load_script(buf: &mut [u8], offset: usize) -> Result<usize, SysError> {
-    get().load_script(buf, offset).into()
+    try_get()?.load_script(buf, offset).into()
 }
 
 pub fn load_script_hash(buf: &mut [u8], offset: usize) -> Result<usize, SysError> {
-    get().load_script_hash(buf, offset).into()
+    try_get()?.load_script_hash(buf, offset).into()
 }
 
 pub fn load_transaction(buf: &mut [u8], offset: usize) -> Result<usize, SysError> {
-    get().load_transaction(buf, offset).into()
+    try_get()?.load_transaction(buf, offset).into()
 }
 
 pub fn load_tx_hash(buf: &mut [u8], offset: usize) -> Result<usize, SysError> {
-    get().load_tx_hash(buf, offset).into()
+    try_get()?.load_tx_hash(buf, offset).into()
 }
 
 pub fn load_witness(
@@ -201,11 +277,11 @@ pub fn load_witness(
     index: usize,
     source: Source,
 ) -> Result<usize, SysError> {
-    get().load_witness(buf, offset, index, source).into()
+    try_get()?.load_witness(buf, offset, index, source).into()
 }
 
 pub fn pipe() -> Result<(u64, u64), SysError> {
-    let pipes = get().pipe()?;
+    let pipes = try_get()?.pipe()?;
     Ok(pipes)
 }
 
@@ -214,7 +290,7 @@ pub fn process_id() -> u64 {
 }
 
 pub fn read(fd: u64, buffer: &mut [u8]) -> Result<usize, SysError> {
-    let read = get().read(fd, buffer)?;
+    let read = try_get()?.read(fd, buffer)?;
     Ok(read)
 }
 
@@ -242,7 +318,7 @@ pub fn spawn(
             i += 1;
         }
     }
-    let process_id = get().spawn(
+    let process_id = try_get()?.spawn(
         index,
         source,
         Place::try_from(place as u64).unwrap(),
@@ -255,7 +331,7 @@ pub fn spawn(
 }
 
 pub fn vm_version() -> Result<u64, SysError> {
-    let version = get().vm_version();
+    let version = try_get()?.vm_version();
     // Personally I think this logic does not make sense but we will just keep
     // ckb-std's convention.
     match version {
@@ -265,12 +341,12 @@ pub fn vm_version() -> Result<u64, SysError> {
 }
 
 pub fn wait(pid: u64) -> Result<i8, SysError> {
-    let exit_code = get().wait(pid)?;
+    let exit_code = try_get()?.wait(pid)?;
     Ok(exit_code)
 }
 
 pub fn write(fd: u64, buffer: &[u8]) -> Result<usize, SysError> {
-    let written = get().write(fd, buffer)?;
+    let written = try_get()?.write(fd, buffer)?;
     Ok(written)
 }
 
@@ -288,7 +364,7 @@ pub extern "C" fn __internal_syscall(
     a5: core::ffi::c_long,
 ) -> core::ffi::c_long {
     crate::syscalls::traits::syscall_to_impls(
-        &**get(),
+        &*get(),
         n as u64,
         a0 as u64,
         a1 as u64,
@@ -298,3 +374,83 @@ pub extern "C" fn __internal_syscall(
         a5 as u64,
     ) as core::ffi::c_long
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscalls::traits::IoResult;
+    use std::sync::{Arc, Barrier};
+
+    // Serves a transaction hash filled with its tag.
+    struct Tagged(u8);
+
+    impl SyscallImpls for Tagged {
+        fn debug(&self, _s: &CStr) {}
+
+        fn load_tx_hash(&self, buf: &mut [u8], _offset: usize) -> IoResult {
+            let len = buf.len().min(32);
+            buf[..len].fill(self.0);
+            IoResult::FullyLoaded(len)
+        }
+    }
+
+    fn tag() -> Result<u8, SysError> {
+        let mut hash = [0; 32];
+        load_tx_hash(&mut hash, 0)?;
+        Ok(hash[0])
+    }
+
+    #[test]
+    fn reports_missing_impls() {
+        assert!(!installed());
+        assert_eq!(tag(), Err(NOT_INSTALLED));
+        assert_eq!(wait(1), Err(NOT_INSTALLED));
+        assert_eq!(exec(0, Source::CellDep, 0, 0, &[]), NOT_INSTALLED_ERRNO);
+    }
+
+    #[test]
+    #[should_panic(expected = "no SyscallImpls installed")]
+    fn infallible_syscalls_panic_without_impls() {
+        process_id();
+    }
+
+    #[test]
+    fn with_impls_nests_and_restores() {
+        with_impls(Tagged(1), || {
+            assert_eq!(tag(), Ok(1));
+            assert_eq!(with_impls(Tagged(2), tag), Ok(2));
+            assert_eq!(tag(), Ok(1));
+            let unwound =
+                std::panic::catch_unwind(|| with_impls(Tagged(3), || panic!("unwinding")));
+            assert!(unwound.is_err());
+            assert_eq!(tag(), Ok(1));
+        });
+        assert!(!installed());
+        init(Box::new(Tagged(4)));
+        assert_eq!(with_impls(Tagged(5), tag), Ok(5));
+        assert_eq!(tag(), Ok(4));
+    }
+
+    #[test]
+    fn impls_are_per_thread() {
+        init(Box::new(Tagged(0)));
+        let barrier = Arc::new(Barrier::new(4));
+        let threads: Vec<_> = (1..4)
+            .map(|id| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    with_impls(Tagged(id), || {
+                        // every thread has installed its impl
+                        barrier.wait();
+                        tag()
+                    })
+                })
+            })
+            .collect();
+        barrier.wait();
+        assert_eq!(tag(), Ok(0));
+        let tags: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(tags, [Ok(1), Ok(2), Ok(3)]);
+        assert_eq!(std::thread::spawn(tag).join().unwrap(), Err(NOT_INSTALLED));
+    }
+}
@@ -0,0 +1,997 @@
+//! Native child processes for the stub backend.
+//!
+//! Under `stub-syscalls`, `spawn`, `exec`, pipes and `wait` have nothing to
+//! run: the programs are RISC-V binaries in cells. [`NativeProcesses`] maps
+//! code hashes to native entry functions, typically the `program_entry` of
+//! each contract crate, and runs a root program whose syscalls spawn them:
+//!
+//! ```ignore
+//! use ckb_std::syscalls::stub_processes::NativeProcesses;
+//!
+//! #[test]
+//! fn caller_spawns_callee() {
+//!     let code = NativeProcesses::new()
+//!         .program(CALLEE_CODE_HASH, callee::program_entry)
+//!         .run(MockTx::new(...), caller::program_entry);
+//!     assert_eq!(code, 0);
+//! }
+//! ```
+//!
+//! Every process runs on its own thread, but as in CKB-VM only one of them
+//! runs at a time: a process keeps running until it blocks in `read`,
+//! `write` or `wait`, or exits, then the next process able to make progress
+//! runs. So the interleaving is deterministic. Pipes follow CKB semantics: a
+//! `write` returns once the reader consumed all of the data, an empty write
+//! included, which the reader sees as a read of 0 bytes, and reading a pipe
+//! whose write end is closed fails with `OtherEndClosed`. Each process
+//! sees its own [`env::argv`](crate::env::argv), `process_id` and
+//! `inherited_fds`, `exit` ends the calling process and `exec` replaces its
+//! program.
+//!
+//! A panic ends the process with the panic exit code, as on chain, and its
+//! message is printed with `debug` like the panic handler of
+//! [`entry!`](crate::entry) does. Only the panics of the programs are
+//! printed, not those of other threads with impls installed. When the root
+//! program ends, the remaining children are terminated. If every process is
+//! blocked, `run` panics.
+
+use crate::{
+    asserts::{DEFAULT_PANIC_EXIT_CODE, panic_exit_code, set_panic_exit_code},
+    ckb_constants::{CellField, HeaderField, InputField, Place, Source},
+    env::{Arg, set_argv},
+    syscalls::{
+        debug,
+        traits::{Bounds, Error, IoResult, SyscallImpls},
+        with_impls,
+    },
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    ffi::CString,
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{cell::Cell, ffi::CStr};
+
+extern crate std;
+
+use std::{
+    panic::{self, AssertUnwindSafe, catch_unwind, resume_unwind},
+    sync::{Condvar, Mutex, MutexGuard, Once},
+    thread::{self, JoinHandle},
+};
+
+/// Entry function of a native program, the exit code is returned
+pub type Program = fn() -> i8;
+
+/// Processes, root included, existing at once
+pub const MAX_PROCESSES: usize = 16;
+
+/// File descriptors created by all processes
+pub const MAX_FDS: u64 = 64;
+
+const ROOT: u64 = 0;
+
+// same numbering as CKB-VM: pipes take two consecutive fds, the read end is
+// even and the write end odd
+const FIRST_FD: u64 = 2;
+
+/// Registry of native programs, see the [module](self) docs
+#[derive(Clone, Default)]
+pub struct NativeProcesses {
+    programs: Vec<([u8; 32], Program)>,
+}
+
+impl NativeProcesses {
+    /// No programs
+    pub fn new() -> Self {
+        NativeProcesses::default()
+    }
+
+    /// Run `program` for the cells whose data hash or type hash is
+    /// `code_hash`
+    ///
+    /// The `bounds` of `spawn` and `exec` are ignored, the program stands for
+    /// the whole cell. They fail with `ItemMissing` for cells without a
+    /// program.
+    pub fn program(mut self, code_hash: [u8; 32], program: Program) -> Self {
+        match self
+            .programs
+            .iter_mut()
+            .find(|(hash, _)| *hash == code_hash)
+        {
+            Some(entry) => entry.1 = program,
+            None => self.programs.push((code_hash, program)),
+        }
+        self
+    }
+
+    /// Run `root` as process 0 on the current thread, with `impls` serving
+    /// the other syscalls of all processes, and return its exit code
+    ///
+    /// `impls` is shared by the process threads, so it must be `Send` and
+    /// `Sync`.
+    pub fn run<S>(&self, impls: S, root: Program) -> i8
+    where
+        S: SyscallImpls + Send + Sync + 'static,
+    {
+        self.run_status(impls, root).code
+    }
+
+    /// Like [`run`](Self::run), also telling whether `root` panicked
+    pub fn run_status<S>(&self, impls: S, root: Program) -> ExitStatus
+    where
+        S: SyscallImpls + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            impls,
+            programs: self.programs.clone(),
+            state: Mutex::new(State::new()),
+            wakeup: Condvar::new(),
+        });
+        let _terminate = Terminate(&*shared);
+        report_panics();
+        let process = Process {
+            shared: shared.clone(),
+            pid: ROOT,
+        };
+        match with_impls(process, || run_program(root, Vec::new())) {
+            Outcome::Exit(code) => ExitStatus {
+                code,
+                panicked: false,
+            },
+            Outcome::Panic(code) => ExitStatus {
+                code,
+                panicked: true,
+            },
+            Outcome::Terminated => unreachable!("the root process is never terminated"),
+            Outcome::Deadlock => {
+                panic!("deadlock: every process is blocked in read, write or wait")
+            }
+        }
+    }
+}
+
+/// How the root program ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus {
+    /// Exit code, the panic exit code if it panicked
+    pub code: i8,
+    /// Whether it panicked, rather than returning or calling `exit`
+    pub panicked: bool,
+}
+
+std::thread_local! {
+    // Whether the thread is running a program, the panics of other threads
+    // aren't printed with `debug`
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Print panic messages with `debug` before the program unwinds, the panic
+// hook is kept so they still show on stderr. Exit, exec and termination
+// unwind with `resume_unwind`, which doesn't run the hook.
+fn report_panics() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if RUNNING.with(Cell::get) {
+                debug(format!("{}", info));
+            }
+            previous(info)
+        }));
+    });
+}
+
+// Carried by the unwinding ending a program.
+enum Unwind {
+    Exit(i8),
+    Exec(Program, Vec<CString>),
+    Terminated,
+    Deadlock,
+}
+
+enum Outcome {
+    Exit(i8),
+    Panic(i8),
+    Terminated,
+    Deadlock,
+}
+
+// Run `program`, and the programs it execs.
+fn run_program(mut program: Program, mut argv: Vec<CString>) -> Outcome {
+    loop {
+        // SAFETY: argv is reset before the strings are dropped.
+        let args: Vec<Arg> = argv
+            .iter()
+            .map(|arg| Arg::from(unsafe { &*(arg.as_c_str() as *const CStr) }))
+            .collect();
+        unsafe { set_argv(&*(args.as_slice() as *const [Arg])) };
+        let running = RUNNING.replace(true);
+        let result = catch_unwind(AssertUnwindSafe(program));
+        RUNNING.set(running);
+        unsafe { set_argv(&[]) };
+        let payload = match result {
+            Ok(code) => return Outcome::Exit(code),
+            Err(payload) => payload,
+        };
+        match payload.downcast::<Unwind>() {
+            Ok(unwind) => match *unwind {
+                Unwind::Exit(code) => return Outcome::Exit(code),
+                Unwind::Exec(next, next_argv) => {
+                    program = next;
+                    argv = next_argv;
+                }
+                Unwind::Terminated => return Outcome::Terminated,
+                Unwind::Deadlock => return Outcome::Deadlock,
+            },
+            // the panic hook has printed the message, the code set by a
+            // failed `ckb_std::assert!` is reset for the next program
+            Err(_) => {
+                let code = panic_exit_code();
+                set_panic_exit_code(DEFAULT_PANIC_EXIT_CODE);
+                return Outcome::Panic(code);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Runnable,
+    Read(u64),
+    Write(u64),
+    Wait(u64),
+    Exited(i8),
+}
+
+struct ProcessState {
+    status: Status,
+    inherited_fds: Vec<u64>,
+}
+
+struct State {
+    processes: BTreeMap<u64, ProcessState>,
+    next_pid: u64,
+    threads: Vec<JoinHandle<()>>,
+    // owner of each open fd
+    fds: BTreeMap<u64, u64>,
+    next_fd: u64,
+    // by read fd
+    pipes: BTreeMap<u64, Pipe>,
+    running: u64,
+    deadlock: bool,
+    terminating: bool,
+}
+
+// A write in progress, possibly empty.
+#[derive(Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    pending: bool,
+}
+
+impl State {
+    fn new() -> Self {
+        let mut processes = BTreeMap::new();
+        processes.insert(
+            ROOT,
+            ProcessState {
+                status: Status::Runnable,
+                inherited_fds: Vec::new(),
+            },
+        );
+        State {
+            processes,
+            next_pid: ROOT + 1,
+            threads: Vec::new(),
+            fds: BTreeMap::new(),
+            next_fd: FIRST_FD,
+            pipes: BTreeMap::new(),
+            running: ROOT,
+            deadlock: false,
+            terminating: false,
+        }
+    }
+
+    fn owns(&self, pid: u64, fd: u64) -> bool {
+        self.fds.get(&fd) == Some(&pid)
+    }
+
+    fn ready(&self, status: Status) -> bool {
+        match status {
+            Status::Runnable => true,
+            Status::Read(fd) => self.pipes[&fd].pending || !self.fds.contains_key(&(fd + 1)),
+            Status::Write(fd) => {
+                !self.pipes[&(fd - 1)].pending || !self.fds.contains_key(&(fd - 1))
+            }
+            // the process may have been waited for by another one
+            Status::Wait(pid) => self
+                .processes
+                .get(&pid)
+                .is_none_or(|process| matches!(process.status, Status::Exited(_))),
+            Status::Exited(_) => false,
+        }
+    }
+
+    // Hand over to the next process able to make progress, in pid order
+    // after `pid`.
+    fn switch(&mut self, pid: u64) {
+        let after = self.processes.range(pid + 1..);
+        let before = self.processes.range(..=pid);
+        let next = after
+            .chain(before)
+            .find(|(_, process)| self.ready(process.status))
+            .map(|(pid, _)| *pid);
+        match next {
+            Some(next) => self.running = next,
+            None => {
+                self.deadlock = true;
+                self.running = ROOT;
+            }
+        }
+    }
+}
+
+struct Shared<S> {
+    impls: S,
+    programs: Vec<([u8; 32], Program)>,
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+impl<S: SyscallImpls> Shared<S> {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // Wait until `pid` runs again.
+    fn park<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, State>,
+        pid: u64,
+    ) -> Result<MutexGuard<'a, State>, Error> {
+        self.wakeup.notify_all();
+        loop {
+            if state.terminating {
+                drop(state);
+                // destructors of a terminated program can't unwind again
+                if thread::panicking() {
+                    return Err(Error::OtherEndClosed);
+                }
+                resume_unwind(Box::new(Unwind::Terminated));
+            }
+            if state.deadlock && pid == ROOT {
+                drop(state);
+                resume_unwind(Box::new(Unwind::Deadlock));
+            }
+            if state.running == pid {
+                return Ok(state);
+            }
+            state = self
+                .wakeup
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    // Block `pid` until `status` is ready.
+    fn block<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, State>,
+        pid: u64,
+        status: Status,
+    ) -> Result<MutexGuard<'a, State>, Error> {
+        state.processes.get_mut(&pid).unwrap().status = status;
+        state.switch(pid);
+        let mut state = self.park(state, pid)?;
+        state.processes.get_mut(&pid).unwrap().status = Status::Runnable;
+        Ok(state)
+    }
+
+    fn exit(&self, pid: u64, code: i8) {
+        let mut state = self.lock();
+        state.processes.get_mut(&pid).unwrap().status = Status::Exited(code);
+        state.fds.retain(|_, owner| *owner != pid);
+        state.switch(pid);
+        self.wakeup.notify_all();
+    }
+
+    fn program(&self, index: usize, source: Source, place: Place) -> Result<Program, Error> {
+        assert!(
+            place == Place::Cell,
+            "native processes only run programs from cells"
+        );
+        for field in [CellField::DataHash, CellField::TypeHash] {
+            let mut hash = [0u8; 32];
+            match self
+                .impls
+                .load_cell_by_field(&mut hash, 0, index, source, field)
+            {
+                IoResult::Error(Error::ItemMissing) if field == CellField::TypeHash => {}
+                IoResult::Error(err) => return Err(err),
+                _ => {
+                    if let Some((_, program)) = self.programs.iter().find(|(h, _)| *h == hash) {
+                        return Ok(*program);
+                    }
+                }
+            }
+        }
+        Err(Error::ItemMissing)
+    }
+}
+
+fn child_main<S: SyscallImpls + Send + Sync + 'static>(
+    shared: Arc<Shared<S>>,
+    pid: u64,
+    program: Program,
+    argv: Vec<CString>,
+) {
+    let process = Process {
+        shared: shared.clone(),
+        pid,
+    };
+    with_impls(process, || {
+        if shared.park(shared.lock(), pid).is_err() {
+            return;
+        }
+        if let Outcome::Exit(code) | Outcome::Panic(code) = run_program(program, argv) {
+            shared.exit(pid, code);
+        }
+    });
+}
+
+// Terminates the children once the root program is done.
+struct Terminate<'a, S>(&'a Shared<S>);
+
+impl<S> Drop for Terminate<'_, S> {
+    fn drop(&mut self) {
+        let threads: Vec<_> = {
+            let mut state = self.0.state.lock().unwrap_or_else(|err| err.into_inner());
+            state.terminating = true;
+            core::mem::take(&mut state.threads)
+        };
+        self.0.wakeup.notify_all();
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The syscalls of one process.
+struct Process<S> {
+    shared: Arc<Shared<S>>,
+    pid: u64,
+}
+
+impl<S: SyscallImpls + Send + Sync + 'static> SyscallImpls for Process<S> {
+    fn syscall(&self, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, n: u64) -> u64 {
+        self.shared.impls.syscall(a0, a1, a2, a3, a4, a5, n)
+    }
+
+    fn syscall_load(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        a3: u64,
+        a4: u64,
+        a5: u64,
+        syscall_num: u64,
+    ) -> IoResult {
+        self.shared
+            .impls
+            .syscall_load(buf, offset, a3, a4, a5, syscall_num)
+    }
+
+    fn debug(&self, s: &CStr) {
+        self.shared.impls.debug(s)
+    }
+
+    fn exit(&self, code: i8) -> ! {
+        resume_unwind(Box::new(Unwind::Exit(code)))
+    }
+
+    fn load_cell(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        self.shared.impls.load_cell(buf, offset, index, source)
+    }
+
+    fn load_cell_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: CellField,
+    ) -> IoResult {
+        self.shared
+            .impls
+            .load_cell_by_field(buf, offset, index, source, field)
+    }
+
+    fn load_cell_code(
+        &self,
+        buf_ptr: *mut u8,
+        len: usize,
+        content_offset: usize,
+        content_size: usize,
+        index: usize,
+        source: Source,
+    ) -> Result<(), Error> {
+        self.shared
+            .impls
+            .load_cell_code(buf_ptr, len, content_offset, content_size, index, source)
+    }
+
+    fn load_cell_data(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        self.shared.impls.load_cell_data(buf, offset, index, source)
+    }
+
+    fn load_header(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        self.shared.impls.load_header(buf, offset, index, source)
+    }
+
+    fn load_header_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: HeaderField,
+    ) -> IoResult {
+        self.shared
+            .impls
+            .load_header_by_field(buf, offset, index, source, field)
+    }
+
+    fn load_input(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        self.shared.impls.load_input(buf, offset, index, source)
+    }
+
+    fn load_input_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: InputField,
+    ) -> IoResult {
+        self.shared
+            .impls
+            .load_input_by_field(buf, offset, index, source, field)
+    }
+
+    fn load_script(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        self.shared.impls.load_script(buf, offset)
+    }
+
+    fn load_script_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        self.shared.impls.load_script_hash(buf, offset)
+    }
+
+    fn load_transaction(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        self.shared.impls.load_transaction(buf, offset)
+    }
+
+    fn load_tx_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        self.shared.impls.load_tx_hash(buf, offset)
+    }
+
+    fn load_witness(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        self.shared.impls.load_witness(buf, offset, index, source)
+    }
+
+    fn vm_version(&self) -> u64 {
+        self.shared.impls.vm_version()
+    }
+
+    fn current_cycles(&self) -> u64 {
+        self.shared.impls.current_cycles()
+    }
+
+    fn exec(
+        &self,
+        index: usize,
+        source: Source,
+        place: Place,
+        _bounds: Bounds,
+        argv: &[&CStr],
+    ) -> Result<(), Error> {
+        let program = self.shared.program(index, source, place)?;
+        let argv = argv.iter().map(|arg| CString::from(*arg)).collect();
+        resume_unwind(Box::new(Unwind::Exec(program, argv)))
+    }
+
+    fn spawn(
+        &self,
+        index: usize,
+        source: Source,
+        place: Place,
+        _bounds: Bounds,
+        argv: &[&CStr],
+        inherited_fds: &[u64],
+    ) -> Result<u64, Error> {
+        let program = self.shared.program(index, source, place)?;
+        let mut state = self.shared.lock();
+        let alive = state
+            .processes
+            .values()
+            .filter(|process| !matches!(process.status, Status::Exited(_)))
+            .count();
+        if alive >= MAX_PROCESSES {
+            return Err(Error::MaxVmsSpawned);
+        }
+        if !inherited_fds.iter().all(|fd| state.owns(self.pid, *fd)) {
+            return Err(Error::InvalidFd);
+        }
+        let pid = state.next_pid;
+        state.next_pid += 1;
+        for fd in inherited_fds {
+            state.fds.insert(*fd, pid);
+        }
+        let shared = self.shared.clone();
+        let argv = argv.iter().map(|arg| CString::from(*arg)).collect();
+        let thread = thread::Builder::new()
+            .name(format!("ckb-process-{}", pid))
+            .spawn(move || child_main(shared, pid, program, argv))
+            .expect("spawn process thread");
+        state.processes.insert(
+            pid,
+            ProcessState {
+                status: Status::Runnable,
+                inherited_fds: inherited_fds.to_vec(),
+            },
+        );
+        state.threads.push(thread);
+        Ok(pid)
+    }
+
+    fn pipe(&self) -> Result<(u64, u64), Error> {
+        let mut state = self.shared.lock();
+        if state.next_fd - FIRST_FD + 2 > MAX_FDS {
+            return Err(Error::MaxFdsCreated);
+        }
+        let fd = state.next_fd;
+        state.next_fd += 2;
+        state.fds.insert(fd, self.pid);
+        state.fds.insert(fd + 1, self.pid);
+        state.pipes.insert(fd, Pipe::default());
+        Ok((fd, fd + 1))
+    }
+
+    fn inherited_fds(&self, fds: &mut [u64]) -> Result<usize, Error> {
+        let state = self.shared.lock();
+        let inherited = &state.processes[&self.pid].inherited_fds;
+        let len = inherited.len().min(fds.len());
+        fds[..len].copy_from_slice(&inherited[..len]);
+        Ok(inherited.len())
+    }
+
+    fn read(&self, fd: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut state = self.shared.lock();
+        if fd & 1 != 0 || !state.owns(self.pid, fd) {
+            return Err(Error::InvalidFd);
+        }
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let pipe = state.pipes.get_mut(&fd).unwrap();
+            if pipe.pending {
+                let len = pipe.data.len().min(buffer.len());
+                for (dst, src) in buffer.iter_mut().zip(pipe.data.drain(..len)) {
+                    *dst = src;
+                }
+                pipe.pending = !pipe.data.is_empty();
+                return Ok(len);
+            }
+            if !state.fds.contains_key(&(fd + 1)) {
+                return Err(Error::OtherEndClosed);
+            }
+            state = self.shared.block(state, self.pid, Status::Read(fd))?;
+        }
+    }
+
+    fn write(&self, fd: u64, buffer: &[u8]) -> Result<usize, Error> {
+        let mut state = self.shared.lock();
+        if fd & 1 != 1 || !state.owns(self.pid, fd) {
+            return Err(Error::InvalidFd);
+        }
+        if !state.fds.contains_key(&(fd - 1)) {
+            return Err(Error::OtherEndClosed);
+        }
+        let pipe = state.pipes.get_mut(&(fd - 1)).unwrap();
+        pipe.data.extend(buffer.iter().copied());
+        pipe.pending = true;
+        while !state.ready(Status::Write(fd)) {
+            state = self.shared.block(state, self.pid, Status::Write(fd))?;
+        }
+        let pipe = state.pipes.get_mut(&(fd - 1)).unwrap();
+        if !pipe.pending {
+            return Ok(buffer.len());
+        }
+        // the reader closed its end before consuming everything
+        let unread = core::mem::take(pipe).data.len();
+        match buffer.len() - unread {
+            0 => Err(Error::OtherEndClosed),
+            written => Ok(written),
+        }
+    }
+
+    fn close(&self, fd: u64) -> Result<(), Error> {
+        let mut state = self.shared.lock();
+        if !state.owns(self.pid, fd) {
+            return Err(Error::InvalidFd);
+        }
+        state.fds.remove(&fd);
+        Ok(())
+    }
+
+    fn wait(&self, pid: u64) -> Result<i8, Error> {
+        let mut state = self.shared.lock();
+        if pid == self.pid || !state.processes.contains_key(&pid) {
+            return Err(Error::WaitFailure);
+        }
+        while !state.ready(Status::Wait(pid)) {
+            state = self.shared.block(state, self.pid, Status::Wait(pid))?;
+        }
+        match state.processes.remove(&pid).map(|process| process.status) {
+            Some(Status::Exited(code)) => Ok(code),
+            _ => Err(Error::WaitFailure),
+        }
+    }
+
+    fn process_id(&self) -> u64 {
+        self.pid
+    }
+
+    fn load_block_extension(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        self.shared
+            .impls
+            .load_block_extension(buf, offset, index, source)
+    }
+
+    fn debug_s(&self, s: String) {
+        self.shared.impls.debug_s(s)
+    }
+}
+
+#[cfg(all(test, feature = "ckb-types"))]
+mod tests {
+    use super::*;
+    use crate::{
+        error::SysError,
+        high_level::{exec_cell, inherited_fds, spawn_cell},
+        syscalls::{close, exit, pipe, process_id, read, wait, write},
+    };
+    use ckb_types::core::ScriptHashType;
+
+    // Cell deps whose data hashes are [1; 32], [2; 32]...
+    struct CellDeps {
+        debug: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SyscallImpls for CellDeps {
+        fn debug(&self, s: &CStr) {
+            self.debug
+                .lock()
+                .unwrap()
+                .push(s.to_string_lossy().into_owned());
+        }
+
+        fn load_cell_by_field(
+            &self,
+            buf: &mut [u8],
+            _offset: usize,
+            index: usize,
+            source: Source,
+            field: CellField,
+        ) -> IoResult {
+            match (source, field) {
+                (Source::CellDep, _) if index >= 4 => IoResult::Error(Error::IndexOutOfBound),
+                (Source::CellDep, CellField::DataHash) => {
+                    buf[..32].copy_from_slice(&[index as u8 + 1; 32]);
+                    IoResult::FullyLoaded(32)
+                }
+                _ => IoResult::Error(Error::ItemMissing),
+            }
+        }
+    }
+
+    fn run(root: Program) -> (ExitStatus, Vec<String>) {
+        let debug = Arc::new(Mutex::new(Vec::new()));
+        let status = NativeProcesses::new()
+            .program([1; 32], writer)
+            .program([2; 32], exec_caller)
+            .program([3; 32], exec_callee)
+            .program([4; 32], blocked_reader)
+            .run_status(
+                CellDeps {
+                    debug: debug.clone(),
+                },
+                root,
+            );
+        let debug = core::mem::take(&mut *debug.lock().unwrap());
+        (status, debug)
+    }
+
+    fn spawn(code_hash: u8, argv: &[&CStr], fds: &[u64]) -> u64 {
+        spawn_cell(&[code_hash; 32], ScriptHashType::Data, argv, fds).unwrap()
+    }
+
+    fn args() -> Vec<&'static CStr> {
+        crate::env::argv().iter().map(|arg| &**arg).collect()
+    }
+
+    fn writer() -> i8 {
+        assert_eq!(process_id(), 1);
+        assert_eq!(args(), [c"writer"]);
+        let [output, closed] = inherited_fds()[..] else {
+            panic!("expected two fds")
+        };
+        assert_eq!(write(output, b"hello"), Ok(5));
+        assert_eq!(write(output, &[]), Ok(0));
+        // the root closed the read end of the other pipe
+        assert_eq!(write(closed, b"lost"), Err(SysError::OtherEndClosed));
+        3
+    }
+
+    fn exec_caller() -> i8 {
+        let Err(err) = exec_cell(&[3; 32], ScriptHashType::Data, &[c"callee"]);
+        panic!("exec failed: {:?}", err)
+    }
+
+    fn exec_callee() -> i8 {
+        assert_eq!(args(), [c"callee"]);
+        exit(7)
+    }
+
+    fn blocked_reader() -> i8 {
+        let _ = read(inherited_fds()[0], &mut [0; 8]);
+        unreachable!("the root never writes")
+    }
+
+    #[test]
+    fn pipes_carry_data_and_empty_writes() {
+        let (status, debug) = run(|| {
+            let (output, input) = pipe().unwrap();
+            let (closed, lost) = pipe().unwrap();
+            close(closed).unwrap();
+            let pid = spawn(1, &[c"writer"], &[input, lost]);
+            // the write ends belong to the child now
+            assert_eq!(close(input), Err(SysError::InvalidFd));
+            let mut buf = [0; 16];
+            assert_eq!(read(output, &mut buf), Ok(5));
+            assert_eq!(&buf[..5], b"hello");
+            assert_eq!(read(output, &mut buf), Ok(0));
+            assert_eq!(read(output, &mut buf), Err(SysError::OtherEndClosed));
+            assert_eq!(wait(pid), Ok(3));
+            assert_eq!(wait(pid), Err(SysError::WaitFailure));
+            0
+        });
+        assert_eq!(
+            status,
+            ExitStatus {
+                code: 0,
+                panicked: false
+            },
+            "{:?}",
+            debug
+        );
+    }
+
+    #[test]
+    fn exec_replaces_the_program() {
+        let (status, debug) = run(|| {
+            let pid = spawn(2, &[], &[]);
+            wait(pid).unwrap()
+        });
+        assert_eq!(
+            status,
+            ExitStatus {
+                code: 7,
+                panicked: false
+            },
+            "{:?}",
+            debug
+        );
+    }
+
+    #[test]
+    fn panics_end_the_process() {
+        let (status, debug) = run(|| {
+            let (output, input) = pipe().unwrap();
+            // panics as it expects two fds
+            let pid = spawn(1, &[c"writer"], &[input]);
+            assert_eq!(wait(pid), Ok(DEFAULT_PANIC_EXIT_CODE));
+            close(output).unwrap();
+            panic!("root panicked")
+        });
+        assert_eq!(
+            status,
+            ExitStatus {
+                code: DEFAULT_PANIC_EXIT_CODE,
+                panicked: true
+            }
+        );
+        assert_eq!(debug.len(), 2);
+        assert!(debug[1].ends_with("root panicked"), "{:?}", debug);
+    }
+
+    #[test]
+    fn cells_without_programs_fail() {
+        let debug = Arc::new(Mutex::new(Vec::new()));
+        let code = NativeProcesses::new().program([1; 32], writer).run(
+            CellDeps {
+                debug: debug.clone(),
+            },
+            || {
+                assert_eq!(
+                    spawn_cell(&[2; 32], ScriptHashType::Data, &[], &[]),
+                    Err(SysError::ItemMissing)
+                );
+                let Err(err) = exec_cell(&[3; 32], ScriptHashType::Data, &[]);
+                assert_eq!(err, SysError::ItemMissing);
+                0
+            },
+        );
+        assert_eq!(code, 0, "{:?}", debug.lock().unwrap());
+    }
+
+    #[test]
+    fn only_programs_report_panics() {
+        let (status, _) = run(|| 0);
+        assert_eq!(status.code, 0);
+        // the panic hook is installed, but this thread runs no program
+        let debug = Arc::new(Mutex::new(Vec::new()));
+        let impls = CellDeps {
+            debug: debug.clone(),
+        };
+        let result = with_impls(impls, || catch_unwind(|| panic!("not a program")));
+        assert!(result.is_err());
+        assert!(debug.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn children_are_terminated_with_the_root() {
+        let (status, _) = run(|| {
+            let (output, _input) = pipe().unwrap();
+            spawn(4, &[], &[output]);
+            let (output, _input) = pipe().unwrap();
+            spawn(4, &[], &[output]);
+            5
+        });
+        assert_eq!(status.code, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "deadlock")]
+    fn deadlocks_are_detected() {
+        run(|| {
+            // the root holds the write end the child reads from
+            let (output, _input) = pipe().unwrap();
+            let pid = spawn(4, &[], &[output]);
+            wait(pid).unwrap()
+        });
+    }
+}
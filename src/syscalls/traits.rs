@@ -758,6 +758,22 @@ impl From<Error> for IoResult {
     }
 }
 
+// Same semantics as CKB: the offset is capped to the data length, and the
+// available length counts from the offset.
+pub(crate) fn serve(data: &[u8], buf: &mut [u8], offset: usize) -> IoResult {
+    let rest = &data[offset.min(data.len())..];
+    let loaded = rest.len().min(buf.len());
+    buf[..loaded].copy_from_slice(&rest[..loaded]);
+    if rest.len() > buf.len() {
+        IoResult::PartialLoaded {
+            loaded,
+            available: rest.len(),
+        }
+    } else {
+        IoResult::FullyLoaded(loaded)
+    }
+}
+
 impl From<IoResult> for Result<usize, SysError> {
     fn from(result: IoResult) -> Result<usize, SysError> {
         match result {
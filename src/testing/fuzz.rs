@@ -0,0 +1,253 @@
+//! Fuzzing lock scripts on transactions derived from arbitrary bytes.
+//!
+//! [`mock_transaction`] turns any byte string into a [`MockTransaction`]
+//! whose first input is locked by the contract under test: the other cells,
+//! scripts, cell data, since values, header deps and witnesses, some of them
+//! `WitnessArgs`, are read from the bytes. [`run`] runs the lock on it with
+//! the stub backend, a panic of the script is a finding, so a cargo-fuzz
+//! target needs no other glue:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//! use ckb_std::testing::{Contract, fuzz};
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     fuzz::run(data, &Contract::new("my-lock", my_lock::program_entry));
+//! });
+//! ```
+//!
+//! Panics of the script must unwind to be caught, which is the default.
+
+use super::{Contract, MockTransaction, RunResult, ScriptGroup};
+use crate::since::EpochNumberWithFraction;
+use alloc::vec::Vec;
+use ckb_types::{core::ScriptHashType, packed::*, prelude::*};
+
+/// Inputs, outputs and header deps of a transaction, at most
+pub const MAX_ITEMS: usize = 8;
+
+/// Script args, cell data and witness fields, in bytes at most
+pub const MAX_BYTES: usize = 255;
+
+// Reads the fuzzer input, it's all zeros once exhausted so every input is
+// valid.
+struct Unstructured<'a>(&'a [u8]);
+
+impl Unstructured<'_> {
+    fn byte(&mut self) -> u8 {
+        match self.0.split_first() {
+            Some((byte, rest)) => {
+                self.0 = rest;
+                *byte
+            }
+            None => 0,
+        }
+    }
+
+    fn u64(&mut self) -> u64 {
+        (0..8).fold(0, |value, _| (value << 8) | self.byte() as u64)
+    }
+
+    fn len(&mut self, max: usize) -> usize {
+        self.byte() as usize % (max + 1)
+    }
+
+    fn chance(&mut self) -> bool {
+        self.byte() & 1 == 1
+    }
+
+    fn bytes(&mut self, max: usize) -> Vec<u8> {
+        let len = self.len(max);
+        (0..len).map(|_| self.byte()).collect()
+    }
+
+    fn hash(&mut self) -> [u8; 32] {
+        let mut hash = [0; 32];
+        hash.iter_mut().for_each(|byte| *byte = self.byte());
+        hash
+    }
+
+    // The script under test, the contract with other args, or another
+    // contract.
+    fn script(&mut self, lock: &Script, contract: &Contract) -> Script {
+        match self.byte() % 3 {
+            0 => lock.clone(),
+            1 => contract.script(&self.bytes(MAX_BYTES)),
+            _ => Script::new_builder()
+                .code_hash(self.hash().pack())
+                .hash_type(Into::<Byte>::into(ScriptHashType::Type))
+                .args(self.bytes(MAX_BYTES).pack())
+                .build(),
+        }
+    }
+
+    fn cell(&mut self, lock: &Script, contract: &Contract) -> CellOutput {
+        let type_script = match self.chance() {
+            true => Some(self.script(lock, contract)),
+            false => None,
+        };
+        CellOutput::new_builder()
+            .capacity(self.u64())
+            .lock(self.script(lock, contract))
+            .type_(ScriptOpt::new_builder().set(type_script).build())
+            .build()
+    }
+
+    fn bytes_opt(&mut self) -> BytesOpt {
+        let bytes = match self.chance() {
+            true => Some(self.bytes(MAX_BYTES).pack()),
+            false => None,
+        };
+        BytesOpt::new_builder().set(bytes).build()
+    }
+
+    fn witness(&mut self) -> Vec<u8> {
+        match self.chance() {
+            true => WitnessArgs::new_builder()
+                .lock(self.bytes_opt())
+                .input_type(self.bytes_opt())
+                .output_type(self.bytes_opt())
+                .build()
+                .as_bytes()
+                .to_vec(),
+            false => self.bytes(MAX_BYTES),
+        }
+    }
+
+    // A header CKB could have produced: the epoch is well formed and starts
+    // at or after the genesis block.
+    fn header(&mut self) -> Header {
+        let number = self.u64();
+        let length = 1 + self.u64() % (EpochNumberWithFraction::LENGTH_MAXIMUM_VALUE - 1);
+        let index = (self.u64() % length).min(number);
+        let epoch = EpochNumberWithFraction::new(
+            self.u64() % EpochNumberWithFraction::NUMBER_MAXIMUM_VALUE,
+            index,
+            length,
+        );
+        let raw = RawHeader::new_builder()
+            .number(number)
+            .epoch(epoch.full_value())
+            .timestamp(self.u64())
+            .build();
+        Header::new_builder().raw(raw).build()
+    }
+}
+
+/// Transaction derived from `data`, and the script group of the lock of
+/// `contract` in it
+///
+/// The first input is locked by `contract` with args read from `data`, the
+/// contract is a cell dep of the transaction.
+pub fn mock_transaction(data: &[u8], contract: &Contract) -> (MockTransaction, ScriptGroup) {
+    let mut data = Unstructured(data);
+    let lock = contract.script(&data.bytes(MAX_BYTES));
+    let mut tx = MockTransaction::new().contract(contract);
+
+    let inputs = 1 + data.len(MAX_ITEMS - 1);
+    for index in 0..inputs {
+        let mut cell = data.cell(&lock, contract);
+        if index == 0 {
+            cell = cell.as_builder().lock(lock.clone()).build();
+        }
+        let since = match data.chance() {
+            true => data.u64(),
+            false => 0,
+        };
+        tx = tx.input_with_since(cell, data.bytes(MAX_BYTES), since);
+    }
+    for _ in 0..data.len(MAX_ITEMS) {
+        let cell = data.cell(&lock, contract);
+        tx = tx.output(cell, data.bytes(MAX_BYTES));
+    }
+    for _ in 0..data.len(MAX_ITEMS) {
+        tx = tx.header_dep(data.header());
+    }
+    // witnesses of the inputs, and maybe a few more
+    for _ in 0..inputs + data.len(2) {
+        tx = tx.witness(data.witness());
+    }
+    (tx, ScriptGroup::Lock(0))
+}
+
+/// Run `contract` on the transaction derived from `data`
+///
+/// Panics with the message of the script if it panicked, so that the fuzzer
+/// reports it. Other exit codes are returned, as are the panics of spawned
+/// processes, which their parent handles.
+pub fn run(data: &[u8], contract: &Contract) -> RunResult {
+    let (tx, group) = mock_transaction(data, contract);
+    let result = tx.run(group, contract);
+    if result.panicked {
+        panic!(
+            "the script panicked with exit code {}: {}",
+            result.exit_code,
+            result.panic_message().unwrap_or_default()
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{ckb_constants::Source, ctx::Ctx, testing::MockSyscalls};
+
+    #[test]
+    fn panics_are_findings() {
+        let contract = Contract::new("lock", || {
+            crate::syscalls::debug(alloc::string::String::from(
+                "panicked at a.rs:1:1:\nnot really",
+            ));
+            let args = crate::high_level::load_script().unwrap().args().raw_data();
+            crate::assert!(3, args.len() < 200, "long args");
+            1
+        });
+        // args of the lock are read first
+        let result = run(&[10], &contract);
+        assert_eq!((result.exit_code, result.panicked), (1, false));
+
+        let finding = std::panic::catch_unwind(|| run(&[250], &contract)).unwrap_err();
+        assert_eq!(
+            finding.downcast_ref::<alloc::string::String>().unwrap(),
+            "the script panicked with exit code 3: long args"
+        );
+    }
+
+    #[test]
+    fn headers_have_an_epoch_start() {
+        let contract = Contract::new("lock", || 0);
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut headers = 0;
+        for len in (0..4096).step_by(8) {
+            let data: Vec<u8> = (0..len)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    seed as u8
+                })
+                .collect();
+            let (tx, group) = mock_transaction(&data, &contract);
+            let ctx = Ctx::new(MockSyscalls::new(&tx, group));
+            for index in 0.. {
+                let number = match ctx.load_header_epoch_number(index, Source::HeaderDep) {
+                    Ok(number) => number,
+                    Err(_) => break,
+                };
+                let length = ctx
+                    .load_header_epoch_length(index, Source::HeaderDep)
+                    .unwrap();
+                assert!(number < EpochNumberWithFraction::NUMBER_MAXIMUM_VALUE);
+                assert!(length > 0);
+                ctx.load_header_epoch_start_block_number(index, Source::HeaderDep)
+                    .unwrap();
+                headers += 1;
+            }
+        }
+        assert!(headers > 100);
+    }
+}
@@ -0,0 +1,335 @@
+//! Mock transactions for testing scripts natively.
+//!
+//! A [`MockTransaction`] holds the cells, witnesses and headers of a
+//! transaction. [`MockTransaction::run`] runs a [`Contract`] as one of its
+//! script groups and returns its exit code and debug output, on the backend
+//! selected by the features of ckb-std:
+//!
+//! * `stub-syscalls`: `main` runs on the current thread, with the syscalls
+//!   served by [`MockSyscalls`]. The contracts added to the transaction can
+//!   be spawned and exec'd, see [`stub_processes`](crate::syscalls::stub_processes).
+//! * `native-simulator`: the [`binary`](Contract::binary) of the contract,
+//!   built with the simulator, runs in a child process reading the
+//!   transaction from the files in `CKB_TX_FILE` and `CKB_RUNNING_SETUP`.
+//!   Its `main` calls [`main`] with the entry function.
+//!
+//! The same test then runs on either backend:
+//!
+//! ```ignore
+//! use ckb_std::testing::{Contract, MockTransaction, ScriptGroup};
+//!
+//! #[test]
+//! fn unlocks_with_signature() {
+//!     let lock = Contract::new("my-lock", my_lock::program_entry)
+//!         .binary(env!("CARGO_BIN_EXE_my-lock"));
+//!     let cell = CellOutput::new_builder()
+//!         .capacity(1000u64 * 100_000_000)
+//!         .lock(lock.script(&pubkey_hash))
+//!         .build();
+//!     let result = MockTransaction::new()
+//!         .contract(&lock)
+//!         .input(cell.clone(), [])
+//!         .output(cell, [])
+//!         .witness(signature_witness)
+//!         .run(ScriptGroup::Lock(0), &lock);
+//!     assert_eq!(result.exit_code, 0);
+//! }
+//! ```
+//!
+//! Everything printed with `syscalls::debug` is captured in
+//! [`RunResult::debug`], so are the `debug!` macro and the `logger`.
+//! As on chain, a panic prints its message before the script exits with the
+//! panic exit code, see [`RunResult::panicked`] and [`RunResult::panic_message`].
+//!
+//! Scripts run by the simulator backend can't spawn or exec other scripts:
+//! its running setup registers no native binaries, so test scripts that do
+//! with the stub backend.
+//!
+//! The tests of `test/simulator` aren't written with this module: they
+//! replay transactions recorded for ckb-debugger and load the contracts as
+//! shared libraries built from the `contracts/*-dbg` crates, which is the
+//! simulator's own way of running scripts, including exec'd ones.
+
+extern crate std;
+
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+#[cfg(feature = "native-simulator")]
+mod simulator;
+#[cfg(feature = "stub-syscalls")]
+mod stub;
+
+#[cfg(feature = "native-simulator")]
+pub use simulator::main;
+#[cfg(feature = "stub-syscalls")]
+pub use stub::MockSyscalls;
+
+use alloc::{string::String, vec::Vec};
+use ckb_types::{core::ScriptHashType, packed::*, prelude::*};
+use std::path::PathBuf;
+
+/// Data hash of `data`, as loaded by `CellField::DataHash`
+pub fn data_hash(data: &[u8]) -> [u8; 32] {
+    CellOutput::calc_data_hash(data).unpack()
+}
+
+/// A script under test
+#[derive(Clone)]
+pub struct Contract {
+    code: Vec<u8>,
+    #[cfg_attr(not(feature = "stub-syscalls"), allow(dead_code))]
+    main: fn() -> i8,
+    binary: Option<PathBuf>,
+}
+
+impl Contract {
+    /// `main` runs the script under `stub-syscalls`
+    ///
+    /// `code` is the data of the cell dep holding the contract, its data hash
+    /// is the code hash of the scripts running it. Anything unique does, such
+    /// as the contract name.
+    pub fn new(code: impl Into<Vec<u8>>, main: fn() -> i8) -> Self {
+        Contract {
+            code: code.into(),
+            main,
+            binary: None,
+        }
+    }
+
+    /// Executable running the script under `native-simulator`
+    pub fn binary(mut self, path: impl Into<PathBuf>) -> Self {
+        self.binary = Some(path.into());
+        self
+    }
+
+    /// Data hash of the contract code
+    pub fn code_hash(&self) -> [u8; 32] {
+        data_hash(&self.code)
+    }
+
+    /// Script running the contract with `args`
+    pub fn script(&self, args: &[u8]) -> Script {
+        Script::new_builder()
+            .code_hash(self.code_hash().pack())
+            .hash_type(Into::<Byte>::into(ScriptHashType::Data2))
+            .args(args.pack())
+            .build()
+    }
+}
+
+/// The script group to run, by the cell whose script it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptGroup {
+    /// Lock script of the input at this index
+    Lock(usize),
+    /// Type script of the input at this index
+    InputType(usize),
+    /// Type script of the output at this index
+    OutputType(usize),
+}
+
+/// Outcome of a script run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResult {
+    /// Exit code of the script
+    pub exit_code: i8,
+    /// Whether the script panicked, the exit code is then the panic exit
+    /// code
+    ///
+    /// Under `native-simulator`, only panics of binaries running
+    /// `testing::main` are detected.
+    pub panicked: bool,
+    /// Messages printed with `syscalls::debug`, one per call
+    ///
+    /// Under `native-simulator`, only the first line of messages spanning
+    /// several lines is kept, except for the panic message.
+    pub debug: Vec<String>,
+}
+
+impl RunResult {
+    /// Message of the panic ending the script, without its location
+    ///
+    /// It's empty if the panic payload isn't a string.
+    pub fn panic_message(&self) -> Option<&str> {
+        if !self.panicked {
+            return None;
+        }
+        self.debug
+            .iter()
+            .rev()
+            .find(|message| message.starts_with("panicked at "))
+            .map(|message| message.split_once('\n').map_or("", |(_, message)| message))
+    }
+}
+
+/// A transaction and the cells it refers to, see the [module](self) docs
+///
+/// Inputs spend made-up out points, cell deps point to made-up cells too.
+#[derive(Clone, Default)]
+pub struct MockTransaction {
+    inputs: Vec<(CellInput, CellOutput, Vec<u8>)>,
+    outputs: Vec<(CellOutput, Vec<u8>)>,
+    cell_deps: Vec<(CellDep, CellOutput, Vec<u8>)>,
+    header_deps: Vec<Header>,
+    witnesses: Vec<Vec<u8>>,
+    contracts: Vec<Contract>,
+}
+
+impl MockTransaction {
+    /// Empty transaction
+    pub fn new() -> Self {
+        MockTransaction::default()
+    }
+
+    /// Add an input spending `output` holding `data`
+    pub fn input(self, output: CellOutput, data: impl Into<Vec<u8>>) -> Self {
+        self.input_with_since(output, data, 0)
+    }
+
+    /// Add an input spending `output` holding `data`, with `since`
+    pub fn input_with_since(
+        mut self,
+        output: CellOutput,
+        data: impl Into<Vec<u8>>,
+        since: u64,
+    ) -> Self {
+        let out_point = OutPoint::new_builder()
+            .tx_hash([0x11; 32].pack())
+            .index(self.inputs.len() as u32)
+            .build();
+        let input = CellInput::new_builder()
+            .previous_output(out_point)
+            .since(since)
+            .build();
+        self.inputs.push((input, output, data.into()));
+        self
+    }
+
+    /// Add an output with `data`
+    pub fn output(mut self, output: CellOutput, data: impl Into<Vec<u8>>) -> Self {
+        self.outputs.push((output, data.into()));
+        self
+    }
+
+    /// Add a cell dep to `output` holding `data`
+    pub fn cell_dep(mut self, output: CellOutput, data: impl Into<Vec<u8>>) -> Self {
+        let out_point = OutPoint::new_builder()
+            .tx_hash([0x22; 32].pack())
+            .index(self.cell_deps.len() as u32)
+            .build();
+        let cell_dep = CellDep::new_builder().out_point(out_point).build();
+        self.cell_deps.push((cell_dep, output, data.into()));
+        self
+    }
+
+    /// Add a cell dep holding `contract`, its scripts can then be spawned
+    /// and exec'd
+    pub fn contract(mut self, contract: &Contract) -> Self {
+        let output = CellOutput::new_builder()
+            .capacity((contract.code.len() as u64 + 41) * 100_000_000)
+            .build();
+        self.contracts.push(contract.clone());
+        self.cell_dep(output, contract.code.clone())
+    }
+
+    /// Add a header dep
+    pub fn header_dep(mut self, header: Header) -> Self {
+        self.header_deps.push(header);
+        self
+    }
+
+    /// Add a witness
+    pub fn witness(mut self, witness: impl Into<Vec<u8>>) -> Self {
+        self.witnesses.push(witness.into());
+        self
+    }
+
+    /// The transaction, as loaded by `load_transaction`
+    pub fn transaction(&self) -> Transaction {
+        let raw = RawTransaction::new_builder()
+            .cell_deps(
+                CellDepVec::new_builder()
+                    .extend(self.cell_deps.iter().map(|(dep, _, _)| dep.clone()))
+                    .build(),
+            )
+            .header_deps(
+                Byte32Vec::new_builder()
+                    .extend(self.header_deps.iter().map(Header::calc_header_hash))
+                    .build(),
+            )
+            .inputs(
+                CellInputVec::new_builder()
+                    .extend(self.inputs.iter().map(|(input, _, _)| input.clone()))
+                    .build(),
+            )
+            .outputs(
+                CellOutputVec::new_builder()
+                    .extend(self.outputs.iter().map(|(output, _)| output.clone()))
+                    .build(),
+            )
+            .outputs_data(
+                BytesVec::new_builder()
+                    .extend(self.outputs.iter().map(|(_, data)| data.pack()))
+                    .build(),
+            )
+            .build();
+        Transaction::new_builder()
+            .raw(raw)
+            .witnesses(
+                BytesVec::new_builder()
+                    .extend(self.witnesses.iter().map(|witness| witness.pack()))
+                    .build(),
+            )
+            .build()
+    }
+
+    /// Script of `group`
+    ///
+    /// Panics if the cell doesn't exist or has no type script.
+    pub fn script(&self, group: ScriptGroup) -> Script {
+        match group {
+            ScriptGroup::Lock(index) => Some(self.inputs[index].1.lock()),
+            ScriptGroup::InputType(index) => self.inputs[index].1.type_().to_opt(),
+            ScriptGroup::OutputType(index) => self.outputs[index].0.type_().to_opt(),
+        }
+        .expect("no type script")
+    }
+
+    /// Indices of the inputs and outputs in `group`
+    pub fn group_indices(&self, group: ScriptGroup) -> (Vec<usize>, Vec<usize>) {
+        let script = Some(self.script(group));
+        let inputs = self
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, output, _))| match group {
+                ScriptGroup::Lock(_) => Some(output.lock()) == script,
+                _ => output.type_().to_opt() == script,
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let outputs = match group {
+            ScriptGroup::Lock(_) => Vec::new(),
+            _ => self
+                .outputs
+                .iter()
+                .enumerate()
+                .filter(|(_, (output, _))| output.type_().to_opt() == script)
+                .map(|(index, _)| index)
+                .collect(),
+        };
+        (inputs, outputs)
+    }
+
+    /// Run `contract` as the script of `group` and wait for it to exit
+    pub fn run(&self, group: ScriptGroup, contract: &Contract) -> RunResult {
+        #[cfg(feature = "stub-syscalls")]
+        {
+            stub::run(self, group, contract)
+        }
+        #[cfg(not(feature = "stub-syscalls"))]
+        {
+            simulator::run(self, group, contract)
+        }
+    }
+}
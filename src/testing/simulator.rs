@@ -0,0 +1,352 @@
+use super::{Contract, MockTransaction, RunResult, ScriptGroup};
+use crate::asserts::panic_exit_code;
+use alloc::{boxed::Box, vec::Vec};
+use ckb_types::{packed::*, prelude::*};
+use core::fmt::{LowerHex, Write};
+use serde_json::{Value, json};
+use std::{
+    fs, panic,
+    path::PathBuf,
+    process::{self, Command},
+    string::String,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Prefix of the debug messages printed by ckb-x64-simulator
+const DEBUG_PREFIX: &str = "[contract debug] ";
+
+// Prefix of the panic message printed by `main`, on one line
+const PANIC_PREFIX: &str = "[contract panic] ";
+
+/// Entry of a contract binary for the native-simulator backend
+///
+/// Runs `program` and exits with its exit code. As in the panic handler of
+/// [`entry!`](crate::entry), a panic prints its message and exits with the
+/// panic exit code, the message is one of the debug messages of the
+/// [`RunResult`] and the run is [`panicked`](RunResult::panicked):
+///
+/// ```ignore
+/// fn main() {
+///     ckb_std::testing::main(my_lock::program_entry)
+/// }
+/// ```
+pub fn main(program: fn() -> i8) -> ! {
+    panic::set_hook(Box::new(|info| {
+        let message = std::format!("{}", info);
+        std::println!("{}{}", PANIC_PREFIX, escape(&message));
+        process::exit(panic_exit_code().into())
+    }));
+    process::exit(program().into())
+}
+
+fn escape(message: &str) -> String {
+    message.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut message = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                message.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                message.push('\\');
+                chars.next();
+            }
+            (c, _) => message.push(c),
+        }
+    }
+    message
+}
+
+// Debug messages in the output of a contract binary, and whether it
+// panicked. The simulator prints messages as they are, only the first line of
+// a message spanning several lines is kept: the others can't be told apart
+// from other output.
+fn parse_output(stdout: &str) -> (Vec<String>, bool) {
+    let mut debug = Vec::new();
+    let mut panicked = false;
+    for line in stdout.lines() {
+        if let Some(message) = line.strip_prefix(DEBUG_PREFIX) {
+            debug.push(String::from(message));
+        } else if let Some(message) = line.strip_prefix(PANIC_PREFIX) {
+            debug.push(unescape(message));
+            panicked = true;
+        }
+    }
+    (debug, panicked)
+}
+
+pub(super) fn run(tx: &MockTransaction, group: ScriptGroup, contract: &Contract) -> RunResult {
+    let binary = contract
+        .binary
+        .as_ref()
+        .expect("Contract::binary is required by the native-simulator backend");
+    run_command(tx, group, Command::new(binary))
+}
+
+// Runs `command` as the contract binary
+fn run_command(tx: &MockTransaction, group: ScriptGroup, mut command: Command) -> RunResult {
+    let binary = PathBuf::from(command.get_program());
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(std::format!(
+        "ckb-std-testing-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir).expect("create the mock transaction directory");
+    let tx_file = dir.join("tx.json");
+    let setup_file = dir.join("setup.json");
+    fs::write(&tx_file, mock_tx_json(tx)).expect("write the mock transaction");
+    fs::write(&setup_file, setup_json(group)).expect("write the running setup");
+
+    let output = command
+        .env("CKB_TX_FILE", &tx_file)
+        .env("CKB_RUNNING_SETUP", &setup_file)
+        .output()
+        .unwrap_or_else(|err| panic!("run {}: {}", binary.display(), err));
+    let _ = fs::remove_dir_all(&dir);
+
+    let (debug, panicked) = parse_output(&String::from_utf8_lossy(&output.stdout));
+    let exit_code = output
+        .status
+        .code()
+        .unwrap_or_else(|| panic!("{} was killed: {}", binary.display(), output.status));
+    RunResult {
+        exit_code: exit_code as i8,
+        panicked,
+        debug,
+    }
+}
+
+fn setup_json(group: ScriptGroup) -> String {
+    let (is_lock_script, is_output, script_index) = match group {
+        ScriptGroup::Lock(index) => (true, false, index),
+        ScriptGroup::InputType(index) => (false, false, index),
+        ScriptGroup::OutputType(index) => (false, true, index),
+    };
+    json!({
+        "is_lock_script": is_lock_script,
+        "is_output": is_output,
+        "script_index": script_index,
+        "vm_version": 2,
+        "native_binaries": {},
+        "run_type": null,
+    })
+    .to_string()
+}
+
+// The mock transaction format of ckb-debugger, with JSON-RPC types.
+fn mock_tx_json(tx: &MockTransaction) -> String {
+    let inputs = tx.inputs.iter().map(|(input, output, data)| {
+        json!({
+            "input": cell_input(input),
+            "output": cell_output(output),
+            "data": hex(data),
+            "header": null,
+        })
+    });
+    let cell_deps = tx.cell_deps.iter().map(|(cell_dep, output, data)| {
+        json!({
+            "cell_dep": dep(cell_dep),
+            "output": cell_output(output),
+            "data": hex(data),
+            "header": null,
+        })
+    });
+    json!({
+        "mock_info": {
+            "inputs": inputs.collect::<Vec<_>>(),
+            "cell_deps": cell_deps.collect::<Vec<_>>(),
+            "header_deps": tx.header_deps.iter().map(header).collect::<Vec<_>>(),
+            "extensions": [],
+        },
+        "tx": transaction(&tx.transaction()),
+    })
+    .to_string()
+}
+
+fn hex(bytes: &[u8]) -> Value {
+    let mut hex = String::from("0x");
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    Value::String(hex)
+}
+
+fn number(value: impl LowerHex) -> Value {
+    Value::String(std::format!("0x{:x}", value))
+}
+
+fn script(script: &Script) -> Value {
+    let hash_type = match script.hash_type().as_slice()[0] {
+        0 => String::from("data"),
+        1 => String::from("type"),
+        n => std::format!("data{}", n / 2),
+    };
+    json!({
+        "code_hash": hex(script.code_hash().as_slice()),
+        "hash_type": hash_type,
+        "args": hex(&script.args().raw_data()),
+    })
+}
+
+fn cell_output(output: &CellOutput) -> Value {
+    json!({
+        "capacity": number(Unpack::<u64>::unpack(&output.capacity())),
+        "lock": script(&output.lock()),
+        "type": output.type_().to_opt().as_ref().map(script),
+    })
+}
+
+fn out_point(out_point: &OutPoint) -> Value {
+    json!({
+        "tx_hash": hex(out_point.tx_hash().as_slice()),
+        "index": number(Unpack::<u32>::unpack(&out_point.index())),
+    })
+}
+
+fn cell_input(input: &CellInput) -> Value {
+    json!({
+        "since": number(Unpack::<u64>::unpack(&input.since())),
+        "previous_output": out_point(&input.previous_output()),
+    })
+}
+
+fn dep(cell_dep: &CellDep) -> Value {
+    let dep_type = match cell_dep.dep_type().as_slice()[0] {
+        0 => "code",
+        _ => "dep_group",
+    };
+    json!({
+        "out_point": out_point(&cell_dep.out_point()),
+        "dep_type": dep_type,
+    })
+}
+
+// A header view, with the hash the header deps of the transaction refer to
+fn header(header: &Header) -> Value {
+    let raw = header.raw();
+    let nonce = u128::from_le_bytes(header.nonce().as_slice().try_into().unwrap());
+    json!({
+        "version": number(Unpack::<u32>::unpack(&raw.version())),
+        "compact_target": number(Unpack::<u32>::unpack(&raw.compact_target())),
+        "timestamp": number(Unpack::<u64>::unpack(&raw.timestamp())),
+        "number": number(Unpack::<u64>::unpack(&raw.number())),
+        "epoch": number(Unpack::<u64>::unpack(&raw.epoch())),
+        "parent_hash": hex(raw.parent_hash().as_slice()),
+        "transactions_root": hex(raw.transactions_root().as_slice()),
+        "proposals_hash": hex(raw.proposals_hash().as_slice()),
+        "extra_hash": hex(raw.extra_hash().as_slice()),
+        "dao": hex(raw.dao().as_slice()),
+        "nonce": number(nonce),
+        "hash": hex(header.calc_header_hash().as_slice()),
+    })
+}
+
+fn transaction(tx: &Transaction) -> Value {
+    let raw = tx.raw();
+    json!({
+        "version": number(Unpack::<u32>::unpack(&raw.version())),
+        "cell_deps": raw.cell_deps().into_iter().map(|d| dep(&d)).collect::<Vec<_>>(),
+        "header_deps": raw.header_deps().into_iter().map(|h| hex(h.as_slice())).collect::<Vec<_>>(),
+        "inputs": raw.inputs().into_iter().map(|i| cell_input(&i)).collect::<Vec<_>>(),
+        "outputs": raw.outputs().into_iter().map(|o| cell_output(&o)).collect::<Vec<_>>(),
+        "outputs_data": raw.outputs_data().into_iter().map(|d| hex(&d.raw_data())).collect::<Vec<_>>(),
+        "witnesses": tx.witnesses().into_iter().map(|w| hex(&w.raw_data())).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ckb_constants::Source, high_level, syscalls};
+    use alloc::vec;
+
+    // Loads the script and the header dep through the simulator
+    fn lock() -> i8 {
+        let args = high_level::load_script().unwrap().args().raw_data();
+        let header = high_level::load_header(0, Source::HeaderDep).unwrap();
+        let number: u64 = header.raw().number().unpack();
+        syscalls::debug(std::format!("args {:?}, block {}", args.as_ref(), number));
+        number as i8
+    }
+
+    // The contract binary of `runs_the_contract_binary`, which runs this test
+    // alone with the transaction in `CKB_TX_FILE`
+    #[test]
+    fn contract_binary() {
+        if std::env::var_os("CKB_TX_FILE").is_some() {
+            main(lock)
+        }
+    }
+
+    #[test]
+    fn runs_the_contract_binary() {
+        let contract = Contract::new("lock", lock);
+        let cell = CellOutput::new_builder()
+            .lock(contract.script(&[7]))
+            .build();
+        let header = Header::new_builder()
+            .raw(RawHeader::new_builder().number(42u64).build())
+            .build();
+        let tx = MockTransaction::new()
+            .contract(&contract)
+            .input(cell, [])
+            .header_dep(header);
+
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command.args([
+            "--exact",
+            "testing::simulator::tests::contract_binary",
+            "--nocapture",
+            "--quiet",
+        ]);
+        let result = run_command(&tx, ScriptGroup::Lock(0), command);
+        assert_eq!(
+            result,
+            RunResult {
+                exit_code: 42,
+                panicked: false,
+                debug: vec![String::from("args [7], block 42")],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_debug_messages_and_panics() {
+        let message = "panicked at src/main.rs:3:5:\nbad \\n input\\";
+        let stdout = std::format!(
+            "[contract debug] first\nstray output\n[contract debug] second\nline 2\n{}{}\n",
+            PANIC_PREFIX,
+            escape(message)
+        );
+        let (debug, panicked) = parse_output(&stdout);
+        assert_eq!(debug, ["first", "second", message]);
+        assert!(panicked);
+
+        let result = RunResult {
+            exit_code: -1,
+            panicked,
+            debug,
+        };
+        assert_eq!(result.panic_message(), Some("bad \\n input\\"));
+    }
+
+    #[test]
+    fn debug_lines_are_not_panics() {
+        let (debug, panicked) = parse_output("[contract debug] panicked at x:1:1:\nmessage\n");
+        assert_eq!(debug, ["panicked at x:1:1:"]);
+        assert!(!panicked);
+        let result = RunResult {
+            exit_code: 0,
+            panicked,
+            debug,
+        };
+        assert_eq!(result.panic_message(), None);
+    }
+}
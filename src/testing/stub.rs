@@ -0,0 +1,550 @@
+use super::{Contract, MockTransaction, RunResult, ScriptGroup};
+use crate::{
+    ckb_constants::{CellField, HeaderField, InputField, Source},
+    since::EpochNumberWithFraction,
+    syscalls::{
+        stub_processes::NativeProcesses,
+        traits::{Error, IoResult, SyscallImpls, serve},
+    },
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use ckb_types::{packed::*, prelude::*};
+use core::ffi::CStr;
+
+extern crate std;
+
+use std::sync::Mutex;
+
+/// [`SyscallImpls`] serving a [`MockTransaction`] to one of its script
+/// groups
+///
+/// Debug messages are collected, see [`debug_messages`](Self::debug_messages).
+/// `exit`, `spawn`, `exec`, pipes and `wait` are not implemented, they're
+/// handled by [`NativeProcesses`] in [`MockTransaction::run`].
+pub struct MockSyscalls {
+    tx: MockTransaction,
+    transaction: Transaction,
+    tx_hash: [u8; 32],
+    script: Script,
+    group_inputs: Vec<usize>,
+    group_outputs: Vec<usize>,
+    debug: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockSyscalls {
+    /// Serve `tx` to the script of `group`
+    pub fn new(tx: &MockTransaction, group: ScriptGroup) -> Self {
+        let transaction = tx.transaction();
+        let (group_inputs, group_outputs) = tx.group_indices(group);
+        MockSyscalls {
+            tx_hash: transaction.calc_tx_hash().unpack(),
+            transaction,
+            script: tx.script(group),
+            group_inputs,
+            group_outputs,
+            tx: tx.clone(),
+            debug: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Messages printed so far, shared with the syscalls
+    pub fn debug_messages(&self) -> Arc<Mutex<Vec<String>>> {
+        self.debug.clone()
+    }
+
+    // index in the transaction of the item at `index` of `source`
+    fn resolve(&self, index: usize, source: Source) -> Result<(Source, usize), Error> {
+        let group = match source {
+            Source::GroupInput => &self.group_inputs,
+            Source::GroupOutput => &self.group_outputs,
+            _ => return Ok((source, index)),
+        };
+        let index = *group.get(index).ok_or(Error::IndexOutOfBound)?;
+        match source {
+            Source::GroupInput => Ok((Source::Input, index)),
+            _ => Ok((Source::Output, index)),
+        }
+    }
+
+    fn cell(&self, index: usize, source: Source) -> Result<(&CellOutput, &[u8]), Error> {
+        let cell = match self.resolve(index, source)? {
+            (Source::Input, index) => self
+                .tx
+                .inputs
+                .get(index)
+                .map(|(_, output, data)| (output, data)),
+            (Source::Output, index) => self
+                .tx
+                .outputs
+                .get(index)
+                .map(|(output, data)| (output, data)),
+            (Source::CellDep, index) => self
+                .tx
+                .cell_deps
+                .get(index)
+                .map(|(_, output, data)| (output, data)),
+            _ => None,
+        };
+        cell.map(|(output, data)| (output, data.as_slice()))
+            .ok_or(Error::IndexOutOfBound)
+    }
+
+    fn input(&self, index: usize, source: Source) -> Result<&CellInput, Error> {
+        match self.resolve(index, source)? {
+            (Source::Input, index) => self.tx.inputs.get(index).map(|(input, _, _)| input),
+            _ => None,
+        }
+        .ok_or(Error::IndexOutOfBound)
+    }
+
+    fn header(&self, index: usize, source: Source) -> Result<&Header, Error> {
+        match source {
+            Source::HeaderDep => self.tx.header_deps.get(index).ok_or(Error::IndexOutOfBound),
+            // inputs and cell deps have no block attached
+            _ => {
+                self.cell(index, source)?;
+                Err(Error::ItemMissing)
+            }
+        }
+    }
+}
+
+fn serve_result(data: Result<&[u8], Error>, buf: &mut [u8], offset: usize) -> IoResult {
+    match data {
+        Ok(data) => serve(data, buf, offset),
+        Err(err) => IoResult::Error(err),
+    }
+}
+
+// Bytes used by a cell, CKB counts the capacity of one byte as 10^8 shannons.
+fn occupied_capacity(output: &CellOutput, data: &[u8]) -> u64 {
+    let script_size = |script: &Script| 32 + 1 + script.args().raw_data().len() as u64;
+    let type_size = output
+        .type_()
+        .to_opt()
+        .map_or(0, |script| script_size(&script));
+    (8 + data.len() as u64 + script_size(&output.lock()) + type_size) * 100_000_000
+}
+
+impl SyscallImpls for MockSyscalls {
+    fn debug(&self, s: &CStr) {
+        let message = String::from(s.to_string_lossy());
+        self.debug.lock().unwrap().push(message);
+    }
+
+    fn load_cell(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let cell = self
+            .cell(index, source)
+            .map(|(output, _)| output.as_slice());
+        serve_result(cell, buf, offset)
+    }
+
+    fn load_cell_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: CellField,
+    ) -> IoResult {
+        let (output, data) = match self.cell(index, source) {
+            Ok(cell) => cell,
+            Err(err) => return IoResult::Error(err),
+        };
+        let type_script = output.type_().to_opt();
+        let value: Vec<u8> = match field {
+            CellField::Capacity => output.capacity().as_slice().to_vec(),
+            CellField::DataHash => super::data_hash(data).to_vec(),
+            CellField::Lock => output.lock().as_slice().to_vec(),
+            CellField::LockHash => output.calc_lock_hash().as_slice().to_vec(),
+            CellField::Type => match type_script {
+                Some(script) => script.as_slice().to_vec(),
+                None => return IoResult::Error(Error::ItemMissing),
+            },
+            CellField::TypeHash => match type_script {
+                Some(script) => script.calc_script_hash().as_slice().to_vec(),
+                None => return IoResult::Error(Error::ItemMissing),
+            },
+            CellField::OccupiedCapacity => occupied_capacity(output, data).to_le_bytes().to_vec(),
+        };
+        serve(&value, buf, offset)
+    }
+
+    fn load_cell_data(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let data = self.cell(index, source).map(|(_, data)| data);
+        serve_result(data, buf, offset)
+    }
+
+    fn load_header(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let header = self.header(index, source).map(|header| header.as_slice());
+        serve_result(header, buf, offset)
+    }
+
+    fn load_header_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: HeaderField,
+    ) -> IoResult {
+        let raw = match self.header(index, source) {
+            Ok(header) => header.raw(),
+            Err(err) => return IoResult::Error(err),
+        };
+        let epoch = EpochNumberWithFraction::from_full_value(raw.epoch().unpack());
+        let value = match field {
+            HeaderField::EpochNumber => epoch.number(),
+            // a made-up header whose epoch index exceeds its number has no
+            // epoch start
+            HeaderField::EpochStartBlockNumber => {
+                match Unpack::<u64>::unpack(&raw.number()).checked_sub(epoch.index()) {
+                    Some(number) => number,
+                    None => return IoResult::Error(Error::ItemMissing),
+                }
+            }
+            HeaderField::EpochLength => epoch.length(),
+        };
+        serve(&value.to_le_bytes(), buf, offset)
+    }
+
+    fn load_input(&self, buf: &mut [u8], offset: usize, index: usize, source: Source) -> IoResult {
+        let input = self.input(index, source).map(|input| input.as_slice());
+        serve_result(input, buf, offset)
+    }
+
+    fn load_input_by_field(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+        field: InputField,
+    ) -> IoResult {
+        let input = match self.input(index, source) {
+            Ok(input) => input,
+            Err(err) => return IoResult::Error(err),
+        };
+        match field {
+            InputField::OutPoint => serve(input.previous_output().as_slice(), buf, offset),
+            InputField::Since => serve(input.since().as_slice(), buf, offset),
+        }
+    }
+
+    fn load_script(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        serve(self.script.as_slice(), buf, offset)
+    }
+
+    fn load_script_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        serve(self.script.calc_script_hash().as_slice(), buf, offset)
+    }
+
+    fn load_transaction(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        serve(self.transaction.as_slice(), buf, offset)
+    }
+
+    fn load_tx_hash(&self, buf: &mut [u8], offset: usize) -> IoResult {
+        serve(&self.tx_hash, buf, offset)
+    }
+
+    fn load_witness(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        index: usize,
+        source: Source,
+    ) -> IoResult {
+        let witness = match self.resolve(index, source) {
+            Ok((Source::Input | Source::Output, index)) => self
+                .tx
+                .witnesses
+                .get(index)
+                .map(|witness| witness.as_slice())
+                .ok_or(Error::IndexOutOfBound),
+            Ok(_) => Err(Error::IndexOutOfBound),
+            Err(err) => Err(err),
+        };
+        serve_result(witness, buf, offset)
+    }
+
+    fn vm_version(&self) -> u64 {
+        2
+    }
+
+    fn current_cycles(&self) -> u64 {
+        0
+    }
+}
+
+pub(super) fn run(tx: &MockTransaction, group: ScriptGroup, contract: &Contract) -> RunResult {
+    let processes = tx
+        .contracts
+        .iter()
+        .fold(NativeProcesses::new(), |processes, contract| {
+            processes.program(contract.code_hash(), contract.main)
+        });
+    let syscalls = MockSyscalls::new(tx, group);
+    let debug = syscalls.debug_messages();
+    let status = processes.run_status(syscalls, contract.main);
+    let debug = core::mem::take(&mut *debug.lock().unwrap());
+    RunResult {
+        exit_code: status.code,
+        panicked: status.panicked,
+        debug,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ctx::Ctx, error::SysError};
+
+    fn header(number: u64, epoch: EpochNumberWithFraction) -> Header {
+        let raw = RawHeader::new_builder()
+            .number(number)
+            .epoch(epoch.full_value())
+            .build();
+        Header::new_builder().raw(raw).build()
+    }
+
+    fn lock_tx() -> MockTransaction {
+        let lock = Contract::new("lock", || 0);
+        let cell = CellOutput::new_builder()
+            .capacity(1000u64)
+            .lock(lock.script(&[1]))
+            .build();
+        MockTransaction::new().input(cell, [])
+    }
+
+    fn lock_cell(contract: &Contract, args: &[u8]) -> CellOutput {
+        CellOutput::new_builder()
+            .capacity(1000u64)
+            .lock(contract.script(args))
+            .build()
+    }
+
+    #[test]
+    fn captures_debug_and_panics() {
+        let lock = Contract::new("lock", || {
+            crate::syscalls::debug(String::from("checking"));
+            let witness = crate::high_level::load_witness(0, Source::GroupInput).unwrap();
+            crate::assert!(
+                42,
+                witness.len() > 3,
+                "witness too short: {}",
+                witness.len()
+            );
+            0
+        });
+        let result = MockTransaction::new()
+            .input(lock_cell(&lock, &[]), [])
+            .witness([1, 2])
+            .run(ScriptGroup::Lock(0), &lock);
+        assert_eq!(result.exit_code, 42);
+        assert!(result.panicked);
+        assert_eq!(result.debug.len(), 2);
+        assert_eq!(result.debug[0], "checking");
+        assert!(result.debug[1].starts_with("panicked at src/testing/stub.rs:"));
+        assert_eq!(result.panic_message(), Some("witness too short: 2"));
+    }
+
+    #[test]
+    fn only_root_panics_count() {
+        // prints what looks like a panic message
+        let printer = Contract::new("printer", || {
+            crate::syscalls::debug(String::from("panicked at a.rs:1:1:\nnot really"));
+            1
+        });
+        let result = MockTransaction::new()
+            .input(lock_cell(&printer, &[]), [])
+            .run(ScriptGroup::Lock(0), &printer);
+        assert_eq!((result.exit_code, result.panicked), (1, false));
+        assert_eq!(result.panic_message(), None);
+
+        // handles the panic of the child it spawns
+        let child = Contract::new("child", || panic!("child failed"));
+        let parent = Contract::new("parent", || {
+            let pid = crate::high_level::spawn_cell(
+                &super::super::data_hash(b"child"),
+                ckb_types::core::ScriptHashType::Data2,
+                &[],
+                &[],
+            )
+            .unwrap();
+            match crate::syscalls::wait(pid) {
+                Ok(-1) => 0,
+                _ => 1,
+            }
+        });
+        let result = MockTransaction::new()
+            .contract(&child)
+            .input(lock_cell(&parent, &[]), [])
+            .run(ScriptGroup::Lock(0), &parent);
+        assert_eq!((result.exit_code, result.panicked), (0, false));
+        assert_eq!(result.debug.len(), 1);
+        assert!(result.debug[0].ends_with("child failed"));
+        assert_eq!(result.panic_message(), None);
+    }
+
+    #[test]
+    fn loads_header_epochs() {
+        let tx = lock_tx()
+            .header_dep(header(1005, EpochNumberWithFraction::new(3, 5, 100)))
+            .header_dep(header(2, EpochNumberWithFraction::new(3, 5, 100)));
+        let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::Lock(0)));
+        assert_eq!(ctx.load_header_epoch_number(0, Source::HeaderDep), Ok(3));
+        assert_eq!(ctx.load_header_epoch_length(0, Source::HeaderDep), Ok(100));
+        assert_eq!(
+            ctx.load_header_epoch_start_block_number(0, Source::HeaderDep),
+            Ok(1000)
+        );
+        assert_eq!(
+            ctx.load_header_epoch_start_block_number(1, Source::HeaderDep),
+            Err(SysError::ItemMissing)
+        );
+        assert_eq!(
+            ctx.load_header_epoch_number(0, Source::Input),
+            Err(SysError::ItemMissing)
+        );
+        assert_eq!(
+            ctx.load_header_epoch_number(2, Source::HeaderDep),
+            Err(SysError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn resolves_script_groups() {
+        let lock = Contract::new("lock", || 0);
+        let other = Contract::new("other", || 0);
+        let type_script = other.script(&[7]);
+        let typed = |capacity: u64| {
+            lock_cell(&other, &[])
+                .as_builder()
+                .capacity(capacity)
+                .type_(
+                    ScriptOpt::new_builder()
+                        .set(Some(type_script.clone()))
+                        .build(),
+                )
+                .build()
+        };
+        let tx = MockTransaction::new()
+            .input(
+                lock_cell(&lock, &[]).as_builder().capacity(100u64).build(),
+                [],
+            )
+            .input(typed(200), [])
+            .input(
+                lock_cell(&lock, &[]).as_builder().capacity(300u64).build(),
+                [],
+            )
+            .output(lock_cell(&lock, &[]), [])
+            .output(typed(400), [])
+            .witness([0])
+            .witness([1])
+            .witness([2]);
+
+        // inputs 0 and 2 are locked by `lock`, a lock group has no outputs
+        let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::Lock(2)));
+        assert_eq!(ctx.load_script(), Ok(lock.script(&[])));
+        assert_eq!(ctx.load_cell_capacity(0, Source::GroupInput), Ok(100));
+        assert_eq!(ctx.load_cell_capacity(1, Source::GroupInput), Ok(300));
+        assert_eq!(
+            ctx.load_cell_capacity(2, Source::GroupInput),
+            Err(SysError::IndexOutOfBound)
+        );
+        assert_eq!(
+            ctx.load_cell_capacity(0, Source::GroupOutput),
+            Err(SysError::IndexOutOfBound)
+        );
+        // group witnesses are at the index of the cells in the transaction
+        assert_eq!(ctx.load_witness(1, Source::GroupInput), Ok(alloc::vec![2]));
+
+        let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::OutputType(1)));
+        assert_eq!(ctx.load_script(), Ok(type_script));
+        assert_eq!(ctx.load_cell_capacity(0, Source::GroupInput), Ok(200));
+        assert_eq!(ctx.load_cell_capacity(0, Source::GroupOutput), Ok(400));
+        assert_eq!(ctx.load_witness(0, Source::GroupInput), Ok(alloc::vec![1]));
+        assert_eq!(ctx.load_witness(0, Source::GroupOutput), Ok(alloc::vec![1]));
+        assert_eq!(
+            ctx.load_witness(1, Source::GroupOutput),
+            Err(SysError::IndexOutOfBound)
+        );
+    }
+
+    #[test]
+    fn loads_occupied_capacity() {
+        let lock = Contract::new("lock", || 0);
+        let cell = lock_cell(&lock, &[1, 2, 3])
+            .as_builder()
+            .type_(ScriptOpt::new_builder().set(Some(lock.script(&[]))).build())
+            .build();
+        let tx = lock_tx().output(cell, [0; 10]);
+        let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::Lock(0)));
+        // capacity, data, lock with its args and type script
+        assert_eq!(
+            ctx.load_cell_occupied_capacity(0, Source::Output),
+            Ok((8 + 10 + 36 + 33) * 100_000_000)
+        );
+        assert_eq!(
+            ctx.load_cell_occupied_capacity(0, Source::Input),
+            Ok((8 + 34) * 100_000_000)
+        );
+    }
+
+    #[test]
+    fn loads_headers() {
+        let first = header(7, EpochNumberWithFraction::new(1, 0, 10));
+        let second = header(8, EpochNumberWithFraction::new(1, 1, 10));
+        let tx = lock_tx()
+            .header_dep(first.clone())
+            .header_dep(second.clone());
+        let ctx = Ctx::new(MockSyscalls::new(&tx, ScriptGroup::Lock(0)));
+        let load = |index| {
+            ctx.load_header(index, Source::HeaderDep)
+                .map(|h| h.as_bytes())
+        };
+        assert_eq!(load(0), Ok(first.as_bytes()));
+        assert_eq!(load(1), Ok(second.as_bytes()));
+        assert_eq!(load(2), Err(SysError::IndexOutOfBound));
+        assert_eq!(ctx.load_transaction().unwrap().raw().header_deps().len(), 2);
+    }
+
+    #[test]
+    fn spawns_contracts() {
+        let child = Contract::new("child", || {
+            let args = crate::high_level::load_script().unwrap().args().raw_data();
+            crate::syscalls::debug(alloc::format!("child of {:?}", args.as_ref()));
+            7
+        });
+        let parent = Contract::new("parent", || {
+            let pid = crate::high_level::spawn_cell(
+                &super::super::data_hash(b"child"),
+                ckb_types::core::ScriptHashType::Data2,
+                &[],
+                &[],
+            )
+            .unwrap();
+            crate::syscalls::wait(pid).unwrap()
+        });
+        let result = MockTransaction::new()
+            .contract(&child)
+            .input(lock_cell(&parent, &[5]), [])
+            .run(ScriptGroup::Lock(0), &parent);
+        assert_eq!((result.exit_code, result.panicked), (7, false));
+        // spawned processes run the script of their parent
+        assert_eq!(result.debug, ["child of [5]"]);
+
+        // contracts not added to the transaction can't be spawned
+        let result = MockTransaction::new()
+            .input(lock_cell(&parent, &[5]), [])
+            .run(ScriptGroup::Lock(0), &parent);
+        assert!(result.panicked);
+    }
+}